@@ -0,0 +1,229 @@
+use fuser::{FileAttr, FileType};
+
+/// Extended attribute holding the ACL checked on access.
+pub const ACCESS: &str = "system.posix_acl_access";
+/// Extended attribute holding the ACL a directory hands down.
+pub const DEFAULT: &str = "system.posix_acl_default";
+
+const VERSION: u32 = 2;
+
+const USER_OBJ: u16 = 0x01;
+const USER: u16 = 0x02;
+const GROUP_OBJ: u16 = 0x04;
+const GROUP: u16 = 0x08;
+const MASK: u16 = 0x10;
+const OTHER: u16 = 0x20;
+
+/// One entry of a POSIX ACL, granting `perm` as rwx bits.
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    tag: u16,
+    perm: u16,
+    id: u32,
+}
+
+/// Parses an ACL in the form the kernel passes to setxattr, or returns `None` if it is malformed.
+pub fn parse(value: &[u8]) -> Option<Vec<Entry>> {
+    let (header, entries) = value.split_first_chunk::<4>()?;
+    if u32::from_le_bytes(*header) != VERSION || entries.len() % 8 != 0 {
+        return None;
+    }
+    let entries: Vec<Entry> = entries
+        .chunks_exact(8)
+        .map(|entry| Entry {
+            tag: u16::from_le_bytes([entry[0], entry[1]]),
+            perm: u16::from_le_bytes([entry[2], entry[3]]),
+            id: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+        })
+        .collect();
+    let required = [USER_OBJ, GROUP_OBJ, OTHER];
+    let valid = entries
+        .iter()
+        .all(|entry| [USER_OBJ, USER, GROUP_OBJ, GROUP, MASK, OTHER].contains(&entry.tag))
+        && required
+            .iter()
+            .all(|tag| entries.iter().filter(|entry| entry.tag == *tag).count() == 1);
+    valid.then_some(entries)
+}
+
+/// Returns the permission bits an ACL stands for, as chmod would show them.
+pub fn mode(entries: &[Entry]) -> u16 {
+    let perm = |tag| {
+        entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .map_or(0, |entry| entry.perm & 0o7)
+    };
+    let group = match entries.iter().any(|entry| entry.tag == MASK) {
+        true => perm(MASK),
+        false => perm(GROUP_OBJ),
+    };
+    perm(USER_OBJ) << 6 | group << 3 | perm(OTHER)
+}
+
+/// Reports whether a caller in `groups` is granted every bit of `mask` on a file with `attr`,
+/// by its ACL if it has one and by its mode otherwise.
+///
+/// Root is granted reading and writing anything, and executing whatever someone may execute.
+pub fn check(
+    entries: Option<&[Entry]>,
+    attr: &FileAttr,
+    uid: u32,
+    groups: &[u32],
+    mask: u32,
+) -> bool {
+    if uid == 0 {
+        return mask & 0o1 == 0 || attr.kind == FileType::Directory || attr.perm & 0o111 != 0;
+    }
+    match entries {
+        Some(entries) => permits(entries, attr, uid, groups, mask),
+        None => {
+            let perm = match () {
+                _ if uid == attr.uid => attr.perm >> 6,
+                _ if groups.contains(&attr.gid) => attr.perm >> 3,
+                _ => attr.perm,
+            };
+            let mask = (mask & 0o7) as u16;
+            perm & mask == mask
+        }
+    }
+}
+
+/// Reports whether the ACL of a file with `attr` grants `uid` in `groups` every bit of `mask`.
+pub fn permits(entries: &[Entry], attr: &FileAttr, uid: u32, groups: &[u32], mask: u32) -> bool {
+    let mask = (mask & 0o7) as u16;
+    let granted = |perm: u16| perm & mask == mask;
+    let limit = entries
+        .iter()
+        .find(|entry| entry.tag == MASK)
+        .map_or(0o7, |entry| entry.perm);
+
+    if uid == attr.uid {
+        return entries
+            .iter()
+            .any(|entry| entry.tag == USER_OBJ && granted(entry.perm));
+    }
+    if let Some(entry) = entries
+        .iter()
+        .find(|entry| entry.tag == USER && entry.id == uid)
+    {
+        return granted(entry.perm & limit);
+    }
+
+    let groups: Vec<&Entry> = entries
+        .iter()
+        .filter(|entry| match entry.tag {
+            GROUP_OBJ => groups.contains(&attr.gid),
+            GROUP => groups.contains(&entry.id),
+            _ => false,
+        })
+        .collect();
+    if !groups.is_empty() {
+        return groups.iter().any(|entry| granted(entry.perm & limit));
+    }
+    entries
+        .iter()
+        .any(|entry| entry.tag == OTHER && granted(entry.perm))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::SystemTime;
+
+    fn acl(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut value = VERSION.to_le_bytes().to_vec();
+        for (tag, perm, id) in entries {
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&perm.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    fn attr(perm: u16) -> FileAttr {
+        FileAttr {
+            ino: 2,
+            size: 0,
+            blocks: 0,
+            atime: SystemTime::UNIX_EPOCH,
+            mtime: SystemTime::UNIX_EPOCH,
+            ctime: SystemTime::UNIX_EPOCH,
+            crtime: SystemTime::UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn parses_acls() {
+        let minimal = acl(&[(USER_OBJ, 6, 0), (GROUP_OBJ, 4, 0), (OTHER, 0, 0)]);
+        assert_eq!(parse(&minimal).map(|entries| entries.len()), Some(3));
+        assert!(parse(&minimal[..minimal.len() - 1]).is_none());
+        assert!(parse(&acl(&[(USER_OBJ, 6, 0), (OTHER, 0, 0)])).is_none());
+        assert!(parse(&acl(&[
+            (USER_OBJ, 6, 0),
+            (GROUP_OBJ, 4, 0),
+            (OTHER, 0, 0),
+            (0x40, 7, 0)
+        ]))
+        .is_none());
+        let mut version = minimal.clone();
+        version[0] = 1;
+        assert!(parse(&version).is_none());
+    }
+
+    #[test]
+    fn mode_shows_the_mask() {
+        let entries = parse(&acl(&[
+            (USER_OBJ, 7, 0),
+            (GROUP_OBJ, 7, 0),
+            (MASK, 5, 0),
+            (OTHER, 4, 0),
+        ]))
+        .unwrap();
+        assert_eq!(mode(&entries), 0o754);
+    }
+
+    #[test]
+    fn checks_entries() {
+        let entries = parse(&acl(&[
+            (USER_OBJ, 6, 0),
+            (USER, 6, 2000),
+            (GROUP_OBJ, 4, 0),
+            (GROUP, 6, 300),
+            (MASK, 4, 0),
+            (OTHER, 0, 0),
+        ]))
+        .unwrap();
+        let attr = attr(0o640);
+        let check = |uid, groups: &[u32], mask| check(Some(&entries), &attr, uid, groups, mask);
+        assert!(check(1000, &[], 6));
+        // Named entries are limited by the mask.
+        assert!(check(2000, &[], 4));
+        assert!(!check(2000, &[], 2));
+        assert!(check(3000, &[300], 4));
+        assert!(!check(3000, &[300], 2));
+        assert!(check(3000, &[100], 4));
+        assert!(!check(3000, &[], 4));
+    }
+
+    #[test]
+    fn checks_modes() {
+        let attr = attr(0o640);
+        assert!(check(None, &attr, 1000, &[], 6));
+        assert!(check(None, &attr, 2000, &[100], 4));
+        assert!(!check(None, &attr, 2000, &[100], 2));
+        assert!(!check(None, &attr, 2000, &[], 4));
+        // Root writes anything but only executes what someone may execute.
+        assert!(check(None, &attr, 0, &[], 6));
+        assert!(!check(None, &attr, 0, &[], 1));
+    }
+}
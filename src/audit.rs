@@ -0,0 +1,57 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use fuser::Request;
+
+use crate::logger;
+
+/// Who asked for an operation.
+#[derive(Clone, Copy, Debug)]
+pub struct Caller {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+}
+
+impl Caller {
+    pub fn of(req: &Request) -> Caller {
+        Caller {
+            uid: req.uid(),
+            gid: req.gid(),
+            pid: req.pid(),
+        }
+    }
+}
+
+/// Append-only record of who opened, created, removed and wrote to which path.
+pub struct Audit {
+    file: Mutex<File>,
+}
+
+impl Audit {
+    pub fn open(path: &Path) -> io::Result<Audit> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Audit {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends a line naming the time, `caller`, `op` and `path`, followed by `detail`.
+    pub fn record(&self, caller: Caller, op: &str, path: &Path, detail: &str) {
+        let line = format!(
+            "{} uid={} gid={} pid={} op={} path={:?}{}\n",
+            logger::now(),
+            caller.uid,
+            caller.gid,
+            caller.pid,
+            op,
+            path,
+            detail
+        );
+        if let Err(err) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            log::error!("failed to write the audit log: {}", err);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Span of the file that random writes land in.
+const RANDOM_SPAN: u64 = 1 << 30;
+
+/// Where the writes of a workload go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Each thread writes one block after the other.
+    Sequential,
+    /// Each thread writes blocks at random aligned offsets.
+    Random,
+}
+
+/// Writes issued against a file to measure how fast it takes them.
+#[derive(Clone, Copy, Debug)]
+pub struct Workload {
+    pub threads: usize,
+    pub block_size: usize,
+    pub pattern: Pattern,
+    pub duration: Duration,
+}
+
+/// Throughput and latencies measured for a workload.
+#[derive(Debug)]
+pub struct Report {
+    pub writes: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Latency of every write, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    /// Returns the latency that `percent` of the writes did not exceed.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "{} writes, {} bytes in {:.1?}",
+            self.writes, self.bytes, self.elapsed
+        )?;
+        writeln!(
+            f,
+            "throughput {:.1} MB/s, {:.0} IOPS",
+            self.bytes as f64 / secs / 1e6,
+            self.writes as f64 / secs
+        )?;
+        write!(f, "latency")?;
+        for percent in [50.0, 90.0, 99.0, 99.9] {
+            write!(f, " p{} {:.1?}", percent, self.percentile(percent))?;
+        }
+        writeln!(f, " max {:.1?}", self.percentile(100.0))
+    }
+}
+
+/// Runs `workload` against the file at `path`, each thread with a handle of its own.
+pub fn run(path: &Path, workload: Workload) -> io::Result<Report> {
+    let start = Instant::now();
+    let threads: Vec<_> = (0..workload.threads)
+        .map(|index| {
+            let file = OpenOptions::new().write(true).open(path)?;
+            Ok(thread::spawn(move || {
+                let mut latencies = Vec::new();
+                let mut random = seed(index);
+                let block = vec![0; workload.block_size];
+                let blocks = (RANDOM_SPAN / workload.block_size as u64).max(1);
+                let mut offset = 0;
+                while start.elapsed() < workload.duration {
+                    if workload.pattern == Pattern::Random {
+                        offset = xorshift(&mut random) % blocks * workload.block_size as u64;
+                    }
+                    let write = Instant::now();
+                    file.write_all_at(&block, offset)?;
+                    latencies.push(write.elapsed());
+                    offset += workload.block_size as u64;
+                }
+                Ok::<_, io::Error>(latencies)
+            }))
+        })
+        .collect::<io::Result<_>>()?;
+
+    let mut latencies = Vec::new();
+    for thread in threads {
+        latencies.extend(thread.join().unwrap()?);
+    }
+    let elapsed = start.elapsed();
+    latencies.sort_unstable();
+    Ok(Report {
+        writes: latencies.len() as u64,
+        bytes: latencies.len() as u64 * workload.block_size as u64,
+        elapsed,
+        latencies,
+    })
+}
+
+fn seed(index: usize) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_nanos() as u64 ^ (index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
@@ -0,0 +1,47 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use fuser::MountOption;
+
+use nullfs::{options, NullFS};
+
+const USAGE: &str = "usage: nullfs-minimal <MOUNT> [-o <OPTION>]...";
+
+fn main() {
+    let mut mount: Option<PathBuf> = None;
+    let mut options: Vec<MountOption> = Vec::new();
+
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-h") | Some("--help") => {
+                println!("{}", USAGE);
+                return;
+            }
+            Some("-o") | Some("--option") => {
+                let value = args.next().unwrap_or_else(|| usage());
+                let value = value.to_str().unwrap_or_else(|| usage());
+                options.extend(options::parse(value).unwrap_or_else(|err| fail(err)));
+            }
+            _ if mount.is_none() => mount = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+
+    let mount = mount.unwrap_or_else(|| usage());
+    let options = options::identify(options::with_defaults(options), "nullfs");
+    options::check_conflicts(&options).unwrap_or_else(|err| fail(err));
+
+    fuser::mount2(NullFS::default(), mount, &options).unwrap();
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("nullfs-minimal: {}", err);
+    process::exit(2);
+}
@@ -0,0 +1,62 @@
+use std::ffi::CString;
+
+/// Which users and groups may use the mount, whatever the permissions of its files.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Callers {
+    /// Users refused with EACCES.
+    pub denied_uids: Vec<u32>,
+    /// Groups refused with EACCES.
+    pub denied_gids: Vec<u32>,
+    /// Users let in; when these or `allowed_gids` are given, everyone else is refused.
+    pub allowed_uids: Vec<u32>,
+    /// Groups let in, by the primary group of the caller.
+    pub allowed_gids: Vec<u32>,
+}
+
+impl Callers {
+    /// Whether the caller with `uid` and `gid` may use the mount, root included.
+    pub fn permits(&self, uid: u32, gid: u32) -> bool {
+        if self.denied_uids.contains(&uid) || self.denied_gids.contains(&gid) {
+            return false;
+        }
+        if self.allowed_uids.is_empty() && self.allowed_gids.is_empty() {
+            return true;
+        }
+        self.allowed_uids.contains(&uid) || self.allowed_gids.contains(&gid)
+    }
+}
+
+/// Parses a user by id or by name.
+pub fn parse_uid(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let name = CString::new(user).map_err(|_| format!("invalid user '{}'", user))?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    match passwd.is_null() {
+        true => Err(format!("unknown user '{}'", user)),
+        false => Ok(unsafe { (*passwd).pw_uid }),
+    }
+}
+
+/// The primary group of the user `uid`, if the user is known.
+pub fn primary_gid(uid: u32) -> Option<u32> {
+    let passwd = unsafe { libc::getpwuid(uid) };
+    match passwd.is_null() {
+        true => None,
+        false => Some(unsafe { (*passwd).pw_gid }),
+    }
+}
+
+/// Parses a group by id or by name.
+pub fn parse_gid(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name = CString::new(group).map_err(|_| format!("invalid group '{}'", group))?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    match entry.is_null() {
+        true => Err(format!("unknown group '{}'", group)),
+        false => Ok(unsafe { (*entry).gr_gid }),
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io;
+
+pub const CAP_SETGID: u32 = 6;
+pub const CAP_SETUID: u32 = 7;
+pub const CAP_SYS_ADMIN: u32 = 21;
+
+/// Names of the capabilities, by number.
+const NAMES: [&str; 41] = [
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+const VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct Header {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Data {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Drops every capability but `keep` from the calling thread and the threads it spawns from
+/// now on, bounding set included, and returns the names of those it is left with.
+pub fn restrict(keep: &[u32]) -> io::Result<Vec<&'static str>> {
+    let keep = keep.iter().fold(0u64, |mask, cap| mask | 1 << cap);
+    let last = fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|last| last.trim().parse().ok())
+        .unwrap_or(NAMES.len() as u32 - 1);
+
+    for cap in (0..=last).filter(|cap| keep & 1 << cap == 0) {
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } == -1 {
+            let err = io::Error::last_os_error();
+            // Without CAP_SETPCAP there is nothing privileged to drop it from.
+            if err.raw_os_error() == Some(libc::EPERM) {
+                break;
+            }
+            return Err(err);
+        }
+    }
+    if unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut header = Header {
+        version: VERSION_3,
+        pid: 0,
+    };
+    let mut data = [Data::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    for (i, data) in data.iter_mut().enumerate() {
+        let keep = (keep >> (32 * i)) as u32;
+        data.effective &= keep;
+        data.permitted &= keep;
+        data.inheritable = 0;
+    }
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let effective = data[0].effective as u64 | (data[1].effective as u64) << 32;
+    Ok((0..64)
+        .filter(|cap| effective & 1 << cap != 0)
+        .map(|cap| NAMES.get(cap).copied().unwrap_or("unknown"))
+        .collect())
+}
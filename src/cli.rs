@@ -0,0 +1,1979 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{self, Path, PathBuf};
+use std::process;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{command, value_parser, Arg, ArgMatches, Command, ErrorKind};
+use clap_complete::Shell;
+use fuser::consts::{FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE};
+use fuser::{MountOption, Session, SessionUnmounter};
+use libc::{SIGHUP, SIGINT, SIGTERM};
+
+use crate::bench;
+use crate::callers::{self, Callers};
+#[cfg(target_os = "linux")]
+use crate::capabilities::{self, CAP_SETGID, CAP_SETUID, CAP_SYS_ADMIN};
+use crate::control;
+use crate::crash::Crash;
+use crate::daemon::{self, Detached};
+use crate::delay::Delay;
+use crate::interrupt::{self, Call, Interrupts};
+#[cfg(target_os = "linux")]
+use crate::landlock::{self, Access};
+use crate::logger;
+use crate::names::NamePolicy;
+use crate::nfs;
+use crate::ninep;
+use crate::options;
+use crate::otel;
+use crate::pattern::Pattern;
+use crate::preload;
+use crate::privileges::Account;
+use crate::replay;
+use crate::rules::Rule;
+#[cfg(target_os = "linux")]
+use crate::seccomp;
+use crate::signal::SignalSet;
+use crate::stats::{Sample, Throughput};
+use crate::systemd;
+use crate::unit;
+use crate::unmount;
+use crate::{
+    Attrs, Config, DataSink, DataSource, NullFS, Registry, Shutdown, SizeAccounting, Stats, Tree,
+};
+
+/// How long a busy mountpoint may hold up the shutdown before it is detached.
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// How long spans still queued may hold up the exit.
+const OTEL_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `--stats-file` is saved while mounted.
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the throughput is sampled for the peak shown on exit.
+const PEAK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long `umount` waits for an instance beyond its grace period.
+const UNMOUNT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn command() -> Command<'static> {
+    let command = command!()
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        // Lets flags given on the command line override those of the configuration file.
+        .args_override_self(true)
+        .arg(
+            Arg::new("CONFIG")
+                .help("read flags from a TOML file, each key naming a long flag; flags given on the command line take precedence")
+                .long("config")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("MOUNT")
+                .help("paths to the mounting points")
+                .index(1)
+                .multiple_values(true)
+                .required_unless_present("MOUNTS"),
+        )
+        .arg(
+            Arg::new("MOUNTS")
+                .help("another path to mount, in addition to MOUNT")
+                .long("mount")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("OPTION")
+                .help("mount options, added to default_permissions,noatime")
+                .short('o')
+                .long("option")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("FSNAME")
+                .help("name of the mounted filesystem, unless set with -o fsname=")
+                .long("fsname")
+                .takes_value(true)
+                .default_value("nullfs"),
+        )
+        .arg(
+            Arg::new("NO_DEFAULT_OPTIONS")
+                .help("do not add the default mount options")
+                .long("no-default-options"),
+        )
+        .arg(
+            Arg::new("THREADS")
+                .help("worker threads serving reads and writes, 0 to serve them inline [default: one per CPU]")
+                .long("threads")
+                .takes_value(true)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("MAX_WRITE")
+                .help("largest write request to negotiate with the kernel, such as 1MiB")
+                .long("max-write")
+                .takes_value(true)
+                .value_parser(|size: &str| {
+                    options::parse_size(size).and_then(|size| {
+                        u32::try_from(size).map_err(|_| {
+                            io::Error::new(io::ErrorKind::InvalidInput, "size too large")
+                        })
+                    })
+                }),
+        )
+        .arg(
+            Arg::new("MAX_BACKGROUND")
+                .help("background requests the kernel keeps in flight [default: four per CPU, at least 12]")
+                .long("max-background")
+                .takes_value(true)
+                .value_parser(value_parser!(u16).range(1..)),
+        )
+        .arg(
+            Arg::new("CONGESTION_THRESHOLD")
+                .help("background requests at which the kernel throttles writers [default: 3/4 of --max-background]")
+                .long("congestion-threshold")
+                .takes_value(true)
+                .value_parser(value_parser!(u16).range(1..)),
+        )
+        .arg(
+            Arg::new("WRITEBACK_CACHE")
+                .help("let the kernel coalesce buffered writes before passing them on")
+                .long("writeback-cache"),
+        )
+        .arg(
+            Arg::new("OPEN_FLAGS")
+                .help("bypass the page cache on open, or keep what it cached")
+                .long("open-flags")
+                .takes_value(true)
+                .possible_values(["direct", "cached"]),
+        )
+        .arg(
+            Arg::new("NO_OPEN")
+                .help("let the kernel skip opening files, which then have no handles")
+                .long("no-open"),
+        )
+        .arg(
+            Arg::new("ENTRY_TTL")
+                .help("how long the kernel may cache names, such as 0, 10s or infinite")
+                .long("entry-ttl")
+                .takes_value(true)
+                .default_value("1s")
+                .value_parser(options::parse_duration),
+        )
+        .arg(
+            Arg::new("ATTR_TTL")
+                .help("how long the kernel may cache attributes")
+                .long("attr-ttl")
+                .takes_value(true)
+                .default_value("1s")
+                .value_parser(options::parse_duration),
+        )
+        .arg(
+            Arg::new("AUDIT")
+                .help("append who opened, created, removed and wrote to which path to a file")
+                .long("audit")
+                .takes_value(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("RECORD")
+                .help("record the requests the mount receives to a trace file for replay")
+                .long("record")
+                .takes_value(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("RECORD_PAYLOAD")
+                .help("keep what writes carried in the trace")
+                .long("record-payload")
+                .requires("RECORD"),
+        )
+        .arg(
+            Arg::new("READ_ONLY")
+                .help("mount read-only and refuse every modification")
+                .long("read-only"),
+        )
+        .arg(
+            Arg::new("VERBOSE")
+                .help("log more, up to every request with -vv and every read and write with -vvv")
+                .short('v')
+                .long("verbose")
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("LOG_FORMAT")
+                .help("write log records as lines of text or as JSON objects")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new("LOG_TARGET")
+                .help("where log records go, journald sending them with their fields")
+                .long("log-target")
+                .takes_value(true)
+                .possible_values(["stderr", "journald"])
+                .default_value("stderr"),
+        )
+        .arg(
+            Arg::new("OTEL_ENDPOINT")
+                .help("export a span for every read, write, flush and fsync to an OTLP/HTTP collector, such as http://localhost:4318")
+                .long("otel-endpoint")
+                .takes_value(true)
+                .value_parser(otel::Endpoint::parse),
+        )
+        .arg(
+            Arg::new("LOG_FILE")
+                .help("append log records to a file instead of stderr, such as /dev/stdout")
+                .long("log-file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("STATS_FILE")
+                .help("keep the traffic counters in a file across mounts, saved periodically and on exit")
+                .long("stats-file")
+                .takes_value(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("METRICS_OUT")
+                .help("write every counter to a file on exit, as CSV if it ends in .csv and JSON otherwise")
+                .long("metrics-out")
+                .takes_value(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("REPORT_INTERVAL")
+                .help("log the read and write throughput of every interval, such as 10s")
+                .long("report-interval")
+                .takes_value(true)
+                .value_parser(|interval: &str| match options::parse_duration(interval)? {
+                    Duration::ZERO => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the interval must not be zero",
+                    )),
+                    interval => Ok(interval),
+                }),
+        )
+        .arg(
+            Arg::new("FOREGROUND")
+                .help("stay in the foreground instead of daemonizing, implied under systemd")
+                .short('f')
+                .long("foreground"),
+        )
+        .arg(
+            Arg::new("RUN_AS")
+                .help("switch to USER[:GROUP] once mounted, the mount belonging to that user")
+                .long("run-as")
+                .takes_value(true)
+                .value_name("USER[:GROUP]")
+                .value_parser(Account::parse),
+        )
+        .arg(
+            Arg::new("LANDLOCK")
+                .help("deny the process filesystem access beyond its log, stats and control paths once mounted, where the kernel supports it; unmounting is left to fusermount")
+                .long("landlock")
+                .requires("AUTO_UNMOUNT"),
+        )
+        .arg(
+            Arg::new("DROP_CAPABILITIES")
+                .help("drop every capability not needed to unmount or switch to --run-as once mounted")
+                .long("drop-capabilities"),
+        )
+        .arg(
+            Arg::new("SECCOMP")
+                .help("confine the process to the syscalls it needs once mounted; unprivileged, it then needs --auto-unmount to unmount")
+                .long("seccomp"),
+        )
+        .arg(
+            Arg::new("AUTO_UNMOUNT")
+                .help("unmount automatically when the process exits")
+                .long("auto-unmount"),
+        );
+
+    namespace_args(command)
+        .subcommand(
+            Command::new("install-unit")
+                .about("Prints or installs a systemd unit mounting nullfs")
+                .arg(
+                    Arg::new("MOUNT_UNIT")
+                        .help("generate a .mount unit using mount.nullfs instead of a .service")
+                        .long("mount-unit"),
+                )
+                .arg(
+                    Arg::new("INSTALL")
+                        .help("write the unit into the unit directory instead of printing it")
+                        .long("install"),
+                )
+                .arg(
+                    Arg::new("UNIT_DIR")
+                        .help("where --install puts the unit")
+                        .long("unit-dir")
+                        .takes_value(true)
+                        .default_value("/etc/systemd/system"),
+                )
+                .arg(
+                    Arg::new("MOUNT")
+                        .help("path to the mounting point")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("ARGS")
+                        .help("arguments for nullfs")
+                        .multiple_values(true)
+                        .allow_hyphen_values(true)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            Command::new("umount")
+                .about("Unmounts a running instance")
+                .arg(
+                    Arg::new("LAZY")
+                        .help("detach right away, even while the mount is busy")
+                        .short('l')
+                        .long("lazy"),
+                )
+                .arg(
+                    Arg::new("FORCE")
+                        .help("kill the instance and detach if it does not unmount in time")
+                        .short('f')
+                        .long("force"),
+                )
+                .arg(
+                    Arg::new("MOUNT")
+                        .help("path to the mounting point")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Mounts nullfs in a temporary directory and measures how fast it takes writes")
+                .arg(
+                    Arg::new("JOBS")
+                        .help("threads writing at the same time")
+                        .long("threads")
+                        .takes_value(true)
+                        .default_value("1")
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("BLOCK_SIZE")
+                        .help("size of every write")
+                        .long("block-size")
+                        .takes_value(true)
+                        .default_value("128KiB")
+                        .value_parser(|size: &str| match options::parse_size(size)? {
+                            0 => Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "the block size must not be zero",
+                            )),
+                            size => usize::try_from(size).map_err(|_| {
+                                io::Error::new(io::ErrorKind::InvalidInput, "size too large")
+                            }),
+                        }),
+                )
+                .arg(
+                    Arg::new("PATTERN")
+                        .help("write one block after the other or at random offsets")
+                        .long("pattern")
+                        .takes_value(true)
+                        .possible_values(["sequential", "random"])
+                        .default_value("sequential"),
+                )
+                .arg(
+                    Arg::new("DURATION")
+                        .help("how long to keep writing")
+                        .long("duration")
+                        .takes_value(true)
+                        .default_value("5s")
+                        .value_parser(options::parse_duration),
+                )
+                .arg(
+                    Arg::new("SINK")
+                        .help("where written data goes, as NAME[:ARGUMENT]")
+                        .long("sink")
+                        .takes_value(true)
+                        .default_value("discard"),
+                ),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Prints a script completing nullfs in a shell")
+                .arg(
+                    Arg::new("SHELL")
+                        .help("shell to complete in")
+                        .required(true)
+                        .value_parser(value_parser!(Shell)),
+                ),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Replays a trace recorded with --record against a directory")
+                .arg(
+                    Arg::new("SPEED")
+                        .help("how many times faster than recorded to replay, 0 for no pauses at all")
+                        .long("speed")
+                        .takes_value(true)
+                        .default_value("1")
+                        .value_parser(|speed: &str| match speed.parse::<f64>() {
+                            Ok(speed) if speed >= 0.0 && speed.is_finite() => Ok(speed),
+                            _ => Err("not a positive number"),
+                        }),
+                )
+                .arg(
+                    Arg::new("TRACE")
+                        .help("trace file to replay")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("TARGET")
+                        .help("directory standing in for the root of the recorded mount")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Prints the counters of a running instance")
+                .arg(
+                    Arg::new("FORMAT")
+                        .help("print the counters as aligned columns, a JSON object, or as sent")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["table", "json", "raw"])
+                        .default_value("table"),
+                )
+                .arg(
+                    Arg::new("MOUNT")
+                        .help("path to the mounting point")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("dump-tree")
+                .about("Prints the files of a running instance as JSON")
+                .arg(
+                    Arg::new("MOUNT")
+                        .help("path to the mounting point")
+                        .required(true),
+                ),
+        )
+        .subcommand(server_args(
+            Command::new("serve-nfs")
+                .about("Serves the null namespace over NFSv3 instead of mounting it")
+                .arg(
+                    Arg::new("LISTEN")
+                        .help("address to take NFS and MOUNT calls on, both on the same port")
+                        .long("listen")
+                        .takes_value(true)
+                        .default_value("0.0.0.0:2049")
+                        .value_parser(value_parser!(SocketAddr)),
+                ),
+        ))
+        .subcommand(server_args(
+            Command::new("serve-9p")
+                .about("Serves the null namespace over 9P2000.L instead of mounting it")
+                .arg(
+                    Arg::new("LISTEN")
+                        .help("address to take connections on")
+                        .long("listen")
+                        .takes_value(true)
+                        .default_value("0.0.0.0:564")
+                        .value_parser(value_parser!(SocketAddr)),
+                )
+                .arg(
+                    Arg::new("SOCKET")
+                        .help("Unix socket to take connections on instead, such as the one behind a virtio-serial port")
+                        .long("socket")
+                        .takes_value(true)
+                        .conflicts_with("LISTEN")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        ))
+}
+
+/// Adds the flags shaping the null namespace, which mounts and servers share, to `command`.
+fn namespace_args(command: Command<'static>) -> Command<'static> {
+    command
+        .arg(
+            Arg::new("SINK")
+                .help("where written data goes, as NAME[:ARGUMENT]")
+                .long("sink")
+                .takes_value(true)
+                .default_value("discard"),
+        )
+        .arg(
+            Arg::new("SOURCE")
+                .help("where read data comes from, as NAME[:ARGUMENT]")
+                .long("source")
+                .takes_value(true)
+                .default_value("empty"),
+        )
+        .arg(
+            Arg::new("SIZE_ACCOUNTING")
+                .help("what the size of the null file reports")
+                .long("size-accounting")
+                .takes_value(true)
+                .possible_values(["none", "high-watermark", "cumulative"])
+                .default_value("none"),
+        )
+        .arg(
+            Arg::new("ADVERTISE_SIZE")
+                .help("size files report at least, such as 1TiB, reading as a hole past what was written")
+                .long("advertise-size")
+                .value_name("SIZE")
+                .takes_value(true)
+                .value_parser(options::parse_size),
+        )
+        .arg(
+            Arg::new("FSYNC_DELAY")
+                .help("take this long to answer every fsync, such as 200ms")
+                .long("fsync-delay")
+                .value_name("DURATION")
+                .takes_value(true)
+                .value_parser(options::parse_duration),
+        )
+        .arg(
+            Arg::new("FSYNC_JITTER")
+                .help("vary --fsync-delay by up to this much either way")
+                .long("fsync-jitter")
+                .value_name("DURATION")
+                .takes_value(true)
+                .requires("FSYNC_DELAY")
+                .value_parser(options::parse_duration),
+        )
+        .arg(
+            Arg::new("FSYNC_DELAY_EVERY")
+                .help("only delay every Nth fsync")
+                .long("fsync-delay-every")
+                .value_name("N")
+                .takes_value(true)
+                .requires("FSYNC_DELAY")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("DIE_AFTER_FSYNC")
+                .help("play dead once N fsyncs have been acknowledged, to test crash recovery")
+                .long("die-after-fsync")
+                .value_name("N")
+                .takes_value(true)
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("DIE_BY")
+                .help("how to play dead: answer nothing more or exit")
+                .long("die-by")
+                .takes_value(true)
+                .possible_values(["hang", "exit"])
+                .default_value("hang")
+                .requires("DIE_AFTER_FSYNC"),
+        )
+        .arg(
+            Arg::new("IGNORE_TRUNCATE")
+                .help("leave the size and kept contents of files as they are when they are truncated")
+                .long("ignore-truncate"),
+        )
+        .arg(
+            Arg::new("KEEP_UNDER")
+                .help("keep what is written to files in memory while they stay under SIZE, such as 64KiB; implies --size-accounting high-watermark")
+                .long("keep-under")
+                .value_name("SIZE")
+                .takes_value(true)
+                .value_parser(options::parse_size),
+        )
+        .arg(
+            Arg::new("READ_FROM")
+                .help("serve reads of files from a template FILE repeated up to their size instead of the source; implies --size-accounting high-watermark")
+                .long("read-from")
+                .value_name("FILE")
+                .takes_value(true)
+                .value_parser(|path: &str| Pattern::read(Path::new(path)).map(Arc::new)),
+        )
+        .arg(
+            Arg::new("FILL_PATTERN")
+                .help("serve reads of files from a hexadecimal PATTERN such as 0xDEADBEEF repeated up to their size; implies --size-accounting high-watermark")
+                .long("fill-pattern")
+                .value_name("PATTERN")
+                .takes_value(true)
+                .conflicts_with("READ_FROM")
+                .value_parser(|pattern: &str| Pattern::parse(pattern).map(Arc::new)),
+        )
+        .arg(
+            Arg::new("FILL_BYTE")
+                .help("serve reads of files from a BYTE such as 0xAA repeated up to their size; implies --size-accounting high-watermark")
+                .long("fill-byte")
+                .value_name("BYTE")
+                .takes_value(true)
+                .conflicts_with_all(&["READ_FROM", "FILL_PATTERN"])
+                .value_parser(|byte: &str| Pattern::parse_byte(byte).map(Arc::new)),
+        )
+        .arg(
+            Arg::new("ENDLESS_READ")
+                .help("answer every read of a file in full whatever its size, like /dev/zero")
+                .long("endless-read"),
+        )
+        .arg(
+            Arg::new("FILES")
+                .help("start out with these null files, as a comma-separated list of paths such as out,logs/out")
+                .long("files")
+                .value_name("PATHS")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("PRELOAD")
+                .help("start out with the files of a tree printed by dump-tree or of a tar archive, without their data")
+                .long("preload")
+                .value_name("FILE")
+                .takes_value(true)
+                .value_parser(|path: &str| preload::Manifest::read(Path::new(path)).map(Arc::new)),
+        )
+        .arg(
+            Arg::new("METADATA")
+                .help("keep the modes, owners, sizes and times set on files while discarding their data; implies --dynamic and --size-accounting high-watermark")
+                .long("metadata"),
+        )
+        .arg(
+            Arg::new("DYNAMIC")
+                .help("let files and directories be created anywhere, all of them null files")
+                .long("dynamic"),
+        )
+        .arg(
+            Arg::new("DIGEST")
+                .help("hash what is written to every file, reported as its user.nullfs.digest attribute")
+                .long("digest"),
+        )
+        .arg(
+            Arg::new("EINTR")
+                .help("fail this FRACTION of opens, reads and writes with EINTR, such as 0.1 or 10%")
+                .long("eintr")
+                .value_name("FRACTION")
+                .takes_value(true)
+                .value_parser(interrupt::parse_fraction),
+        )
+        .arg(
+            Arg::new("EINTR_ON")
+                .help("only interrupt these calls, as a comma-separated list of open, read and write")
+                .long("eintr-on")
+                .value_name("CALLS")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .requires("EINTR")
+                .value_parser(Call::parse),
+        )
+        .arg(
+            Arg::new("RULE")
+                .help("what happens to files by name, as PATTERN => discard, endless, pipe, ERRNO, quota SIZE or echo SIZE; the first matching rule applies")
+                .long("rule")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true)
+                .value_parser(Rule::parse),
+        )
+        .arg(
+            Arg::new("NAME_POLICY")
+                .help("refuse names of new files like strict filesystems, as max-length=N, utf8, no-control or strict")
+                .long("name-policy")
+                .takes_value(true)
+                .value_parser(NamePolicy::parse),
+        )
+        .arg(
+            Arg::new("CASE_INSENSITIVE")
+                .help("resolve names regardless of case, keeping the case they were created with")
+                .long("case-insensitive"),
+        )
+        .arg(
+            Arg::new("NAME")
+                .help("name of the null file in the root directory")
+                .long("name")
+                .takes_value(true)
+                .default_value("null")
+                .value_parser(parse_name),
+        )
+        .arg(
+            Arg::new("FILE_MODE")
+                .help("permission bits of the null file and of those of --files, in octal")
+                .long("file-mode")
+                .value_name("MODE")
+                .takes_value(true)
+                .default_value("0666")
+                .value_parser(options::parse_mode),
+        )
+        .arg(
+            Arg::new("FILE_UID")
+                .help("owner of the null file, by id or name")
+                .long("file-uid")
+                .value_name("USER")
+                .takes_value(true)
+                .default_value("0")
+                .value_parser(callers::parse_uid),
+        )
+        .arg(
+            Arg::new("FILE_GID")
+                .help("group of the null file, by id or name")
+                .long("file-gid")
+                .value_name("GROUP")
+                .takes_value(true)
+                .default_value("0")
+                .value_parser(callers::parse_gid),
+        )
+        .arg(
+            Arg::new("DIR_MODE")
+                .help("permission bits of the root directory and of those leading to the files of --files, in octal")
+                .long("dir-mode")
+                .value_name("MODE")
+                .takes_value(true)
+                .default_value("0777")
+                .value_parser(options::parse_mode),
+        )
+        .arg(
+            Arg::new("DIR_UID")
+                .help("owner of the root directory, by id or name")
+                .long("dir-uid")
+                .value_name("USER")
+                .takes_value(true)
+                .default_value("0")
+                .value_parser(callers::parse_uid),
+        )
+        .arg(
+            Arg::new("DIR_GID")
+                .help("group of the root directory, by id or name")
+                .long("dir-gid")
+                .value_name("GROUP")
+                .takes_value(true)
+                .default_value("0")
+                .value_parser(callers::parse_gid),
+        )
+        .arg(
+            Arg::new("BLKSIZE")
+                .help("preferred I/O size files report, such as 64KiB")
+                .long("blksize")
+                .value_name("SIZE")
+                .takes_value(true)
+                .default_value("4096")
+                .value_parser(|size: &str| match options::parse_size(size)? {
+                    size @ 1..=0xffff_ffff => Ok(size as u32),
+                    _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "blksize must be between 1 byte and 4GiB")),
+                }),
+        )
+        .arg(
+            Arg::new("DENY_UID")
+                .help("refuse a user, by id or name, with EACCES; may be given several times")
+                .long("deny-uid")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true)
+                .value_parser(callers::parse_uid),
+        )
+        .arg(
+            Arg::new("DENY_GID")
+                .help("refuse a group, by id or name, with EACCES; may be given several times")
+                .long("deny-gid")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true)
+                .value_parser(callers::parse_gid),
+        )
+        .arg(
+            Arg::new("ALLOW_UID")
+                .help("let in only the users and groups allowed, by id or name; may be given several times")
+                .long("allow-uid")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true)
+                .value_parser(callers::parse_uid),
+        )
+        .arg(
+            Arg::new("ALLOW_GID")
+                .help("let in only the users and groups allowed, by primary group id or name; may be given several times")
+                .long("allow-gid")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple_occurrences(true)
+                .value_parser(callers::parse_gid),
+        )
+}
+
+/// Adds the flags the server subcommands share to `command`.
+fn server_args(command: Command<'static>) -> Command<'static> {
+    namespace_args(command)
+        .arg(
+            Arg::new("READ_ONLY")
+                .help("refuse every modification")
+                .long("read-only"),
+        )
+        .arg(
+            Arg::new("VERBOSE")
+                .help("log more")
+                .short('v')
+                .long("verbose")
+                .multiple_occurrences(true),
+        )
+}
+
+/// Runs the `nullfs` command line with the sinks and sources of `registry` available.
+///
+/// Invoked as `mount.nullfs`, it follows the calling convention of mount(8) instead.
+pub fn run(registry: Registry) {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    if args
+        .first()
+        .and_then(|arg| Path::new(arg).file_name())
+        .is_some_and(|name| name == "mount.nullfs")
+    {
+        args = mount_helper_args(&args[1..]);
+    }
+
+    let mut command = command();
+    if let Some(file) = config_file(&args) {
+        let flags = fs::read_to_string(&file)
+            .and_then(|text| options::parse_config_file(&text))
+            .unwrap_or_else(|err| {
+                let message = format!("failed to read {}: {}", file.to_string_lossy(), err);
+                command.error(ErrorKind::Io, message).exit()
+            });
+        let flags = flags.into_iter().map(OsString::from);
+        args = args[..1]
+            .iter()
+            .cloned()
+            .chain(flags)
+            .chain(args[1..].iter().cloned())
+            .collect();
+    }
+    let matches = command
+        .try_get_matches_from_mut(args)
+        .unwrap_or_else(|err| err.exit());
+
+    match matches.subcommand() {
+        Some(("install-unit", matches)) => install_unit(&mut command, matches),
+        Some(("umount", matches)) => umount(&mut command, matches),
+        Some(("stats", matches)) => stats(&mut command, matches),
+        Some(("dump-tree", matches)) => dump_tree(&mut command, matches),
+        Some(("replay", matches)) => replay(matches),
+        Some(("completions", matches)) => {
+            let shell = *matches.get_one::<Shell>("SHELL").unwrap();
+            clap_complete::generate(shell, &mut command, "nullfs", &mut io::stdout())
+        }
+        Some(("bench", matches)) => bench(&mut command, matches, registry),
+        Some(("serve-nfs", matches)) => serve_nfs(&mut command, matches, registry),
+        Some(("serve-9p", matches)) => serve_9p(&mut command, matches, registry),
+        _ => mount(&mut command, &matches, registry),
+    }
+}
+
+fn mount(command: &mut Command, matches: &ArgMatches, registry: Registry) {
+    let format = match matches.value_of("LOG_FORMAT") {
+        Some("json") => logger::Format::Json,
+        _ => logger::Format::Text,
+    };
+    let output = match matches.value_of("LOG_FILE") {
+        _ if matches.value_of("LOG_TARGET") == Some("journald") => logger::Output::Journald,
+        Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => logger::Output::Stream(Box::new(file)),
+            Err(err) => command
+                .error(
+                    ErrorKind::Io,
+                    format!("failed to open log file {}: {}", path, err),
+                )
+                .exit(),
+        },
+        None => logger::Output::Stream(Box::new(io::stderr())),
+    };
+    // Reports are informational messages, which are shown once they are asked for.
+    let report_interval = matches.get_one::<Duration>("REPORT_INTERVAL").copied();
+    let verbosity = matches
+        .occurrences_of("VERBOSE")
+        .max(report_interval.is_some() as u64);
+    // Only fails if the embedding program installed a logger of its own.
+    let _ = logger::init(verbosity, format, output);
+    if let Some(endpoint) = matches.get_one::<otel::Endpoint>("OTEL_ENDPOINT") {
+        otel::init(endpoint.clone());
+    }
+
+    // The daemon leaves the working directory, so relative paths would go astray.
+    let paths: Vec<PathBuf> = matches
+        .values_of("MOUNT")
+        .unwrap_or_default()
+        .chain(matches.values_of("MOUNTS").unwrap_or_default())
+        .map(|path| {
+            fs::canonicalize(path)
+                .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit())
+        })
+        .collect();
+
+    let stats_file = matches.get_one::<PathBuf>("STATS_FILE").map(|file| {
+        if paths.len() > 1 {
+            let message = "--stats-file only keeps the counters of a single mount";
+            command.error(ErrorKind::ArgumentConflict, message).exit();
+        }
+        path::absolute(file).unwrap_or_else(|err| command.error(ErrorKind::Io, err).exit())
+    });
+
+    let metrics_out = matches.get_one::<PathBuf>("METRICS_OUT").map(|file| {
+        path::absolute(file).unwrap_or_else(|err| command.error(ErrorKind::Io, err).exit())
+    });
+
+    // Mounting for another user and confining the process rely on Linux.
+    #[cfg(not(target_os = "linux"))]
+    for (id, flag) in [
+        ("RUN_AS", "--run-as"),
+        ("LANDLOCK", "--landlock"),
+        ("DROP_CAPABILITIES", "--drop-capabilities"),
+        ("SECCOMP", "--seccomp"),
+    ] {
+        if matches.is_present(id) {
+            let message = format!("{} is only supported on Linux", flag);
+            command.error(ErrorKind::ArgumentConflict, message).exit();
+        }
+    }
+
+    // Only fusermount unmounts once the process is gone.
+    #[cfg(target_os = "freebsd")]
+    if matches.is_present("AUTO_UNMOUNT") {
+        let message = "--auto-unmount is not supported on FreeBSD";
+        command.error(ErrorKind::ArgumentConflict, message).exit();
+    }
+
+    let mut options: Vec<MountOption> = Vec::new();
+    for value in matches.values_of("OPTION").unwrap_or_default() {
+        match options::parse(value) {
+            Ok(parsed) => options.extend(parsed),
+            Err(err) => command.error(ErrorKind::InvalidValue, err).exit(),
+        }
+    }
+
+    if matches.is_present("AUTO_UNMOUNT") {
+        options.push(MountOption::AutoUnmount);
+    }
+
+    let mut config = Config {
+        threads: match matches.get_one::<usize>("THREADS") {
+            Some(threads) => *threads,
+            None => thread::available_parallelism().map_or(1, usize::from),
+        },
+        max_write: matches.get_one::<u32>("MAX_WRITE").copied(),
+        max_background: matches.get_one::<u16>("MAX_BACKGROUND").copied(),
+        congestion_threshold: matches.get_one::<u16>("CONGESTION_THRESHOLD").copied(),
+        writeback_cache: matches.is_present("WRITEBACK_CACHE"),
+        open_flags: match matches.value_of("OPEN_FLAGS") {
+            Some("direct") => FOPEN_DIRECT_IO,
+            Some("cached") => FOPEN_KEEP_CACHE,
+            _ => 0,
+        },
+        no_open: matches.is_present("NO_OPEN"),
+        entry_ttl: *matches.get_one::<Duration>("ENTRY_TTL").unwrap(),
+        attr_ttl: *matches.get_one::<Duration>("ATTR_TTL").unwrap(),
+        audit: matches.get_one::<PathBuf>("AUDIT").map(|file| {
+            // Fail now rather than when the kernel initializes the mount.
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .and_then(|_| path::absolute(file))
+                .unwrap_or_else(|err| {
+                    let message = format!("failed to open audit log {}: {}", file.display(), err);
+                    command.error(ErrorKind::Io, message).exit()
+                })
+        }),
+        record: matches.get_one::<PathBuf>("RECORD").map(|file| {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(file)
+                .and_then(|_| path::absolute(file))
+                .unwrap_or_else(|err| {
+                    let message = format!("failed to open trace {}: {}", file.display(), err);
+                    command.error(ErrorKind::Io, message).exit()
+                })
+        }),
+        record_payload: matches.is_present("RECORD_PAYLOAD"),
+        // Known once the mount options are.
+        check_permissions: false,
+        ..namespace_config(command, matches)
+    };
+
+    if config.read_only {
+        options.push(MountOption::RO);
+    }
+
+    if !matches.is_present("NO_DEFAULT_OPTIONS") {
+        options = options::with_defaults(options);
+    }
+    options = options::identify(options, matches.value_of("FSNAME").unwrap());
+    config.check_permissions = !options.contains(&MountOption::DefaultPermissions);
+    // Owning the mount lets the account unmount it through fusermount after the switch.
+    let run_as = matches.get_one::<Account>("RUN_AS").copied();
+    if let Some(account) = run_as {
+        options.push(MountOption::CUSTOM(format!(
+            "user_id={},group_id={}",
+            account.uid, account.gid
+        )));
+    }
+
+    if let Err(err) = options::check_conflicts(&options) {
+        command.error(ErrorKind::ArgumentConflict, err).exit();
+    }
+
+    let mut sessions = Vec::new();
+    let mut mounts = Vec::new();
+    let mut summaries = Vec::new();
+    for path in &paths {
+        let sink = registry
+            .sink(matches.value_of("SINK").unwrap())
+            .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+        let source = registry
+            .source(matches.value_of("SOURCE").unwrap())
+            .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+
+        let fs = NullFS::new(config.clone(), sink, source);
+        let shutdown = fs.shutdown();
+        let stats = fs.stats();
+        let tree = fs.tree();
+        if let Some(file) = &stats_file {
+            if let Err(err) = stats.load(file) {
+                let message = format!("failed to load {}: {}", file.display(), err);
+                command.error(ErrorKind::Io, message).exit();
+            }
+        }
+        let mut session = match Session::new(fs, path, &options) {
+            Ok(session) => session,
+            Err(err) => {
+                // Unmount whatever was mounted already.
+                drop(sessions);
+                eprintln!("nullfs: failed to mount {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        };
+        summaries.push(Arc::new(Summary::new(stats.clone())));
+        mounts.push(Arc::new(Mutex::new(Mounted {
+            path: path.clone(),
+            shutdown,
+            stats,
+            tree,
+            unmounter: session.unmount_callable(),
+        })));
+        sessions.push(session);
+    }
+
+    // A notify service has to stay the main process of its unit.
+    let mut detached = None;
+    if !matches.is_present("FOREGROUND") && !systemd::is_notify_service() {
+        match daemon::daemonize() {
+            Ok(parent) => detached = Some(parent),
+            Err(err) => abort_start(None, &mounts, &format!("failed to daemonize: {}", err)),
+        }
+    }
+
+    let signals = SignalSet::block(&[SIGHUP, SIGINT, SIGTERM]).unwrap();
+
+    // Landlock only confines the threads spawned after it, so it comes ahead of them all.
+    #[cfg(target_os = "linux")]
+    if matches.is_present("LANDLOCK") {
+        if let Err(err) = sandbox(&config, stats_file.as_deref(), metrics_out.as_deref()) {
+            let message = format!("failed to restrict filesystem access: {}", err);
+            abort_start(detached.take(), &mounts, &message);
+        }
+    }
+
+    // Capabilities are per thread too. Switching accounts drops the ones it needs itself,
+    // and fusermount unmounts for the account or with --auto-unmount.
+    #[cfg(target_os = "linux")]
+    if matches.is_present("DROP_CAPABILITIES") {
+        let keep = match run_as {
+            Some(_) => vec![CAP_SETGID, CAP_SETUID],
+            None if matches.is_present("AUTO_UNMOUNT") => vec![],
+            None => vec![CAP_SYS_ADMIN],
+        };
+        match capabilities::restrict(&keep) {
+            Ok(left) if left.is_empty() => log::info!("dropped every capability"),
+            Ok(left) => log::info!("dropped every capability but {}", left.join(",")),
+            Err(err) => {
+                let message = format!("failed to drop capabilities: {}", err);
+                abort_start(detached.take(), &mounts, &message);
+            }
+        }
+    }
+
+    {
+        let summaries = summaries.clone();
+        thread::spawn(move || loop {
+            thread::sleep(PEAK_INTERVAL);
+            for summary in &summaries {
+                summary.sample();
+            }
+        });
+    }
+
+    let saved_stats = stats_file.map(|file| (file, mounts[0].lock().unwrap().stats.clone()));
+    if let Some((file, stats)) = saved_stats.clone() {
+        thread::spawn(move || loop {
+            thread::sleep(STATS_SAVE_INTERVAL);
+            save_stats(&stats, &file);
+        });
+    }
+
+    if let Some(interval) = report_interval {
+        let mounts: Vec<(PathBuf, Arc<Stats>)> = mounts
+            .iter()
+            .map(|mounted| {
+                let mounted = mounted.lock().unwrap();
+                (mounted.path.clone(), mounted.stats.clone())
+            })
+            .collect();
+        thread::spawn(move || report(interval, &mounts));
+    }
+
+    let _controls: Vec<_> = mounts
+        .iter()
+        .filter_map(|mounted| {
+            let path = mounted.lock().unwrap().path.clone();
+            let mounted = mounted.clone();
+            control::Server::bind(&path, move |command| match command {
+                "pid" => Ok(process::id().to_string()),
+                "stats" => Ok(mounted.lock().unwrap().stats.to_string()),
+                "tree" => Ok(mounted.lock().unwrap().tree.to_json() + "\n"),
+                "unmount" => {
+                    let mounted = mounted.clone();
+                    thread::spawn(move || {
+                        mounted.lock().unwrap().unmount();
+                        thread::sleep(GRACE_PERIOD);
+                        mounted.lock().unwrap().detach();
+                    });
+                    Ok(String::new())
+                }
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown command '{}'", command),
+                )),
+            })
+            .map_err(|err| eprintln!("nullfs: no control socket for {}: {}", path.display(), err))
+            .ok()
+        })
+        .collect();
+
+    if let Some(account) = run_as {
+        if let Err(err) = account.switch() {
+            let message = format!(
+                "failed to switch to {}:{}: {}",
+                account.uid, account.gid, err
+            );
+            abort_start(detached.take(), &mounts, &message);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if matches.is_present("SECCOMP") {
+        if let Err(err) = seccomp::install() {
+            let message = format!("failed to install the syscall filter: {}", err);
+            abort_start(detached.take(), &mounts, &message);
+        }
+    }
+
+    if let Some(parent) = detached {
+        parent.ready();
+    }
+
+    // Terminating signals unmount the filesystems, which ends their session loops.
+    // Should a mountpoint still be busy after a grace period, or another signal
+    // arrive, it is detached lazily so the process can exit regardless.
+    thread::spawn(move || {
+        signals.wait().unwrap();
+        for mounted in &mounts {
+            mounted.lock().unwrap().unmount();
+        }
+
+        signals.wait_timeout(GRACE_PERIOD).unwrap();
+        for mounted in &mounts {
+            mounted.lock().unwrap().detach();
+        }
+    });
+
+    let threads: Vec<_> = sessions
+        .into_iter()
+        .map(|mut session| thread::spawn(move || session.run()))
+        .collect();
+
+    let mut failed = false;
+    for ((thread, path), summary) in threads.into_iter().zip(&paths).zip(&summaries) {
+        match thread.join().unwrap() {
+            Ok(()) => summary.print(path),
+            Err(err) => {
+                eprintln!("nullfs: session on {} failed: {}", path.display(), err);
+                failed = true;
+            }
+        }
+    }
+    otel::flush(OTEL_FLUSH_TIMEOUT);
+    if let Some((file, stats)) = &saved_stats {
+        save_stats(stats, file);
+    }
+    if let Some(file) = &metrics_out {
+        let stats: Vec<_> = summaries.iter().map(|summary| &*summary.stats).collect();
+        if let Err(err) = write_metrics(file, &paths, &stats) {
+            eprintln!("nullfs: failed to write {}: {}", file.display(), err);
+            failed = true;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Reports a failure to start, to the parent waiting on the daemon if there is one, then
+/// unmounts everything and exits.
+fn abort_start(detached: Option<Detached>, mounts: &[Arc<Mutex<Mounted>>], message: &str) -> ! {
+    match detached {
+        Some(parent) => parent.fail(message),
+        None => eprintln!("nullfs: {}", message),
+    }
+    for mounted in mounts {
+        mounted.lock().unwrap().unmount();
+    }
+    process::exit(1);
+}
+
+/// Restricts filesystem access to the control sockets, the audit log, the trace, the files
+/// holding the stats and metrics, what the caller groups are read from, and what resolving
+/// span collectors takes. The log is open already.
+#[cfg(target_os = "linux")]
+fn sandbox(
+    config: &Config,
+    stats_file: Option<&Path>,
+    metrics_out: Option<&Path>,
+) -> io::Result<()> {
+    let control = control::socket_dir()?;
+    let mut paths = vec![
+        (control.as_path(), Access::Write),
+        (Path::new("/proc"), Access::Read),
+    ];
+    // Opened as the filesystems start, after the restriction.
+    for file in config.audit.iter().chain(&config.record) {
+        paths.push((file.as_path(), Access::Write));
+    }
+    // Rewritten through a temporary file next to it.
+    for file in stats_file.into_iter().chain(metrics_out) {
+        paths.extend(file.parent().map(|dir| (dir, Access::Write)));
+    }
+    if otel::is_enabled() {
+        for file in [
+            "/etc/hosts",
+            "/etc/resolv.conf",
+            "/etc/nsswitch.conf",
+            "/etc/gai.conf",
+        ] {
+            paths.push((Path::new(file), Access::Read));
+        }
+    }
+    match landlock::restrict(&paths)? {
+        Some(abi) => log::info!("restricted filesystem access with Landlock ABI {}", abi),
+        None => {
+            log::warn!("Landlock is not supported by the kernel, filesystem access is unrestricted")
+        }
+    }
+    Ok(())
+}
+
+/// Returns the user or group ids given to the repeatable argument `id`.
+fn ids(matches: &ArgMatches, id: &str) -> Vec<u32> {
+    matches
+        .get_many::<u32>(id)
+        .unwrap_or_default()
+        .copied()
+        .collect()
+}
+
+fn save_stats(stats: &Stats, file: &Path) {
+    if let Err(err) = stats.save(file) {
+        log::error!("failed to save the stats to {}: {}", file.display(), err);
+    }
+}
+
+/// Writes the counters of every mount to `file`, as rows of `mount,name,value` if it
+/// ends in `.csv`, or as a JSON object of counters per mount otherwise.
+fn write_metrics(file: &Path, paths: &[PathBuf], stats: &[&Stats]) -> io::Result<()> {
+    let mut metrics = String::new();
+    if file.extension().is_some_and(|extension| extension == "csv") {
+        metrics.push_str("mount,name,value\n");
+        for (path, stats) in paths.iter().zip(stats) {
+            for (name, value) in stats.counters() {
+                let path = path.to_string_lossy().replace('"', "\"\"");
+                let _ = writeln!(metrics, "\"{}\",{},{}", path, name, value);
+            }
+        }
+    } else {
+        metrics.push('{');
+        for (i, (path, stats)) in paths.iter().zip(stats).enumerate() {
+            let separator = if i > 0 { "," } else { "" };
+            let path = logger::json_string(&path.to_string_lossy());
+            let _ = write!(metrics, "{}\n  {}: {{", separator, path);
+            for (j, (name, value)) in stats.counters().into_iter().enumerate() {
+                let separator = if j > 0 { "," } else { "" };
+                let _ = write!(metrics, "{}\n    \"{}\": {}", separator, name, value);
+            }
+            metrics.push_str("\n  }");
+        }
+        metrics.push_str("\n}\n");
+    }
+    fs::write(file, metrics)
+}
+
+/// Logs the throughput of every mount once per `interval`, for as long as the process runs.
+fn report(interval: Duration, mounts: &[(PathBuf, Arc<Stats>)]) {
+    let mut samples: Vec<_> = mounts.iter().map(|(_, stats)| stats.sample()).collect();
+    loop {
+        thread::sleep(interval);
+        for ((path, stats), sample) in mounts.iter().zip(&mut samples) {
+            let now = stats.sample();
+            log::info!("{}: {}", path.display(), now.since(sample));
+            *sample = now;
+        }
+    }
+}
+
+/// What a mount went through, summed up when it ends.
+struct Summary {
+    start: Instant,
+    stats: Arc<Stats>,
+    last: Mutex<Sample>,
+    peak: Mutex<Throughput>,
+}
+
+impl Summary {
+    fn new(stats: Arc<Stats>) -> Summary {
+        Summary {
+            start: Instant::now(),
+            last: Mutex::new(stats.sample()),
+            stats,
+            peak: Mutex::new(Throughput::default()),
+        }
+    }
+
+    /// Updates the peak throughput with the one since the previous sample.
+    fn sample(&self) {
+        let now = self.stats.sample();
+        let mut last = self.last.lock().unwrap();
+        let mut peak = self.peak.lock().unwrap();
+        *peak = peak.max(now.since(&last));
+        *last = now;
+    }
+
+    fn print(&self, path: &Path) {
+        // Whatever came after the last sample counts too, unless too little time
+        // passed since for a rate to mean anything.
+        if self.last.lock().unwrap().age() >= PEAK_INTERVAL / 2 {
+            self.sample();
+        }
+        let stats = &self.stats;
+        let peak = self.peak.lock().unwrap();
+        eprintln!(
+            "nullfs: {} was mounted for {:.1?}",
+            path.display(),
+            self.start.elapsed()
+        );
+        eprintln!(
+            "nullfs:   {} bytes written in {} writes, {} bytes read in {} reads",
+            stats.bytes_written.load(Ordering::Relaxed),
+            stats.writes.load(Ordering::Relaxed),
+            stats.bytes_read.load(Ordering::Relaxed),
+            stats.reads.load(Ordering::Relaxed)
+        );
+        eprintln!(
+            "nullfs:   peak write {:.1} MB/s {:.0} IOPS, peak read {:.1} MB/s {:.0} IOPS",
+            peak.write_bytes / 1e6,
+            peak.write_ops,
+            peak.read_bytes / 1e6,
+            peak.read_ops
+        );
+    }
+}
+
+/// A mounted session, as far as bringing it down is concerned.
+struct Mounted {
+    path: PathBuf,
+    shutdown: Shutdown,
+    stats: Arc<Stats>,
+    tree: Tree,
+    unmounter: SessionUnmounter,
+}
+
+impl Mounted {
+    fn unmount(&mut self) {
+        self.shutdown.begin();
+        self.unmounter.unmount().unwrap();
+    }
+
+    /// Detaches a mountpoint that is still busy.
+    fn detach(&self) {
+        match unmount::unmount(&self.path, true) {
+            Ok(()) => eprintln!("nullfs: detached {}", self.path.display()),
+            // Already gone, the session is merely still winding down.
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {}
+            Err(err) => eprintln!("nullfs: failed to detach {}: {}", self.path.display(), err),
+        }
+    }
+}
+
+fn umount(command: &mut Command, matches: &ArgMatches) {
+    // A dead mountpoint cannot be resolved, so it is only made absolute.
+    let path = std::path::absolute(matches.value_of("MOUNT").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+
+    let result = if matches.is_present("LAZY") {
+        unmount::unmount(&path, true)
+    } else {
+        let pid = control::server_pid(&path).ok();
+        match control::request(&path, "unmount") {
+            Ok(_) => wait_unmounted(&path, GRACE_PERIOD + UNMOUNT_TIMEOUT),
+            Err(_) => unmount::unmount(&path, false),
+        }
+        .or_else(|err| {
+            if !matches.is_present("FORCE") {
+                return Err(err);
+            }
+            if let Some(pid) = pid {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+            unmount::unmount(&path, true)
+        })
+    };
+
+    if let Err(err) = result {
+        eprintln!("nullfs: failed to unmount {}: {}", path.display(), err);
+        process::exit(1);
+    }
+}
+
+fn stats(command: &mut Command, matches: &ArgMatches) {
+    let path = fs::canonicalize(matches.value_of("MOUNT").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+
+    match control::request(&path, "stats") {
+        Ok(stats) => print!(
+            "{}",
+            format_stats(&stats, matches.value_of("FORMAT").unwrap())
+        ),
+        Err(err) => {
+            eprintln!("nullfs: failed to query {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+fn dump_tree(command: &mut Command, matches: &ArgMatches) {
+    let path = fs::canonicalize(matches.value_of("MOUNT").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+
+    match control::request(&path, "tree") {
+        Ok(tree) => print!("{}", tree),
+        Err(err) => {
+            eprintln!("nullfs: failed to query {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+fn bench(command: &mut Command, matches: &ArgMatches, registry: Registry) {
+    let workload = bench::Workload {
+        threads: *matches.get_one::<u64>("JOBS").unwrap() as usize,
+        block_size: *matches.get_one::<usize>("BLOCK_SIZE").unwrap(),
+        pattern: match matches.value_of("PATTERN") {
+            Some("random") => bench::Pattern::Random,
+            _ => bench::Pattern::Sequential,
+        },
+        duration: *matches.get_one::<Duration>("DURATION").unwrap(),
+    };
+    let sink = registry
+        .sink(matches.value_of("SINK").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+    let source = registry.source("empty").unwrap();
+    let config = Config {
+        threads: thread::available_parallelism().map_or(1, usize::from),
+        ..Config::default()
+    };
+
+    let dir = env::temp_dir().join(format!("nullfs-bench.{}", process::id()));
+    let options = options::identify(options::with_defaults(Vec::new()), "nullfs");
+    let result = fs::create_dir(&dir).and_then(|_| {
+        let session = Session::new(NullFS::new(config, sink, source), &dir, &options)?.spawn()?;
+        let report = bench::run(&dir.join("null"), workload);
+        // Unmounts the filesystem.
+        drop(session);
+        report
+    });
+    let _ = fs::remove_dir(&dir);
+
+    match result {
+        Ok(report) => print!("{}", report),
+        Err(err) => {
+            eprintln!("nullfs: benchmark failed: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// The parts of the configuration the flags of [`namespace_args`] set, the same for mounts
+/// and servers.
+fn namespace_config(command: &mut Command, matches: &ArgMatches) -> Config {
+    Config {
+        read_only: matches.is_present("READ_ONLY"),
+        size_accounting: size_accounting(matches),
+        advertise_size: matches
+            .get_one::<u64>("ADVERTISE_SIZE")
+            .copied()
+            .unwrap_or(0),
+        ignore_truncate: matches.is_present("IGNORE_TRUNCATE"),
+        fsync_delay: fsync_delay(matches),
+        interrupts: matches.get_one::<f64>("EINTR").map(|fraction| {
+            let calls = match matches.get_many::<Call>("EINTR_ON") {
+                Some(calls) => calls.copied().collect(),
+                None => vec![Call::Open, Call::Read, Call::Write],
+            };
+            Arc::new(Interrupts::new(*fraction, calls))
+        }),
+        crash: matches.get_one::<u64>("DIE_AFTER_FSYNC").map(|after| {
+            let exit = matches.value_of("DIE_BY") == Some("exit");
+            Arc::new(Crash::new(*after, exit))
+        }),
+        keep_under: matches.get_one::<u64>("KEEP_UNDER").copied(),
+        metadata: matches.is_present("METADATA"),
+        fill: ["READ_FROM", "FILL_PATTERN", "FILL_BYTE"]
+            .iter()
+            .find_map(|id| matches.get_one::<Arc<Pattern>>(id))
+            .cloned(),
+        endless_read: matches.is_present("ENDLESS_READ"),
+        preload: preload(command, matches),
+        dynamic: matches.is_present("DYNAMIC") || matches.is_present("METADATA"),
+        digest: matches.is_present("DIGEST"),
+        rules: matches
+            .get_many::<Rule>("RULE")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        case_insensitive: matches.is_present("CASE_INSENSITIVE"),
+        name: matches.get_one::<OsString>("NAME").unwrap().clone(),
+        file_attrs: file_attrs(matches),
+        dir_attrs: dir_attrs(matches),
+        blksize: *matches.get_one::<u32>("BLKSIZE").unwrap(),
+        name_policy: matches
+            .get_one::<NamePolicy>("NAME_POLICY")
+            .copied()
+            .unwrap_or_default(),
+        callers: Callers {
+            denied_uids: ids(matches, "DENY_UID"),
+            denied_gids: ids(matches, "DENY_GID"),
+            allowed_uids: ids(matches, "ALLOW_UID"),
+            allowed_gids: ids(matches, "ALLOW_GID"),
+        },
+        ..Config::default()
+    }
+}
+
+/// Kept files are only read back as far as their size reaches, and metadata includes the
+/// size, so both account it unless told otherwise.
+fn size_accounting(matches: &ArgMatches) -> SizeAccounting {
+    match matches.value_of("SIZE_ACCOUNTING") {
+        Some("high-watermark") => SizeAccounting::HighWatermark,
+        Some("cumulative") => SizeAccounting::Cumulative,
+        _ if matches.occurrences_of("SIZE_ACCOUNTING") == 0
+            && (matches.is_present("KEEP_UNDER")
+                || matches.is_present("METADATA")
+                || matches.is_present("READ_FROM")
+                || matches.is_present("FILL_PATTERN")
+                || matches.is_present("FILL_BYTE")) =>
+        {
+            SizeAccounting::HighWatermark
+        }
+        _ => SizeAccounting::None,
+    }
+}
+
+/// How long fsyncs take, from `--fsync-delay`, `--fsync-jitter` and `--fsync-delay-every`.
+fn fsync_delay(matches: &ArgMatches) -> Option<Arc<Delay>> {
+    let duration = *matches.get_one::<Duration>("FSYNC_DELAY")?;
+    let jitter = matches.get_one::<Duration>("FSYNC_JITTER").copied();
+    let every = matches.get_one::<u64>("FSYNC_DELAY_EVERY").copied();
+    Some(Arc::new(Delay::new(
+        duration,
+        jitter.unwrap_or_default(),
+        every.unwrap_or(1),
+    )))
+}
+
+/// The files a mount starts out with, from `--preload` followed by `--files`.
+fn preload(command: &mut Command, matches: &ArgMatches) -> Option<Arc<preload::Manifest>> {
+    let manifest = matches.get_one::<Arc<preload::Manifest>>("PRELOAD");
+    let Some(files) = matches.get_many::<PathBuf>("FILES") else {
+        return manifest.cloned();
+    };
+    let mut manifest = manifest
+        .map(|manifest| preload::Manifest::clone(manifest))
+        .unwrap_or_default();
+    let files = files.map(PathBuf::as_path);
+    if let Err(err) = manifest.add_files(files, file_attrs(matches), dir_attrs(matches)) {
+        command.error(ErrorKind::InvalidValue, err).exit();
+    }
+    Some(Arc::new(manifest))
+}
+
+/// Mode and owner of the null file, from `--file-mode`, `--file-uid` and `--file-gid`.
+fn file_attrs(matches: &ArgMatches) -> Attrs {
+    Attrs {
+        perm: *matches.get_one::<u16>("FILE_MODE").unwrap(),
+        uid: *matches.get_one::<u32>("FILE_UID").unwrap(),
+        gid: *matches.get_one::<u32>("FILE_GID").unwrap(),
+    }
+}
+
+/// Mode and owner of the root directory, from `--dir-mode`, `--dir-uid` and `--dir-gid`.
+fn dir_attrs(matches: &ArgMatches) -> Attrs {
+    Attrs {
+        perm: *matches.get_one::<u16>("DIR_MODE").unwrap(),
+        uid: *matches.get_one::<u32>("DIR_UID").unwrap(),
+        gid: *matches.get_one::<u32>("DIR_GID").unwrap(),
+    }
+}
+
+/// Checks that `name` can name a file in the root directory.
+fn parse_name(name: &str) -> Result<OsString, String> {
+    match name {
+        "" | "." | ".." => Err(format!("invalid file name '{}'", name)),
+        _ if name.contains('/') => Err(format!("file name '{}' contains a slash", name)),
+        _ => Ok(OsString::from(name)),
+    }
+}
+
+/// Sets up logging and returns what the flags of [`server_args`] ask a server for.
+fn server_parts(
+    command: &mut Command,
+    matches: &ArgMatches,
+    registry: Registry,
+) -> (Config, Box<dyn DataSink>, Box<dyn DataSource>) {
+    let _ = logger::init(
+        matches.occurrences_of("VERBOSE"),
+        logger::Format::Text,
+        logger::Output::Stream(Box::new(io::stderr())),
+    );
+
+    let sink = registry
+        .sink(matches.value_of("SINK").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+    let source = registry
+        .source(matches.value_of("SOURCE").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+    let config = namespace_config(command, matches);
+    (config, sink, source)
+}
+
+fn serve_nfs(command: &mut Command, matches: &ArgMatches, registry: Registry) {
+    let (config, sink, source) = server_parts(command, matches, registry);
+    let address = matches.get_one::<SocketAddr>("LISTEN").unwrap();
+    let listener = TcpListener::bind(address).unwrap_or_else(|err| {
+        let message = format!("failed to listen on {}: {}", address, err);
+        command.error(ErrorKind::Io, message).exit()
+    });
+    // Blocked before the threads are spawned, so they are left to the waiting one.
+    let signals = SignalSet::block(&[SIGHUP, SIGINT, SIGTERM]).unwrap();
+
+    let server = Arc::new(nfs::Server::new(config, sink, source));
+    thread::spawn({
+        let server = server.clone();
+        move || server.serve(listener)
+    });
+    log::info!("serving NFS on {}", address);
+
+    signals.wait().unwrap();
+    server.close();
+}
+
+fn serve_9p(command: &mut Command, matches: &ArgMatches, registry: Registry) {
+    let (config, sink, source) = server_parts(command, matches, registry);
+    let signals = SignalSet::block(&[SIGHUP, SIGINT, SIGTERM]).unwrap();
+    let server = Arc::new(ninep::Server::new(config, sink, source));
+
+    let socket = matches.get_one::<PathBuf>("SOCKET");
+    if let Some(path) = socket {
+        // A socket nobody answers on was left behind by an instance that died.
+        if UnixStream::connect(path).is_err() {
+            let _ = fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path).unwrap_or_else(|err| {
+            let message = format!("failed to listen on {}: {}", path.display(), err);
+            command.error(ErrorKind::Io, message).exit()
+        });
+        let server = server.clone();
+        thread::spawn(move || server.serve_unix(listener));
+        log::info!("serving 9P on {}", path.display());
+    } else {
+        let address = matches.get_one::<SocketAddr>("LISTEN").unwrap();
+        let listener = TcpListener::bind(address).unwrap_or_else(|err| {
+            let message = format!("failed to listen on {}: {}", address, err);
+            command.error(ErrorKind::Io, message).exit()
+        });
+        let server = server.clone();
+        thread::spawn(move || server.serve_tcp(listener));
+        log::info!("serving 9P on {}", address);
+    }
+
+    signals.wait().unwrap();
+    server.close();
+    if let Some(path) = socket {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn replay(matches: &ArgMatches) {
+    let _ = logger::init(
+        0,
+        logger::Format::Text,
+        logger::Output::Stream(Box::new(io::stderr())),
+    );
+
+    let trace = matches.get_one::<PathBuf>("TRACE").unwrap();
+    let target = matches.get_one::<PathBuf>("TARGET").unwrap();
+    let speed = *matches.get_one::<f64>("SPEED").unwrap();
+    match replay::replay(trace, target, speed) {
+        Ok(summary) => println!(
+            "Replayed {} requests, {} failed",
+            summary.replayed + summary.failed,
+            summary.failed
+        ),
+        Err(err) => {
+            eprintln!("nullfs: failed to replay {}: {}", trace.display(), err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Formats the `name value` lines sent by the control socket as `format`.
+fn format_stats(stats: &str, format: &str) -> String {
+    let counters: Vec<(&str, &str)> = stats
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .collect();
+    let mut formatted = String::new();
+    match format {
+        "json" => {
+            formatted.push('{');
+            for (i, (name, value)) in counters.iter().enumerate() {
+                let separator = if i > 0 { "," } else { "" };
+                let name = logger::json_string(name);
+                let _ = write!(formatted, "{}\n  {}: {}", separator, name, value);
+            }
+            formatted.push_str("\n}\n");
+        }
+        "table" => {
+            let names = counters
+                .iter()
+                .map(|(name, _)| name.len())
+                .max()
+                .unwrap_or(0);
+            let values = counters
+                .iter()
+                .map(|(_, value)| value.len())
+                .max()
+                .unwrap_or(0);
+            for (name, value) in counters {
+                let _ = writeln!(formatted, "{:<names$}  {:>values$}", name, value);
+            }
+        }
+        _ => formatted.push_str(stats),
+    }
+    formatted
+}
+
+fn wait_unmounted(path: &Path, timeout: Duration) -> io::Result<()> {
+    let start = Instant::now();
+    while unmount::is_mounted(path)? {
+        if start.elapsed() > timeout {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the instance did not unmount in time",
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+fn install_unit(command: &mut Command, matches: &ArgMatches) {
+    let path = fs::canonicalize(matches.value_of("MOUNT").unwrap())
+        .unwrap_or_else(|err| command.error(ErrorKind::InvalidValue, err).exit());
+    let args: Vec<String> = matches
+        .values_of("ARGS")
+        .unwrap_or_default()
+        .map(String::from)
+        .collect();
+
+    // Catch mistakes now rather than when the unit starts.
+    let mut check = vec!["nullfs".to_string()];
+    check.extend(args.iter().cloned());
+    check.push(path.to_string_lossy().into_owned());
+    if let Err(err) = self::command().try_get_matches_from(check) {
+        err.exit();
+    }
+
+    let unit = if matches.is_present("MOUNT_UNIT") {
+        unit::mount(&path, &mount_options(&args))
+    } else {
+        let exe = env::current_exe().unwrap();
+        unit::service(&exe, &path, &args)
+    };
+
+    if !matches.is_present("INSTALL") {
+        print!("{}", unit.contents);
+        return;
+    }
+
+    let file = Path::new(matches.value_of("UNIT_DIR").unwrap()).join(&unit.name);
+    if let Err(err) = fs::write(&file, &unit.contents) {
+        command
+            .error(
+                ErrorKind::Io,
+                format!("failed to write {}: {}", file.display(), err),
+            )
+            .exit();
+    }
+    println!("Installed {}", file.display());
+    println!(
+        "Run `systemctl daemon-reload && systemctl enable --now {}` to start it.",
+        unit.name
+    );
+}
+
+/// Finds the file given with `--config`, which has to be read before the rest is parsed.
+fn config_file(args: &[OsString]) -> Option<OsString> {
+    let mut args = args.iter().skip(1);
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            file = args.next().cloned();
+        } else if let Some(value) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            file = Some(value.into());
+        }
+    }
+    file
+}
+
+/// Turns nullfs arguments into the options of a mount unit: `-o` values are
+/// kept and every long flag becomes `name` or `name=value`.
+fn mount_options(args: &[String]) -> Vec<String> {
+    let command = command();
+    let mut options = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" || arg == "--option" {
+            options.extend(args.next().cloned());
+        } else if let Some(value) = arg.strip_prefix("--option=") {
+            options.push(value.to_string());
+        } else if let Some(flag) = arg.strip_prefix("--") {
+            if flag.contains('=') {
+                options.push(flag.to_string());
+                continue;
+            }
+            let takes_value = command
+                .get_arguments()
+                .any(|a| a.get_long() == Some(flag) && a.is_takes_value_set());
+            let value = if takes_value { args.next() } else { None };
+            match value {
+                Some(value) => options.push(format!("{}={}", flag, value)),
+                None if flag == "foreground" => {}
+                None => options.push(flag.to_string()),
+            }
+        }
+    }
+    options
+}
+
+/// Options that mount(8) and fstab use for themselves.
+fn is_mount_only_option(option: &str) -> bool {
+    matches!(
+        option,
+        "defaults"
+            | "auto"
+            | "noauto"
+            | "user"
+            | "nouser"
+            | "users"
+            | "owner"
+            | "group"
+            | "nofail"
+            | "_netdev"
+    ) || option.starts_with("x-")
+        || option.starts_with("comment=")
+}
+
+/// Translates `mount.nullfs SOURCE DIR [-sfnv] [-o OPTIONS] [-t TYPE]` into
+/// nullfs arguments. Mount options are passed on as `-o`, options of mount(8)
+/// are dropped and any other option names a long flag of nullfs.
+fn mount_helper_args(args: &[OsString]) -> Vec<OsString> {
+    let command = command();
+    let mut positional = Vec::new();
+    let mut options = Vec::new();
+    let mut sloppy = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-o") => options.extend(args.next().map(|o| o.to_string_lossy().into_owned())),
+            Some("-t") => {
+                args.next();
+            }
+            Some("-s") => sloppy = true,
+            Some("-f") => process::exit(0),
+            Some("-n") | Some("-v") => {}
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if positional.len() != 2 {
+        eprintln!("usage: mount.nullfs SOURCE DIR [-sfnv] [-o OPTIONS]");
+        process::exit(1);
+    }
+
+    let mut translated = vec![OsString::from("nullfs")];
+    if positional[0] != "none" {
+        translated.push(OsString::from("--fsname"));
+        translated.push(positional[0].clone());
+    }
+
+    for option in options.iter().flat_map(|o| o.split(',')) {
+        if option.is_empty() || is_mount_only_option(option) {
+            continue;
+        }
+        if options::parse(option).is_ok() {
+            translated.push(OsString::from("-o"));
+            translated.push(OsString::from(option));
+            continue;
+        }
+        let flag = option.split_once('=').map_or(option, |(flag, _)| flag);
+        if sloppy && !command.get_arguments().any(|a| a.get_long() == Some(flag)) {
+            continue;
+        }
+        translated.push(OsString::from(format!("--{}", option)));
+    }
+
+    translated.push(positional[1].clone());
+    translated
+}
@@ -0,0 +1,273 @@
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::callers::Callers;
+use crate::crash::Crash;
+use crate::delay::Delay;
+use crate::interrupt::Interrupts;
+use crate::logger::{json_duration, json_string};
+use crate::names::NamePolicy;
+use crate::pattern::Pattern;
+#[cfg(feature = "preload")]
+use crate::preload::Manifest;
+use crate::rules::Rule;
+
+/// Runtime behavior of the filesystem.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Refuse every modification with EROFS.
+    pub read_only: bool,
+    /// Worker threads serving reads, writes and flushes; 0 serves them on the request loop.
+    pub threads: usize,
+    /// Largest write the kernel may send in one request, instead of fuser's default.
+    pub max_write: Option<u32>,
+    /// Requests the kernel keeps in flight in the background, such as readahead and
+    /// writeback, instead of a value derived from the number of CPUs.
+    pub max_background: Option<u16>,
+    /// Background requests at which the kernel considers the mount congested, instead of
+    /// three quarters of `max_background`.
+    pub congestion_threshold: Option<u16>,
+    /// Preferred I/O size reported in the attributes of every file.
+    pub blksize: u32,
+    /// Let the kernel cache and coalesce buffered writes before sending them.
+    pub writeback_cache: bool,
+    /// `FOPEN_*` flags returned when the file is opened, such as `FOPEN_DIRECT_IO`.
+    pub open_flags: u32,
+    /// Let the kernel skip open requests, and opendir ones with the `abi-7-29` feature,
+    /// serving I/O without handles.
+    pub no_open: bool,
+    /// How long the kernel may cache names; fuser sends it as the attribute timeout of lookups too.
+    pub entry_ttl: Duration,
+    /// How long the kernel may cache attributes returned by getattr and setattr.
+    pub attr_ttl: Duration,
+    /// How the advertised size of the null files follows what is written to it.
+    pub size_accounting: SizeAccounting,
+    /// Size files report at least, as if they were that large but sparse.
+    pub advertise_size: u64,
+    /// Leave the size and the kept contents of files as they are when they are truncated.
+    pub ignore_truncate: bool,
+    /// How long flushing files and directories to disk takes, if it is simulated.
+    pub fsync_delay: Option<Arc<Delay>>,
+    /// When the filesystem plays dead, if it does.
+    pub crash: Option<Arc<Crash>>,
+    /// Opens, reads and writes failed with EINTR, if any are.
+    pub interrupts: Option<Arc<Interrupts>>,
+    /// Keep what is written to files in memory as long as they stay this small, reading it
+    /// back instead of the source.
+    pub keep_under: Option<u64>,
+    /// Keep the sizes and times set on files and have writes modify them, while the data is
+    /// still sunk. Modes and owners are kept regardless.
+    pub metadata: bool,
+    /// What files read as up to their size when nothing was kept of them, instead of
+    /// asking the source.
+    pub fill: Option<Arc<Pattern>>,
+    /// Answer every read of a file in full whatever its size, with the fill pattern or zeros.
+    pub endless_read: bool,
+    /// Files and directories the mount starts out with.
+    #[cfg(feature = "preload")]
+    pub preload: Option<Arc<Manifest>>,
+    /// Let files and directories be created anywhere, each of them a null file.
+    pub dynamic: bool,
+    /// Hash what is written to every file, reported as its `user.nullfs.digest`.
+    pub digest: bool,
+    /// File recording who opened, created, removed and wrote to which path.
+    pub audit: Option<PathBuf>,
+    /// File recording the requests the mount receives, to be replayed later.
+    pub record: Option<PathBuf>,
+    /// Keep what writes carried in the recorded trace.
+    pub record_payload: bool,
+    /// What happens to files by their name, the first matching rule applying.
+    pub rules: Vec<Rule>,
+    /// Restrictions on the names of new files.
+    pub name_policy: NamePolicy,
+    /// Resolve names regardless of case, keeping the case they were created with.
+    pub case_insensitive: bool,
+    /// Name of the null file the root directory starts out with.
+    pub name: OsString,
+    /// Mode and owner of the null file and of the files of `--files`.
+    pub file_attrs: Attrs,
+    /// Mode and owner of the root directory and of those leading to the files of `--files`.
+    pub dir_attrs: Attrs,
+    /// Users and groups refused with EACCES when they access, open or write files.
+    pub callers: Callers,
+    /// Check the modes, owners and ACLs of files on open, which the kernel does itself
+    /// when mounted with default_permissions.
+    pub check_permissions: bool,
+}
+
+impl Config {
+    /// Describes the configuration as a JSON object, leaving out what is unset as null.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(
+            json,
+            "{{\"read_only\":{},\"threads\":{},\"max_write\":{},\"max_background\":{},\"congestion_threshold\":{},\"blksize\":{},\"writeback_cache\":{},\"open_flags\":{},\"no_open\":{},\"entry_ttl\":{},\"attr_ttl\":{}",
+            self.read_only,
+            self.threads,
+            json_option(self.max_write),
+            json_option(self.max_background),
+            json_option(self.congestion_threshold),
+            self.blksize,
+            self.writeback_cache,
+            self.open_flags,
+            self.no_open,
+            json_duration(self.entry_ttl),
+            json_duration(self.attr_ttl),
+        );
+        let size_accounting = match self.size_accounting {
+            SizeAccounting::None => "none",
+            SizeAccounting::HighWatermark => "high-watermark",
+            SizeAccounting::Cumulative => "cumulative",
+        };
+        let _ = write!(
+            json,
+            ",\"size_accounting\":\"{}\",\"advertise_size\":{},\"ignore_truncate\":{},\"fsync_delay\":{},\"crash\":{},\"interrupts\":{},\"keep_under\":{},\"metadata\":{}",
+            size_accounting,
+            self.advertise_size,
+            self.ignore_truncate,
+            json_option(self.fsync_delay.as_ref().map(|delay| delay.to_json())),
+            json_option(self.crash.as_ref().map(|crash| crash.to_json())),
+            json_option(self.interrupts.as_ref().map(|interrupts| interrupts.to_json())),
+            json_option(self.keep_under),
+            self.metadata,
+        );
+        #[cfg(feature = "preload")]
+        let preload = self
+            .preload
+            .as_ref()
+            .map(|manifest| format!("{{\"entries\":{}}}", manifest.count()));
+        #[cfg(not(feature = "preload"))]
+        let preload: Option<String> = None;
+        let rules: Vec<String> = self.rules.iter().map(Rule::to_json).collect();
+        let _ = write!(
+            json,
+            ",\"fill\":{},\"endless_read\":{},\"preload\":{},\"dynamic\":{},\"digest\":{},\"audit\":{},\"record\":{},\"record_payload\":{},\"rules\":[{}]",
+            json_option(self.fill.as_ref().map(|fill| json_string(&fill.to_hex()))),
+            self.endless_read,
+            json_option(preload),
+            self.dynamic,
+            self.digest,
+            json_option(self.audit.as_deref().map(json_path)),
+            json_option(self.record.as_deref().map(json_path)),
+            self.record_payload,
+            rules.join(","),
+        );
+        let _ = write!(
+            json,
+            ",\"name_policy\":{{\"max_length\":{},\"utf8\":{},\"no_control\":{}}},\"case_insensitive\":{},\"name\":{},\"file_attrs\":{},\"dir_attrs\":{}",
+            json_option(self.name_policy.max_length),
+            self.name_policy.utf8,
+            self.name_policy.no_control,
+            self.case_insensitive,
+            json_string(&self.name.to_string_lossy()),
+            self.file_attrs.to_json(),
+            self.dir_attrs.to_json(),
+        );
+        let _ = write!(
+            json,
+            ",\"callers\":{{\"denied_uids\":{},\"denied_gids\":{},\"allowed_uids\":{},\"allowed_gids\":{}}},\"check_permissions\":{}}}",
+            json_ids(&self.callers.denied_uids),
+            json_ids(&self.callers.denied_gids),
+            json_ids(&self.callers.allowed_uids),
+            json_ids(&self.callers.allowed_gids),
+            self.check_permissions,
+        );
+        json
+    }
+}
+
+fn json_option(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| "null".to_string(), |value| value.to_string())
+}
+
+fn json_path(path: &Path) -> String {
+    json_string(&path.to_string_lossy())
+}
+
+fn json_ids(ids: &[u32]) -> String {
+    let ids: Vec<String> = ids.iter().map(u32::to_string).collect();
+    format!("[{}]", ids.join(","))
+}
+
+/// Permission bits and owner a file or directory of the mount starts out with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attrs {
+    pub perm: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Attrs {
+    fn to_json(self) -> String {
+        format!(
+            "{{\"mode\":{},\"uid\":{},\"gid\":{}}}",
+            self.perm, self.uid, self.gid
+        )
+    }
+}
+
+/// What the size of a null file reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeAccounting {
+    /// Always empty.
+    #[default]
+    None,
+    /// The end of the furthest write or allocation.
+    HighWatermark,
+    /// Every byte written so far.
+    Cumulative,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            read_only: false,
+            threads: 0,
+            max_write: None,
+            max_background: None,
+            congestion_threshold: None,
+            blksize: 4096,
+            writeback_cache: false,
+            open_flags: 0,
+            no_open: false,
+            entry_ttl: Duration::from_secs(1),
+            attr_ttl: Duration::from_secs(1),
+            size_accounting: SizeAccounting::None,
+            advertise_size: 0,
+            ignore_truncate: false,
+            fsync_delay: None,
+            crash: None,
+            interrupts: None,
+            keep_under: None,
+            metadata: false,
+            fill: None,
+            endless_read: false,
+            #[cfg(feature = "preload")]
+            preload: None,
+            dynamic: false,
+            digest: false,
+            audit: None,
+            record: None,
+            record_payload: false,
+            rules: Vec::new(),
+            name_policy: NamePolicy::default(),
+            case_insensitive: false,
+            name: OsString::from("null"),
+            file_attrs: Attrs {
+                perm: 0o666,
+                uid: 0,
+                gid: 0,
+            },
+            dir_attrs: Attrs {
+                perm: 0o777,
+                uid: 0,
+                gid: 0,
+            },
+            callers: Callers::default(),
+            check_permissions: false,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Mutex;
+
+/// What was written to a file as long as it stayed small enough to be kept in memory.
+#[derive(Debug)]
+pub struct Contents(Mutex<Option<Vec<u8>>>);
+
+/// How much of what is written to a file is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keep {
+    /// Everything, as long as the file never grows past this size.
+    Under(u64),
+    /// Only the last write, up to this many bytes of it, read back from the start.
+    Last(u64),
+}
+
+impl Contents {
+    /// Keeps `data` written at `offset` as `keep` says.
+    pub fn write(&self, keep: Keep, offset: u64, data: &[u8]) {
+        match keep {
+            Keep::Under(limit) => self.write_under(limit, offset, data),
+            Keep::Last(limit) => {
+                let data = &data[..data.len().min(usize::try_from(limit).unwrap_or(usize::MAX))];
+                *self.0.lock().unwrap() = Some(data.to_vec());
+            }
+        }
+    }
+
+    /// Keeps `data` written at `offset`, or drops everything once the file outgrows `limit`
+    /// for good.
+    fn write_under(&self, limit: u64, offset: u64, data: &[u8]) {
+        let mut kept = self.0.lock().unwrap();
+        let end = offset.saturating_add(data.len() as u64);
+        match kept.as_mut() {
+            Some(bytes) if end <= limit => {
+                let (offset, end) = (offset as usize, end as usize);
+                if bytes.len() < end {
+                    bytes.resize(end, 0);
+                }
+                bytes[offset..end].copy_from_slice(data);
+            }
+            _ => *kept = None,
+        }
+    }
+
+    /// Cuts or extends what is kept to `size` bytes as a truncation does, dropping it when
+    /// the file grows past the limit and starting over when it is emptied.
+    pub fn truncate(&self, keep: Keep, size: u64) {
+        let mut kept = self.0.lock().unwrap();
+        match (keep, kept.as_mut()) {
+            (_, None) if size == 0 => *kept = Some(Vec::new()),
+            (_, None) => {}
+            (Keep::Under(limit), Some(bytes)) if size <= limit => bytes.resize(size as usize, 0),
+            (Keep::Under(_), Some(_)) => *kept = None,
+            (Keep::Last(_), Some(bytes)) => {
+                bytes.truncate(usize::try_from(size).unwrap_or(usize::MAX));
+            }
+        }
+    }
+
+    /// Reports whether the contents are still kept.
+    pub fn is_kept(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    /// Returns up to `size` bytes at `offset`, or nothing once the contents were dropped.
+    pub fn read(&self, offset: u64, size: u32) -> Option<Vec<u8>> {
+        let kept = self.0.lock().unwrap();
+        kept.as_ref().map(|bytes| {
+            let start = bytes
+                .len()
+                .min(usize::try_from(offset).unwrap_or(usize::MAX));
+            let end = bytes.len().min(start.saturating_add(size as usize));
+            bytes[start..end].to_vec()
+        })
+    }
+}
+
+impl Default for Contents {
+    fn default() -> Contents {
+        Contents(Mutex::new(Some(Vec::new())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_small_files() {
+        let contents = Contents::default();
+        contents.write(Keep::Under(8), 2, b"cd");
+        contents.write(Keep::Under(8), 0, b"ab");
+        assert_eq!(contents.read(0, 16), Some(b"abcd".to_vec()));
+        assert_eq!(contents.read(3, 16), Some(b"d".to_vec()));
+        assert_eq!(contents.read(9, 16), Some(Vec::new()));
+        contents.truncate(Keep::Under(8), 6);
+        assert_eq!(contents.read(0, 16), Some(b"abcd\0\0".to_vec()));
+    }
+
+    #[test]
+    fn drops_files_outgrowing_the_limit() {
+        let contents = Contents::default();
+        contents.write(Keep::Under(4), 2, b"abc");
+        assert!(!contents.is_kept());
+        contents.write(Keep::Under(4), 0, b"ab");
+        assert_eq!(contents.read(0, 4), None);
+        contents.truncate(Keep::Under(4), 0);
+        assert_eq!(contents.read(0, 4), Some(Vec::new()));
+    }
+
+    #[test]
+    fn keeps_the_last_write() {
+        let contents = Contents::default();
+        contents.write(Keep::Last(3), 10, b"abcdef");
+        contents.write(Keep::Last(3), 0, b"xy");
+        assert_eq!(contents.read(0, 8), Some(b"xy".to_vec()));
+        contents.write(Keep::Last(3), 0, b"abcdef");
+        assert_eq!(contents.read(0, 8), Some(b"abc".to_vec()));
+        contents.truncate(Keep::Last(3), 1);
+        assert_eq!(contents.read(0, 8), Some(b"a".to_vec()));
+    }
+}
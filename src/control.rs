@@ -0,0 +1,191 @@
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::systemd;
+
+/// Where root binds control sockets without `$XDG_RUNTIME_DIR`.
+#[cfg(target_os = "linux")]
+const ROOT_RUNTIME_DIR: &str = "/run/nullfs";
+#[cfg(not(target_os = "linux"))]
+const ROOT_RUNTIME_DIR: &str = "/var/run/nullfs";
+
+/// Control socket of a mounted instance, removed again when dropped.
+///
+/// Clients send a single line with a command and receive either `ok` followed
+/// by the result, or `error` followed by a message.
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Server {
+    /// Listens for commands concerning `mountpoint`, answering them with `handler`.
+    pub fn bind<F>(mountpoint: &Path, handler: F) -> io::Result<Server>
+    where
+        F: Fn(&str) -> io::Result<String> + Send + Sync + 'static,
+    {
+        socket_dir()?;
+        let path = socket_path(mountpoint);
+
+        // A socket nobody answers on was left behind by an instance that died.
+        if UnixStream::connect(&path).is_err() {
+            let _ = fs::remove_file(&path);
+        }
+        let listener = UnixListener::bind(&path)?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(err) = answer(stream, &handler) {
+                    log::error!("control request failed: {}", err);
+                }
+            }
+        });
+        Ok(Server { path })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn answer<F>(stream: UnixStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&str) -> io::Result<String>,
+{
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let mut stream = stream;
+    match handler(line.trim()) {
+        Ok(result) => write!(stream, "ok\n{}", result),
+        Err(err) => writeln!(stream, "error\n{}", err),
+    }
+}
+
+/// Sends `command` to the instance mounted on `mountpoint` and returns its result.
+pub fn request(mountpoint: &Path, command: &str) -> io::Result<String> {
+    exchange(connect(mountpoint)?, command)
+}
+
+/// Returns the process serving `mountpoint`, as its control socket reports it and, where
+/// the kernel tells, as the process on the other end of the socket and holding a FUSE
+/// connection confirms.
+pub fn server_pid(mountpoint: &Path) -> io::Result<libc::pid_t> {
+    let stream = connect(mountpoint)?;
+    #[cfg(target_os = "linux")]
+    let peer = peer_pid(&stream)?;
+    let pid = exchange(stream, "pid")?
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "malformed pid"))?;
+    #[cfg(target_os = "linux")]
+    if pid != peer || !holds_fuse(pid) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("pid {} from the control socket serves no mount", pid),
+        ));
+    }
+    Ok(pid)
+}
+
+/// Reports whether the process `pid` has /dev/fuse open.
+#[cfg(target_os = "linux")]
+fn holds_fuse(pid: libc::pid_t) -> bool {
+    let fds = fs::read_dir(format!("/proc/{}/fd", pid));
+    fds.into_iter()
+        .flatten()
+        .flatten()
+        .any(|fd| fs::read_link(fd.path()).is_ok_and(|target| target == Path::new("/dev/fuse")))
+}
+
+/// Connects to the control socket of `mountpoint`, provided nobody else could have bound it.
+fn connect(mountpoint: &Path) -> io::Result<UnixStream> {
+    check_private(&runtime_dir())?;
+    UnixStream::connect(socket_path(mountpoint))
+}
+
+#[cfg(target_os = "linux")]
+fn peer_pid(stream: &UnixStream) -> io::Result<libc::pid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.pid)
+}
+
+fn exchange(mut stream: UnixStream, command: &str) -> io::Result<String> {
+    writeln!(stream, "{}", command)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    match response.split_once('\n') {
+        Some(("ok", result)) => Ok(result.to_string()),
+        Some(("error", message)) => Err(io::Error::other(message.trim_end().to_string())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed control response",
+        )),
+    }
+}
+
+pub fn socket_path(mountpoint: &Path) -> PathBuf {
+    runtime_dir().join(format!("{}.sock", systemd::escape_path(mountpoint)))
+}
+
+/// Returns the directory control sockets are bound in, creating it if need be.
+pub fn socket_dir() -> io::Result<PathBuf> {
+    let dir = runtime_dir();
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    check_private(&dir)?;
+    Ok(dir)
+}
+
+/// Refuses `dir` unless it is a directory of the effective user that nobody else may
+/// enter, since anyone who can bind a socket in it can pose as a mount. Under the shared
+/// temporary directory, another user may well have created it first.
+fn check_private(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    let euid = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != euid || metadata.mode() & 0o777 != 0o700 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory private to uid {}",
+                dir.display(),
+                euid
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn runtime_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return Path::new(&dir).join("nullfs");
+    }
+    match unsafe { libc::geteuid() } {
+        0 => PathBuf::from(ROOT_RUNTIME_DIR),
+        uid => env::temp_dir().join(format!("nullfs-{}", uid)),
+    }
+}
@@ -0,0 +1,50 @@
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+/// Point at which the filesystem plays dead, for applications to test how they recover
+/// from a crash.
+#[derive(Debug)]
+pub struct Crash {
+    after: u64,
+    exit: bool,
+    fsyncs: AtomicU64,
+}
+
+impl Crash {
+    /// Dies once `after` fsyncs have been acknowledged, exiting if `exit` says so and
+    /// answering nothing from then on otherwise.
+    pub fn new(after: u64, exit: bool) -> Crash {
+        Crash {
+            after,
+            exit,
+            fsyncs: AtomicU64::new(0),
+        }
+    }
+
+    /// Describes when and how the filesystem dies as JSON.
+    pub fn to_json(&self) -> String {
+        let by = if self.exit { "exit" } else { "hang" };
+        format!("{{\"after\":{},\"by\":\"{}\"}}", self.after, by)
+    }
+
+    /// Counts an acknowledged fsync.
+    pub fn fsynced(&self) {
+        self.fsyncs.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Dies before a request is answered if enough fsyncs were.
+    pub fn check(&self) {
+        if self.fsyncs.load(Ordering::SeqCst) < self.after {
+            return;
+        }
+        if self.exit {
+            log::error!("exiting after {} fsyncs", self.after);
+            process::exit(1);
+        }
+        log::error!("answering nothing after {} fsyncs", self.after);
+        loop {
+            thread::park();
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+/// What the daemon tells the parent waiting on it, ahead of the message of a failure.
+const READY: u8 = 0;
+const FAILED: u8 = 1;
+
+/// End of the pipe the parent of a daemon waits on until the daemon is up or gives up, so
+/// failures to start reach the terminal and the exit code of whoever launched it.
+pub struct Detached(File);
+
+impl Detached {
+    /// Tells the parent the daemon is up, letting it exit successfully.
+    pub fn ready(self) {
+        let _ = (&self.0).write_all(&[READY]);
+    }
+
+    /// Hands `message` to the parent to print before it exits with a failure.
+    pub fn fail(self, message: &str) {
+        let _ = (&self.0).write_all(&[&[FAILED], message.as_bytes()].concat());
+    }
+}
+
+/// Detaches from the controlling terminal in a forked child, which must be called before
+/// any thread is spawned. The parent stays behind until the child reports whether it came
+/// up through the returned [`Detached`], and exits accordingly.
+pub fn daemonize() -> io::Result<Detached> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    // Helpers such as fusermount must not hold the parent up.
+    if unsafe { libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => drop(reader),
+        _ => {
+            drop(writer);
+            wait(reader)
+        }
+    }
+
+    let detached = Detached(writer);
+    match detach() {
+        Ok(()) => Ok(detached),
+        Err(err) => {
+            detached.fail(&format!("failed to daemonize: {}", err));
+            Err(err)
+        }
+    }
+}
+
+/// Waits in the parent for the daemon to report, exiting as it says without running exit
+/// handlers, the mounts belonging to the daemon now.
+fn wait(mut reader: File) -> ! {
+    let mut report = Vec::new();
+    let _ = reader.read_to_end(&mut report);
+    let code = match report.split_first() {
+        Some((&READY, _)) => 0,
+        Some((_, message)) => {
+            eprintln!("nullfs: {}", String::from_utf8_lossy(message));
+            1
+        }
+        None => {
+            eprintln!("nullfs: the daemon exited before it was up");
+            1
+        }
+    };
+    unsafe { libc::_exit(code) }
+}
+
+fn detach() -> io::Result<()> {
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    env::set_current_dir("/")?;
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::logger::json_duration;
+
+/// Time an operation takes to answer, to simulate slow hardware.
+#[derive(Debug)]
+pub struct Delay {
+    duration: Duration,
+    jitter: Duration,
+    every: u64,
+    seed: u64,
+    count: AtomicU64,
+}
+
+impl Delay {
+    /// Delays every `every`th operation by `duration`, give or take up to `jitter`.
+    pub fn new(duration: Duration, jitter: Duration, every: u64) -> Delay {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Delay {
+            duration,
+            jitter,
+            every: every.max(1),
+            seed,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// How long the next operation waits, zero if it is not one of those delayed.
+    pub fn next(&self) -> Duration {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if !count.is_multiple_of(self.every) {
+            return Duration::ZERO;
+        }
+        let jitter = self.jitter.as_nanos().min(u64::MAX as u128 / 2) as u64;
+        if jitter == 0 {
+            return self.duration;
+        }
+        let offset = Duration::from_nanos(splitmix(self.seed ^ count) % (2 * jitter + 1));
+        self.duration
+            .saturating_add(offset)
+            .saturating_sub(self.jitter)
+    }
+
+    /// Describes how long and how often operations wait as JSON.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"duration\":{},\"jitter\":{},\"every\":{}}}",
+            json_duration(self.duration),
+            json_duration(self.jitter),
+            self.every
+        )
+    }
+
+    /// Sleeps for as long as the next operation waits.
+    pub fn wait(&self) {
+        let duration = self.next();
+        if !duration.is_zero() {
+            thread::sleep(duration);
+        }
+    }
+}
+
+pub(crate) fn splitmix(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,2056 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "abi-7-29")]
+use fuser::consts::FUSE_NO_OPENDIR_SUPPORT;
+use fuser::consts::{
+    FOPEN_DIRECT_IO, FUSE_CACHE_SYMLINKS, FUSE_DO_READDIRPLUS, FUSE_EXPORT_SUPPORT,
+    FUSE_FLOCK_LOCKS, FUSE_NO_OPEN_SUPPORT, FUSE_POSIX_LOCKS, FUSE_READDIRPLUS_AUTO,
+    FUSE_WRITEBACK_CACHE,
+};
+use fuser::fuse_forget_one;
+#[cfg(target_os = "macos")]
+use fuser::ReplyXTimes;
+use fuser::{
+    FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek,
+    ReplyOpen, ReplyWrite, ReplyXattr, Request, TimeOrNow,
+};
+#[cfg(not(target_os = "linux"))]
+use libc::ENOATTR as ENODATA;
+use libc::{
+    c_int, EACCES, EBADF, EDQUOT, EFBIG, EINTR, EINVAL, EIO, ENOENT, ENOSYS, ENOTDIR, ENOTTY,
+    ENXIO, EOPNOTSUPP, EPERM, ERANGE, EROFS, ESTALE, F_UNLCK, O_ACCMODE, O_RDONLY, O_TRUNC,
+    O_WRONLY, R_OK, S_IFCHR, S_IFMT, S_IFREG, W_OK, X_OK,
+};
+#[cfg(target_os = "linux")]
+use libc::{ENODATA, FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE};
+#[cfg(not(target_os = "macos"))]
+use libc::{SEEK_DATA, SEEK_HOLE};
+
+use crate::acl;
+use crate::audit::{Audit, Caller};
+use crate::config::{Config, SizeAccounting};
+use crate::contents::{Contents, Keep};
+use crate::interrupt::Call;
+use crate::ioctl;
+use crate::locks::{Lock, LockTable};
+use crate::logger::Op;
+use crate::namespace::{Inode, Namespace, SetAttr, Tree, NULL, NULL_RDEV};
+use crate::pattern::Pattern;
+use crate::pipe::Pipe;
+use crate::pool::Pool;
+use crate::rules::{self, Action};
+use crate::shutdown::Shutdown;
+use crate::sink::{DataSink, DataSource, Discard, Empty};
+use crate::stats::Stats;
+use crate::systemd;
+#[cfg(feature = "trace")]
+use crate::trace::Recorder;
+use crate::trace::{Operation, Record};
+
+// The FUSE protocol carries the fallocate(2) modes of Linux, which only its libc names.
+#[cfg(not(target_os = "linux"))]
+const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+#[cfg(not(target_os = "linux"))]
+const FALLOC_FL_PUNCH_HOLE: i32 = 0x02;
+#[cfg(not(target_os = "linux"))]
+const FALLOC_FL_ZERO_RANGE: i32 = 0x10;
+#[cfg(target_os = "macos")]
+const SEEK_HOLE: i32 = 3;
+#[cfg(target_os = "macos")]
+const SEEK_DATA: i32 = 4;
+
+/// How long sinks and sources get to close once the filesystem shuts down.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most bytes copied by one `copy_file_range`, callers asking again for the rest.
+const COPY_CHUNK: u32 = 1 << 20;
+
+/// Mode bit of directories whose entries only their owners may remove or rename.
+const STICKY: u16 = 0o1000;
+
+pub struct NullFS {
+    config: Config,
+    sink: Arc<dyn DataSink>,
+    source: Arc<dyn DataSource>,
+    pool: Option<Pool>,
+    namespace: Arc<Mutex<Namespace>>,
+    handles: HashMap<u64, Handle>,
+    next_handle: u64,
+    /// Files, or directories, are served without handles since the kernel agreed to skip
+    /// opening them.
+    no_open: bool,
+    no_opendir: bool,
+    locks: LockTable,
+    stats: Arc<Stats>,
+    audit: Option<Audit>,
+    #[cfg(feature = "trace")]
+    recorder: Option<Recorder>,
+    shutdown: Shutdown,
+    closed: bool,
+}
+
+/// An open file or directory.
+struct Handle {
+    ino: u64,
+    caller: Caller,
+    /// Bytes written through the handle, for the audit log.
+    written: Arc<AtomicU64>,
+}
+
+impl NullFS {
+    pub fn new(config: Config, sink: Box<dyn DataSink>, source: Box<dyn DataSource>) -> NullFS {
+        let stats = Arc::<Stats>::default();
+        let namespace = Namespace::new(stats.clone(), &config);
+        NullFS {
+            config,
+            sink: Arc::from(sink),
+            source: Arc::from(source),
+            pool: None,
+            namespace: Arc::new(Mutex::new(namespace)),
+            handles: HashMap::new(),
+            next_handle: 1,
+            no_open: false,
+            no_opendir: false,
+            locks: LockTable::default(),
+            stats,
+            audit: None,
+            #[cfg(feature = "trace")]
+            recorder: None,
+            shutdown: Shutdown::default(),
+            closed: false,
+        }
+    }
+
+    /// Returns the handle that starts the orderly shutdown of this filesystem.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Returns the counters of this filesystem.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Returns the handle to inspect the files of this filesystem while it is mounted.
+    pub fn tree(&self) -> Tree {
+        Tree::new(self.namespace.clone())
+    }
+
+    fn namespace(&self) -> MutexGuard<'_, Namespace> {
+        self.namespace.lock().unwrap()
+    }
+
+    fn is_file(&self, ino: u64) -> bool {
+        self.namespace().is_kind(ino, FileType::RegularFile)
+    }
+
+    fn is_dir(&self, ino: u64) -> bool {
+        self.namespace().is_kind(ino, FileType::Directory)
+    }
+
+    /// Returns the size counter of a regular file.
+    fn size(&self, ino: u64) -> Option<Arc<AtomicU64>> {
+        match self.namespace().get(ino) {
+            Some(inode) if inode.kind() == FileType::RegularFile => Some(inode.size.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the errno a rule answers `name` with, if one does.
+    fn refused(&self, name: &OsStr) -> Option<c_int> {
+        match rules::find(&self.config.rules, name) {
+            Some(Action::Errno(errno)) => Some(errno),
+            _ => None,
+        }
+    }
+
+    /// Checks a name given to a new file against the name policy and the rules.
+    fn check_new_name(&self, name: &OsStr) -> Result<(), c_int> {
+        self.config.name_policy.check(name)?;
+        match self.refused(name) {
+            Some(errno) => Err(errno),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates a node in dynamic mode, or hands out the null file in its place otherwise.
+    fn make_node(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        kind: FileType,
+        mode: u32,
+        umask: u32,
+    ) -> Result<FileAttr, c_int> {
+        self.check_new_name(name)?;
+        if !self.config.dynamic {
+            let mut namespace = self.namespace();
+            return match namespace.child(parent, name) {
+                Ok(NULL) if kind == FileType::RegularFile => Ok(namespace.remember(NULL)),
+                _ => Err(EPERM),
+            };
+        }
+        self.check_dir_change(req, parent, None)?;
+        let perm = (mode & !umask & 0o7777) as u16;
+        self.namespace()
+            .create(parent, name, kind, perm, req.uid(), req.gid())
+    }
+
+    /// Lists a directory, starting with . and .., the directory standing in for a parent
+    /// that is gone.
+    fn entries(&self, ino: u64) -> Result<Vec<(OsString, FileAttr)>, c_int> {
+        let namespace = self.namespace();
+        let dir = namespace.get(ino).ok_or(ESTALE)?;
+        let mut entries = vec![
+            (".".into(), dir.attr()),
+            (
+                "..".into(),
+                namespace.attr(dir.parent).unwrap_or_else(|| dir.attr()),
+            ),
+        ];
+        for (name, child) in &dir.entries {
+            if let Some(attr) = namespace.attr(*child) {
+                entries.push((name.clone(), attr));
+            }
+        }
+        Ok(entries)
+    }
+
+    fn open_handle(&mut self, req: &Request, ino: u64) -> u64 {
+        let fh = self.next_handle;
+        self.next_handle += 1;
+        let handle = Handle {
+            ino,
+            caller: Caller::of(req),
+            written: Arc::default(),
+        };
+        self.handles.insert(fh, handle);
+        self.stats.open_handles.fetch_add(1, Ordering::Relaxed);
+        fh
+    }
+
+    fn is_open(&self, fh: u64, ino: u64) -> bool {
+        // Handles start at 1, 0 is what the kernel sends for files it never opened.
+        if fh == 0 && (self.no_open || self.no_opendir) {
+            return true;
+        }
+        self.handles
+            .get(&fh)
+            .is_some_and(|handle| handle.ino == ino)
+    }
+
+    /// The contents kept of `ino` and how, if any are.
+    fn kept(&self, ino: u64) -> Option<(Arc<Contents>, Keep)> {
+        let namespace = self.namespace();
+        let inode = namespace.get(ino)?;
+        keep(&self.config, &inode.name).map(|keep| (inode.contents.clone(), keep))
+    }
+
+    /// Reports whether reads of `ino` never come to an end.
+    fn endless(&self, ino: u64) -> bool {
+        let namespace = self.namespace();
+        namespace
+            .get(ino)
+            .is_some_and(|inode| endless(&self.config, &inode.name))
+    }
+
+    /// The pipe reads of `ino` consume, if it is one.
+    fn pipe(&self, ino: u64) -> Option<Arc<Pipe>> {
+        let namespace = self.namespace();
+        namespace
+            .get(ino)
+            .and_then(|inode| pipe(&self.config, inode))
+    }
+
+    /// `FOPEN_*` flags of a handle on `ino`, reads of endless, echoing and pipe files
+    /// bypassing the page cache, which would stop them at the size or serve earlier writes.
+    fn open_flags(&self, ino: u64) -> u32 {
+        let echoes = matches!(self.kept(ino), Some((_, Keep::Last(_))));
+        match echoes || self.endless(ino) || self.pipe(ino).is_some() {
+            true => self.config.open_flags | FOPEN_DIRECT_IO,
+            false => self.config.open_flags,
+        }
+    }
+
+    /// The pattern `ino` reads as and the size it fills, unless reads go to the source.
+    fn filled(&self, ino: u64) -> Option<(Arc<Pattern>, Arc<AtomicU64>)> {
+        if self.endless(ino) {
+            let pattern = self.config.fill.clone().unwrap_or_default();
+            return Some((pattern, Arc::new(AtomicU64::new(u64::MAX))));
+        }
+        let pattern = self.config.fill.clone()?;
+        let size = self.namespace().get(ino)?.size.clone();
+        Some((pattern, size))
+    }
+
+    fn release_handle(&mut self, fh: u64, ino: u64) -> Option<Handle> {
+        if !self.is_open(fh, ino) {
+            return None;
+        }
+        self.stats.open_handles.fetch_sub(1, Ordering::Relaxed);
+        self.handles.remove(&fh)
+    }
+
+    /// Reports whether the caller of `req` is granted every bit of `mask` on `ino`.
+    fn permits(&self, req: &Request, ino: u64, mask: u32) -> bool {
+        let namespace = self.namespace();
+        let Some(inode) = namespace.get(ino) else {
+            return false;
+        };
+        let acl = inode.xattrs.get(OsStr::new(acl::ACCESS));
+        let entries = acl.and_then(|acl| acl::parse(acl));
+        acl::check(
+            entries.as_deref(),
+            &inode.attr(),
+            req.uid(),
+            &groups(req),
+            mask,
+        )
+    }
+
+    /// Checks, when permissions are left to us, that the caller of `req` may add names to
+    /// `dir` and remove `removed` from it, which a sticky directory only lets root and the
+    /// owners of the directory or of the entry do.
+    fn check_dir_change(
+        &self,
+        req: &Request,
+        dir: u64,
+        removed: Option<&OsStr>,
+    ) -> Result<(), c_int> {
+        if !self.config.check_permissions {
+            return Ok(());
+        }
+        if !self.permits(req, dir, (W_OK | X_OK) as u32) {
+            return Err(EACCES);
+        }
+        let namespace = self.namespace();
+        let (Some(attr), Some(name)) = (namespace.attr(dir), removed) else {
+            return Ok(());
+        };
+        if attr.perm & STICKY == 0 || req.uid() == 0 || req.uid() == attr.uid {
+            return Ok(());
+        }
+        // A missing entry is left for the namespace to report.
+        match namespace.child(dir, name).map(|ino| namespace.attr(ino)) {
+            Ok(Some(entry)) if entry.uid != req.uid() => Err(EPERM),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks, when permissions are left to us, that the caller of `req` may give `ino` the
+    /// mode `mode` and the owner `uid` and `gid`, as chmod(2) and chown(2) allow: only root
+    /// changes the user, and the owner alone changes the mode and the group, into one of
+    /// their own groups.
+    fn check_attr_change(
+        &self,
+        req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), c_int> {
+        if !self.config.check_permissions || req.uid() == 0 {
+            return Ok(());
+        }
+        let Some(attr) = self.namespace().attr(ino) else {
+            return Err(ENOENT);
+        };
+        let owner = req.uid() == attr.uid;
+        let permitted = (mode.is_none() || owner)
+            && uid.is_none_or(|uid| owner && uid == attr.uid)
+            && gid.is_none_or(|gid| owner && (gid == attr.gid || groups(req).contains(&gid)));
+        match permitted {
+            true => Ok(()),
+            false => Err(EPERM),
+        }
+    }
+
+    /// Adds a request to the trace being recorded, if there is one.
+    #[cfg(feature = "trace")]
+    fn record(&self, record: impl FnOnce() -> Record) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(record());
+        }
+    }
+
+    #[cfg(not(feature = "trace"))]
+    fn record(&self, _record: impl FnOnce() -> Record) {}
+
+    /// Whether the trace being recorded keeps what writes carried.
+    #[cfg(feature = "trace")]
+    fn keeps_payload(&self) -> bool {
+        self.recorder.as_ref().is_some_and(Recorder::keeps_payload)
+    }
+
+    #[cfg(not(feature = "trace"))]
+    fn keeps_payload(&self) -> bool {
+        false
+    }
+
+    /// Records `op` by `caller` on `path` in the audit log, if there is one.
+    fn audit(&self, caller: Caller, op: &str, path: impl FnOnce() -> PathBuf, detail: &str) {
+        if let Some(audit) = &self.audit {
+            audit.record(caller, op, &path(), detail);
+        }
+    }
+
+    /// Tears the filesystem down once a shutdown has begun and reports whether
+    /// requests should be refused.
+    fn stopped(&mut self) -> bool {
+        if let Some(crash) = &self.config.crash {
+            crash.check();
+        }
+        if self.shutdown.has_begun() {
+            self.close();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Writes `data` at `offset` of the file `ino` open as `fh` once the rules, callers and
+    /// quotas allow it, keeping the books of everything written.
+    fn write_data(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        reply: ReplyWrite,
+    ) {
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.callers.permits(req.uid(), req.gid()) {
+            reply.error(EACCES);
+            return;
+        }
+
+        let size = match self.size(ino) {
+            Some(size) if self.is_open(fh, ino) => size,
+            _ => {
+                reply.error(EBADF);
+                return;
+            }
+        };
+
+        if interrupted(&self.config, Call::Write) {
+            reply.error(EINTR);
+            return;
+        }
+
+        match rules::find(&self.config.rules, &self.namespace().get(ino).unwrap().name) {
+            Some(Action::Errno(errno)) => {
+                reply.error(errno);
+                return;
+            }
+            Some(Action::Quota(quota)) if offset as u64 + data.len() as u64 > quota => {
+                reply.error(EDQUOT);
+                return;
+            }
+            _ => {}
+        }
+
+        if self.config.metadata {
+            self.namespace().modified(ino);
+        }
+
+        let sink = self.sink.clone();
+        let accounting = self.config.size_accounting;
+        let stats = self.stats.clone();
+        let file_stats = self.namespace().get(ino).unwrap().stats.clone();
+        let contents = self.kept(ino);
+        let pipe = self.pipe(ino);
+        let handle_written = self
+            .handles
+            .get(&fh)
+            .map(|handle| handle.written.clone())
+            .unwrap_or_default();
+        let (uid, pid) = (req.uid(), req.pid());
+        let op = Op::start("write", req, ino).range(offset, data.len() as u64);
+        let data = data.to_vec();
+        self.dispatch(move || match sink.write(ino, offset, &data) {
+            Ok(written) => {
+                op.done(Ok(written as u64));
+                account(&size, accounting, offset as u64, written as u64);
+                file_stats.record_write(&data[..written.min(data.len())]);
+                if let Some((contents, keep)) = contents {
+                    contents.write(keep, offset as u64, &data[..written.min(data.len())]);
+                }
+                if let Some(pipe) = pipe {
+                    pipe.write(&data[..written.min(data.len())]);
+                }
+                handle_written.fetch_add(written as u64, Ordering::Relaxed);
+                stats.record_write(uid, pid, written as u64);
+                reply.written(written as u32);
+            }
+            Err(err) => {
+                let errno = errno(err);
+                op.done(Err(errno));
+                reply.error(errno);
+            }
+        });
+    }
+
+    /// Reads up to `size` bytes of `ino` at `offset` from what is kept of it, the pattern it
+    /// fills with or the source, in that order. Pipes are read apart.
+    fn reader(
+        &self,
+        ino: u64,
+        offset: i64,
+        size: u32,
+    ) -> impl FnOnce() -> io::Result<Vec<u8>> + Send + 'static {
+        let source = self.source.clone();
+        let contents = self.kept(ino);
+        let filled = self.filled(ino);
+        move || {
+            let kept = contents.and_then(|(kept, _)| kept.read(offset as u64, size));
+            let fill = |(pattern, len): (Arc<Pattern>, Arc<AtomicU64>)| {
+                pattern.fill(offset as u64, size, len.load(Ordering::Relaxed))
+            };
+            match kept.or_else(|| filled.map(fill)) {
+                Some(data) => Ok(data),
+                None => source.read(ino, offset, size),
+            }
+        }
+    }
+
+    /// Runs `job` on a worker thread, or right away without any.
+    fn dispatch<F: FnOnce() + Send + 'static>(&self, job: F) {
+        match &self.pool {
+            Some(pool) => pool.execute(job),
+            None => job(),
+        }
+    }
+
+    /// Closes the sink and the source, giving up on them after `CLOSE_TIMEOUT`.
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+
+        if let Err(err) = systemd::notify("STOPPING=1") {
+            log::error!("failed to notify systemd: {}", err);
+        }
+
+        // Dropping the recorder flushes the trace.
+        #[cfg(feature = "trace")]
+        {
+            self.recorder = None;
+        }
+        let pool = self.pool.take();
+        let sink = self.sink.clone();
+        let source = self.source.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // Let the requests in flight finish first.
+            drop(pool);
+            let result = sink.close().and(source.close());
+            let _ = sender.send(result);
+        });
+
+        match receiver.recv_timeout(CLOSE_TIMEOUT) {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::error!("failed to close: {}", err),
+            Err(_) => log::error!("gave up closing after {:?}", CLOSE_TIMEOUT),
+        }
+    }
+}
+
+impl Default for NullFS {
+    fn default() -> NullFS {
+        NullFS::new(Config::default(), Box::new(Discard), Box::new(Empty))
+    }
+}
+
+/// Returns the groups of the caller of `req`, supplementary ones included when procfs has them.
+fn groups(req: &Request) -> Vec<u32> {
+    let mut groups = vec![req.gid()];
+    let status = std::fs::read_to_string(format!("/proc/{}/status", req.pid()));
+    if let Some(line) = status
+        .iter()
+        .flat_map(|status| status.lines())
+        .find(|line| line.starts_with("Groups:"))
+    {
+        groups.extend(
+            line["Groups:".len()..]
+                .split_whitespace()
+                .filter_map(|gid| gid.parse::<u32>().ok()),
+        );
+    }
+    groups
+}
+
+pub fn errno(err: io::Error) -> i32 {
+    err.raw_os_error().unwrap_or(EIO)
+}
+
+/// Extended attributes every regular file reports about its own traffic.
+const STATS_XATTRS: [&str; 3] = [
+    "user.nullfs.bytes_written",
+    "user.nullfs.write_count",
+    "user.nullfs.digest",
+];
+
+/// Returns the value of the statistics attribute `name` of a regular file, if it is one.
+fn stats_xattr(inode: &Inode, name: &OsStr) -> Option<Vec<u8>> {
+    if inode.kind() != FileType::RegularFile {
+        return None;
+    }
+    let stats = &inode.stats;
+    let value = match name.to_str()? {
+        "user.nullfs.bytes_written" => stats.bytes_written.load(Ordering::Relaxed).to_string(),
+        "user.nullfs.write_count" => stats.writes.load(Ordering::Relaxed).to_string(),
+        "user.nullfs.digest" => format!("{:016x}", stats.digest()?),
+        _ => return None,
+    };
+    Some(value.into_bytes())
+}
+
+/// Answers an xattr request with `data`, or with its length when `size` is 0.
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if (size as usize) < data.len() {
+        reply.error(ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
+/// Reports whether reads of the file `name` never come to an end.
+pub fn endless(config: &Config, name: &OsStr) -> bool {
+    config.endless_read || rules::find(&config.rules, name) == Some(Action::Endless)
+}
+
+/// How what is written to the file `name` is kept, if it is.
+pub fn keep(config: &Config, name: &OsStr) -> Option<Keep> {
+    match rules::find(&config.rules, name) {
+        Some(Action::Echo(limit)) => Some(Keep::Last(limit)),
+        Some(Action::Pipe) => None,
+        _ if endless(config, name) => None,
+        _ => config.keep_under.map(Keep::Under),
+    }
+}
+
+/// Whether to fail this `call` with EINTR.
+pub fn interrupted(config: &Config, call: Call) -> bool {
+    let interrupts = config.interrupts.as_ref();
+    interrupts.is_some_and(|interrupts| interrupts.interrupt(call))
+}
+
+/// The pipe reads of the file `inode` consume, if it is one.
+pub fn pipe(config: &Config, inode: &Inode) -> Option<Arc<Pipe>> {
+    (rules::find(&config.rules, &inode.name) == Some(Action::Pipe)).then(|| inode.pipe.clone())
+}
+
+/// Truncates or extends the file `ino` to `size`, the accounted size and the kept contents
+/// along with it, unless truncation is ignored.
+pub fn truncate(
+    config: &Config,
+    namespace: &mut Namespace,
+    ino: u64,
+    size: u64,
+) -> Result<(), c_int> {
+    if config.ignore_truncate {
+        return Ok(());
+    }
+    // Files whose size does not follow writes have nothing to truncate.
+    if config.metadata || config.size_accounting != SizeAccounting::None {
+        let changes = SetAttr {
+            size: Some(size),
+            ..SetAttr::default()
+        };
+        namespace.set_attr(ino, changes)?;
+    }
+    if let Some(inode) = namespace.get(ino) {
+        if let Some(keep) = keep(config, &inode.name) {
+            inode.contents.truncate(keep, size);
+        }
+    }
+    Ok(())
+}
+
+/// Grows the advertised size by a write of `written` bytes at `offset`.
+pub fn account(size: &AtomicU64, accounting: SizeAccounting, offset: u64, written: u64) {
+    match accounting {
+        SizeAccounting::None => {}
+        SizeAccounting::HighWatermark => {
+            size.fetch_max(offset.saturating_add(written), Ordering::Relaxed);
+        }
+        SizeAccounting::Cumulative => {
+            size.fetch_add(written, Ordering::Relaxed);
+        }
+    }
+}
+
+fn system_time(time: TimeOrNow) -> SystemTime {
+    match time {
+        TimeOrNow::SpecificTime(time) => time,
+        TimeOrNow::Now => SystemTime::now(),
+    }
+}
+
+impl Filesystem for NullFS {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // Started here so that the workers share the signal mask of the session thread.
+        if self.config.threads > 0 {
+            self.pool = Some(Pool::new(self.config.threads));
+        }
+
+        #[cfg(feature = "trace")]
+        if let Some(path) = &self.config.record {
+            match Recorder::create(path, self.config.record_payload) {
+                Ok(recorder) => self.recorder = Some(recorder),
+                Err(err) => {
+                    log::error!("failed to start the trace {}: {}", path.display(), err);
+                    return Err(errno(err));
+                }
+            }
+        }
+        #[cfg(not(feature = "trace"))]
+        if let Some(path) = &self.config.record {
+            log::error!(
+                "cannot record the trace {}: built without the trace feature",
+                path.display()
+            );
+            return Err(ENOSYS);
+        }
+
+        if let Some(path) = &self.config.audit {
+            match Audit::open(path) {
+                Ok(audit) => self.audit = Some(audit),
+                Err(err) => {
+                    log::error!("failed to open the audit log {}: {}", path.display(), err);
+                    return Err(errno(err));
+                }
+            }
+        }
+
+        if let Some(max_write) = self.config.max_write {
+            if let Err(nearest) = config.set_max_write(max_write) {
+                log::warn!("max_write {} unsupported, using {}", max_write, nearest);
+                let _ = config.set_max_write(nearest);
+            }
+        }
+
+        // The kernel default of 12 leaves parallel writers waiting on many CPUs.
+        let max_background = self.config.max_background.unwrap_or_else(|| {
+            let cpus = thread::available_parallelism().map_or(1, usize::from);
+            cpus.saturating_mul(4).clamp(12, u16::MAX as usize) as u16
+        });
+        let _ = config.set_max_background(max_background);
+        if let Some(threshold) = self.config.congestion_threshold {
+            if threshold > max_background {
+                log::warn!(
+                    "congestion threshold {} above max_background, using {}",
+                    threshold,
+                    max_background
+                );
+            }
+            let _ = config.set_congestion_threshold(threshold);
+        }
+
+        if self.config.writeback_cache && config.add_capabilities(FUSE_WRITEBACK_CACHE).is_err() {
+            log::warn!("the kernel does not support writeback caching");
+        }
+        // AUTO lets the kernel fall back to plain readdir when nobody stats the entries.
+        let _ = config.add_capabilities(FUSE_DO_READDIRPLUS | FUSE_READDIRPLUS_AUTO);
+        let _ = config.add_capabilities(FUSE_POSIX_LOCKS | FUSE_FLOCK_LOCKS);
+        // Lets file handles outlive the dentry cache, as NFS re-exports need.
+        let _ = config.add_capabilities(FUSE_EXPORT_SUPPORT);
+        // Symlinks never change their target, so the kernel may keep what it read.
+        let _ = config.add_capabilities(FUSE_CACHE_SYMLINKS);
+
+        if self.config.no_open {
+            // Opening is where modes are checked unless the kernel checks them itself.
+            if self.config.check_permissions {
+                log::warn!("--no-open needs -o default_permissions, opening files as usual");
+            } else if config.add_capabilities(FUSE_NO_OPEN_SUPPORT).is_err() {
+                log::warn!("the kernel does not support skipping open");
+            } else {
+                self.no_open = true;
+                #[cfg(feature = "abi-7-29")]
+                {
+                    self.no_opendir = config.add_capabilities(FUSE_NO_OPENDIR_SUPPORT).is_ok();
+                }
+            }
+        }
+
+        if let Err(err) = systemd::notify("READY=1") {
+            log::error!("failed to notify systemd: {}", err);
+        }
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        self.close();
+    }
+
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if let Some(errno) = self.refused(name) {
+            reply.error(errno);
+            return;
+        }
+
+        let mut namespace = self.namespace();
+        match namespace.lookup(parent, name) {
+            Ok(attr) => reply.entry(&self.config.entry_ttl, &attr, namespace.generation()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        match self.namespace().attr(ino) {
+            Some(attr) => reply.attr(&self.config.attr_ttl, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if let Some(size) = size {
+            self.record(|| {
+                Record::new(Operation::Truncate, self.namespace().path(ino)).range(0, size)
+            });
+        }
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if let Err(err) = self.check_attr_change(req, ino, mode, uid, gid) {
+            reply.error(err);
+            return;
+        }
+
+        if let Some(size) = size {
+            let truncated = truncate(&self.config, &mut self.namespace(), ino, size);
+            if let Err(err) = truncated {
+                reply.error(err);
+                return;
+            }
+        }
+
+        // Modes and owners always stay for permissions to be checked against, times only
+        // with the rest of the metadata.
+        let (atime, mtime) = match self.config.metadata {
+            true => (atime, mtime),
+            false => (None, None),
+        };
+        let changes = SetAttr {
+            perm: mode.map(|mode| mode as u16),
+            uid,
+            gid,
+            size: None,
+            atime: atime.map(system_time),
+            mtime: mtime.map(system_time),
+        };
+        let set = self.namespace().set_attr(ino, changes);
+        if let Err(err) = set {
+            reply.error(err);
+            return;
+        }
+
+        // Only macOS sets these, Finder doing so on every copy.
+        match self
+            .namespace()
+            .set_xtimes(ino, crtime, chgtime, bkuptime, flags)
+        {
+            Ok(attr) => reply.attr(&self.config.attr_ttl, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&mut self, _req: &Request, ino: u64, reply: ReplyXTimes) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        match self.namespace().get(ino) {
+            Some(inode) => reply.xtimes(inode.bkuptime, inode.attr().crtime),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| Record::on_handle(Operation::Read, fh).range(offset, size as u64));
+
+        if !self.is_file(ino) || !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+
+        if interrupted(&self.config, Call::Read) {
+            reply.error(EINTR);
+            return;
+        }
+
+        let read = self.reader(ino, offset, size);
+        let stats = self.stats.clone();
+        let (uid, pid) = (req.uid(), req.pid());
+        let op = Op::start("read", req, ino).range(offset, size as u64);
+        let answer = move |result: io::Result<Vec<u8>>| match result {
+            Ok(data) => {
+                op.done(Ok(data.len() as u64));
+                stats.record_read(uid, pid, data.len() as u64);
+                reply.data(&data);
+            }
+            Err(err) => {
+                let errno = errno(err);
+                op.done(Err(errno));
+                reply.error(errno);
+            }
+        };
+        // Reads of a pipe are answered by the write bringing them data, if need be.
+        match self.pipe(ino) {
+            Some(pipe) => pipe.read(size, move |data| answer(Ok(data))),
+            None => self.dispatch(move || answer(read())),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_dir(ino) || !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+
+        let entries = match self.entries(ino) {
+            Ok(entries) => entries,
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+        for (i, (name, attr)) in entries.into_iter().enumerate().skip(offset as usize) {
+            // i + 1 means the index of the next entry
+            if reply.add(attr.ino, (i + 1) as i64, attr.kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn readdirplus(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectoryPlus,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_dir(ino) || !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+
+        let entries = match self.entries(ino) {
+            Ok(entries) => entries,
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+        for (i, (name, attr)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(
+                attr.ino,
+                (i + 1) as i64,
+                &name,
+                &self.config.entry_ttl,
+                &attr,
+                self.namespace().generation(),
+            ) {
+                break;
+            }
+            // Every entry but . and .. counts as a lookup.
+            if name != "." && name != ".." {
+                self.namespace().remember(attr.ino);
+            }
+        }
+        reply.ok();
+    }
+
+    fn write(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| {
+            let record = Record::on_handle(Operation::Write, fh).range(offset, data.len() as u64);
+            match self.keeps_payload() {
+                true => record.data(data.to_vec()),
+                false => record,
+            }
+        });
+
+        self.write_data(req, ino, fh, offset, data, reply);
+    }
+
+    fn create(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.callers.permits(req.uid(), req.gid()) {
+            reply.error(EACCES);
+            return;
+        }
+
+        match self.make_node(req, parent, name, FileType::RegularFile, mode, umask) {
+            Ok(attr) => {
+                let fh = self.open_handle(req, attr.ino);
+                self.record(|| {
+                    Record::new(Operation::Create, self.namespace().path(attr.ino))
+                        .handle(fh)
+                        .mode(mode)
+                });
+                let detail = format!(" mode={:#o}", attr.perm);
+                self.audit(
+                    Caller::of(req),
+                    "create",
+                    || self.namespace().path(attr.ino),
+                    &detail,
+                );
+                let flags = self.open_flags(attr.ino);
+                reply.created(
+                    &self.config.entry_ttl,
+                    &attr,
+                    self.namespace().generation(),
+                    fh,
+                    flags,
+                );
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn mknod(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        // Besides null files, only another /dev/null can be made.
+        let kind = match mode as libc::mode_t & S_IFMT {
+            S_IFREG => FileType::RegularFile,
+            S_IFCHR if rdev == NULL_RDEV => FileType::CharDevice,
+            _ => {
+                reply.error(EPERM);
+                return;
+            }
+        };
+
+        match self.make_node(req, parent, name, kind, mode, umask) {
+            Ok(attr) => reply.entry(&self.config.entry_ttl, &attr, self.namespace().generation()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| {
+            Record::new(Operation::Mkdir, self.namespace().path(parent).join(name)).mode(mode)
+        });
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        match self.make_node(req, parent, name, FileType::Directory, mode, umask) {
+            Ok(attr) => reply.entry(&self.config.entry_ttl, &attr, self.namespace().generation()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn symlink(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        link: &Path,
+        reply: ReplyEntry,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| {
+            Record::new(Operation::Symlink, self.namespace().path(parent).join(name))
+                .target(link.to_path_buf())
+        });
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.dynamic {
+            reply.error(EPERM);
+            return;
+        }
+
+        if let Err(errno) = self.check_new_name(name) {
+            reply.error(errno);
+            return;
+        }
+
+        if let Err(err) = self.check_dir_change(req, parent, None) {
+            reply.error(err);
+            return;
+        }
+
+        let mut namespace = self.namespace();
+        match namespace.symlink(parent, name, link.as_os_str(), req.uid(), req.gid()) {
+            Ok(attr) => reply.entry(&self.config.entry_ttl, &attr, namespace.generation()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        match self.namespace().get(ino) {
+            Some(inode) if inode.kind() == FileType::Symlink => reply.data(inode.target.as_bytes()),
+            Some(_) => reply.error(EINVAL),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn link(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| {
+            Record::new(Operation::Link, self.namespace().path(ino))
+                .target(self.namespace().path(newparent).join(newname))
+        });
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.dynamic {
+            reply.error(EPERM);
+            return;
+        }
+
+        if let Err(errno) = self.check_new_name(newname) {
+            reply.error(errno);
+            return;
+        }
+
+        if let Err(err) = self.check_dir_change(req, newparent, None) {
+            reply.error(err);
+            return;
+        }
+
+        let mut namespace = self.namespace();
+        match namespace.link(ino, newparent, newname) {
+            Ok(attr) => reply.entry(&self.config.entry_ttl, &attr, namespace.generation()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| Record::new(Operation::Unlink, self.namespace().path(parent).join(name)));
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.dynamic {
+            reply.error(EPERM);
+            return;
+        }
+
+        if let Err(err) = self.check_dir_change(req, parent, Some(name)) {
+            reply.error(err);
+            return;
+        }
+
+        let path = self.namespace().path(parent).join(name);
+        match self.namespace().unlink(parent, name) {
+            Ok(()) => {
+                self.audit(Caller::of(req), "unlink", || path, "");
+                reply.ok();
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| Record::new(Operation::Rmdir, self.namespace().path(parent).join(name)));
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.dynamic {
+            reply.error(EPERM);
+            return;
+        }
+
+        if let Err(err) = self.check_dir_change(req, parent, Some(name)) {
+            reply.error(err);
+            return;
+        }
+
+        match self.namespace().rmdir(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| {
+            Record::new(Operation::Rename, self.namespace().path(parent).join(name))
+                .target(self.namespace().path(newparent).join(newname))
+                .mode(flags)
+        });
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if !self.config.dynamic {
+            reply.error(EPERM);
+            return;
+        }
+
+        if let Err(errno) = self.check_new_name(newname) {
+            reply.error(errno);
+            return;
+        }
+
+        let checked = self.check_dir_change(req, parent, Some(name));
+        if let Err(err) =
+            checked.and_then(|()| self.check_dir_change(req, newparent, Some(newname)))
+        {
+            reply.error(err);
+            return;
+        }
+
+        match self
+            .namespace()
+            .rename(parent, name, newparent, newname, flags)
+        {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        self.namespace().forget(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, _req: &Request, nodes: &[fuse_forget_one]) {
+        for node in nodes {
+            self.namespace().forget(node.nodeid, node.nlookup);
+        }
+    }
+
+    fn flush(&mut self, req: &Request, ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| Record::on_handle(Operation::Flush, fh));
+
+        match ino {
+            _ if !self.is_file(ino) || !self.is_open(fh, ino) => reply.error(EBADF),
+            _ => {
+                let sink = self.sink.clone();
+                let op = Op::start("flush", req, ino);
+                self.dispatch(move || match sink.flush(ino) {
+                    Ok(()) => {
+                        op.done(Ok(0));
+                        reply.ok();
+                    }
+                    Err(err) => {
+                        let errno = errno(err);
+                        op.done(Err(errno));
+                        reply.error(errno);
+                    }
+                });
+            }
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| Record::on_handle(Operation::Release, fh));
+
+        if let Some(owner) = lock_owner {
+            self.locks.release(ino, owner);
+        }
+
+        if !self.is_file(ino) {
+            reply.error(EBADF);
+            return;
+        }
+        match self.release_handle(fh, ino) {
+            Some(handle) => {
+                let written = handle.written.load(Ordering::Relaxed);
+                if written > 0 {
+                    let detail = format!(" bytes={}", written);
+                    self.audit(
+                        handle.caller,
+                        "write",
+                        || self.namespace().path(ino),
+                        &detail,
+                    );
+                }
+                reply.ok();
+            }
+            None => reply.error(EBADF),
+        }
+    }
+
+    fn fsync(&mut self, req: &Request, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| Record::on_handle(Operation::Fsync, fh));
+
+        match ino {
+            _ if !self.is_file(ino) || !self.is_open(fh, ino) => reply.error(EBADF),
+            _ => {
+                let sink = self.sink.clone();
+                let delay = self.config.fsync_delay.clone();
+                let crash = self.config.crash.clone();
+                let op = Op::start("fsync", req, ino);
+                self.dispatch(move || {
+                    if let Some(delay) = delay {
+                        delay.wait();
+                    }
+                    match sink.flush(ino) {
+                        Ok(()) => {
+                            op.done(Ok(0));
+                            reply.ok();
+                            if let Some(crash) = crash {
+                                crash.fsynced();
+                            }
+                        }
+                        Err(err) => {
+                            let errno = errno(err);
+                            op.done(Err(errno));
+                            reply.error(errno);
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.config.callers.permits(req.uid(), req.gid()) {
+            reply.error(EACCES);
+            return;
+        }
+
+        // Tells the kernel to stop sending opens for good.
+        if self.no_open {
+            reply.error(ENOSYS);
+            return;
+        }
+
+        if interrupted(&self.config, Call::Open) {
+            reply.error(EINTR);
+            return;
+        }
+
+        let mask = match flags & O_ACCMODE {
+            O_RDONLY => R_OK,
+            O_WRONLY => W_OK,
+            _ => R_OK | W_OK,
+        } | if flags & O_TRUNC != 0 { W_OK } else { 0 };
+
+        let kind = self.namespace().get(ino).map(|inode| inode.kind());
+        match kind {
+            Some(FileType::RegularFile)
+                if self.config.check_permissions && !self.permits(req, ino, mask as u32) =>
+            {
+                reply.error(EACCES)
+            }
+            Some(FileType::RegularFile) => {
+                // Only sent with the open when the kernel leaves truncation to it.
+                if flags & O_TRUNC != 0 && !self.config.read_only {
+                    let truncated = truncate(&self.config, &mut self.namespace(), ino, 0);
+                    if let Err(err) = truncated {
+                        reply.error(err);
+                        return;
+                    }
+                }
+                let fh = self.open_handle(req, ino);
+                self.record(|| {
+                    Record::new(Operation::Open, self.namespace().path(ino))
+                        .handle(fh)
+                        .mode(flags as u32)
+                });
+                let detail = format!(" flags={:#o}", flags);
+                self.audit(
+                    Caller::of(req),
+                    "open",
+                    || self.namespace().path(ino),
+                    &detail,
+                );
+                reply.opened(fh, self.open_flags(ino));
+            }
+            Some(_) => reply.error(EPERM),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn releasedir(&mut self, _req: &Request, ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if self.is_dir(ino) && self.release_handle(fh, ino).is_some() {
+            reply.ok();
+        } else {
+            reply.error(EBADF);
+        }
+    }
+
+    fn fsyncdir(&mut self, _req: &Request, ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_dir(ino) || !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+        let delay = self.config.fsync_delay.clone();
+        let crash = self.config.crash.clone();
+        self.dispatch(move || {
+            if let Some(delay) = delay {
+                delay.wait();
+            }
+            reply.ok();
+            if let Some(crash) = crash {
+                crash.fsynced();
+            }
+        });
+    }
+
+    fn opendir(&mut self, req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if self.no_opendir {
+            reply.error(ENOSYS);
+            return;
+        }
+
+        let kind = self.namespace().get(ino).map(|inode| inode.kind());
+        match kind {
+            Some(FileType::Directory) => {
+                let fh = self.open_handle(req, ino);
+                reply.opened(fh, 0);
+            }
+            Some(_) => reply.error(ENOTDIR),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: ReplyLock,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+
+        let lock = Lock {
+            ino,
+            owner: lock_owner,
+            start,
+            end,
+            typ,
+            pid,
+        };
+        match self.locks.get(&lock) {
+            Some(held) => reply.locked(held.start, held.end, held.typ, held.pid),
+            None => reply.locked(start, end, F_UNLCK as c_int, 0),
+        }
+    }
+
+    fn setlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+
+        let lock = Lock {
+            ino,
+            owner: lock_owner,
+            start,
+            end,
+            typ,
+            pid,
+        };
+        // Waiting replies are held by the table, the request loop moves on.
+        self.locks.set(lock, sleep, reply);
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        self.record(|| {
+            Record::on_handle(Operation::Fallocate, fh)
+                .range(offset, length as u64)
+                .mode(mode as u32)
+        });
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        let size = match self.size(ino) {
+            Some(size) if self.is_open(fh, ino) => size,
+            _ => {
+                reply.error(EBADF);
+                return;
+            }
+        };
+
+        if offset < 0 || length <= 0 {
+            reply.error(EINVAL);
+            return;
+        }
+        let end = match offset.checked_add(length) {
+            Some(end) => end as u64,
+            None => {
+                reply.error(EFBIG);
+                return;
+            }
+        };
+
+        // Nothing is stored, so there is nothing to allocate, punch or zero either.
+        match mode & !FALLOC_FL_KEEP_SIZE {
+            0 | FALLOC_FL_ZERO_RANGE => {}
+            FALLOC_FL_PUNCH_HOLE if mode & FALLOC_FL_KEEP_SIZE != 0 => {}
+            _ => {
+                reply.error(EOPNOTSUPP);
+                return;
+            }
+        }
+
+        let name = self.namespace().get(ino).unwrap().name.clone();
+        match rules::find(&self.config.rules, &name) {
+            Some(Action::Quota(quota)) if mode & FALLOC_FL_PUNCH_HOLE == 0 && end > quota => {
+                reply.error(EDQUOT);
+                return;
+            }
+            _ => {}
+        }
+
+        if mode & FALLOC_FL_KEEP_SIZE == 0 && self.config.size_accounting != SizeAccounting::None {
+            size.fetch_max(end, Ordering::Relaxed);
+        }
+        reply.ok();
+    }
+
+    fn lseek(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        let attr = self.namespace().attr(ino);
+        let size = match attr {
+            Some(attr) if attr.kind == FileType::RegularFile && self.is_open(fh, ino) => attr.size,
+            _ => {
+                reply.error(EBADF);
+                return;
+            }
+        };
+
+        // Files that read back what was written or a pattern hold data up to their real
+        // size, endless ones and pipes up to the reported one. Everything else is a hole,
+        // and the kernel handles every other whence itself.
+        let kept = self
+            .kept(ino)
+            .is_some_and(|(contents, _)| contents.is_kept());
+        let data = if self.endless(ino) || self.pipe(ino).is_some() {
+            size
+        } else if kept || self.config.fill.is_some() {
+            let real = self
+                .size(ino)
+                .map_or(0, |real| real.load(Ordering::Relaxed));
+            real.min(size)
+        } else {
+            0
+        };
+        match whence {
+            _ if offset < 0 => reply.error(EINVAL),
+            _ if offset as u64 >= size => reply.error(ENXIO),
+            SEEK_DATA if (offset as u64) < data => reply.offset(offset),
+            SEEK_DATA => reply.error(ENXIO),
+            SEEK_HOLE if (offset as u64) < data => reply.offset(data as i64),
+            SEEK_HOLE => reply.offset(offset),
+            _ => reply.error(EINVAL),
+        }
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &Request,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_file(ino_in) || !self.is_open(fh_in, ino_in) {
+            reply.error(EBADF);
+            return;
+        }
+
+        if offset_in < 0 || offset_out < 0 {
+            reply.error(EINVAL);
+            return;
+        }
+
+        // Copies what the source file reads as, a chunk at a time, as a write of it would.
+        let size = len.min(COPY_CHUNK as u64) as u32;
+        let data = match self.pipe(ino_in) {
+            Some(pipe) => Ok(pipe.take(size)),
+            None => self.reader(ino_in, offset_in, size)(),
+        };
+        match data {
+            Ok(data) => self.write_data(req, ino_out, fh_out, offset_out, &data, reply),
+            Err(err) => reply.error(errno(err)),
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: u32,
+        cmd: u32,
+        _in_data: &[u8],
+        out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.is_open(fh, ino) {
+            reply.error(EBADF);
+            return;
+        }
+
+        if !self.is_file(ino) {
+            reply.error(ENOTTY);
+            return;
+        }
+
+        match cmd {
+            ioctl::GET_BYTES_WRITTEN => {
+                let written = self.stats.bytes_written.load(Ordering::Relaxed);
+                reply.ioctl(0, &written.to_ne_bytes());
+            }
+            ioctl::RESET_STATS => {
+                self.stats.reset();
+                reply.ioctl(0, &[]);
+            }
+            ioctl::GET_CONFIG => {
+                let mut config = self.config.to_json().into_bytes();
+                config.push(0);
+                if config.len() > ioctl::CONFIG_SIZE.min(out_size as usize) {
+                    reply.error(ERANGE);
+                } else {
+                    reply.ioctl(0, &config);
+                }
+            }
+            _ => reply.error(ENOTTY),
+        }
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if !self.config.callers.permits(req.uid(), req.gid()) {
+            reply.error(EACCES);
+            return;
+        }
+
+        // Only called without default_permissions, when the kernel leaves permissions to us.
+        if self.namespace().get(ino).is_none() {
+            reply.error(ENOENT);
+        } else if !self.permits(req, ino, mask as u32) {
+            reply.error(EACCES);
+        } else {
+            reply.ok();
+        }
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if self.is_file(ino) && STATS_XATTRS.iter().any(|stat| name == *stat) {
+            reply.error(EPERM);
+            return;
+        }
+
+        match self.namespace().setxattr(ino, name, value, flags) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        match self.namespace().get(ino) {
+            Some(inode) => {
+                match stats_xattr(inode, name).or_else(|| inode.xattrs.get(name).cloned()) {
+                    Some(value) => reply_xattr(reply, size, &value),
+                    None => reply.error(ENODATA),
+                }
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        match self.namespace().get(ino) {
+            Some(inode) => {
+                let mut names = Vec::new();
+                if inode.kind() == FileType::RegularFile {
+                    for name in STATS_XATTRS {
+                        if stats_xattr(inode, OsStr::new(name)).is_none() {
+                            continue;
+                        }
+                        names.extend_from_slice(name.as_bytes());
+                        names.push(0);
+                    }
+                }
+                for name in inode.xattrs.keys() {
+                    names.extend_from_slice(name.as_bytes());
+                    names.push(0);
+                }
+                reply_xattr(reply, size, &names);
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn removexattr(&mut self, _req: &Request, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        if self.stopped() {
+            reply.error(EIO);
+            return;
+        }
+
+        if self.config.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        if self.is_file(ino) && STATS_XATTRS.iter().any(|stat| name == *stat) {
+            reply.error(EPERM);
+            return;
+        }
+
+        match self.namespace().removexattr(ino, name) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use crate::delay::splitmix;
+
+/// Calls that can be interrupted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Call {
+    Open,
+    Read,
+    Write,
+}
+
+impl Call {
+    /// Parses `open`, `read` or `write`.
+    pub fn parse(call: &str) -> Result<Call, String> {
+        match call {
+            "open" => Ok(Call::Open),
+            "read" => Ok(Call::Read),
+            "write" => Ok(Call::Write),
+            _ => Err(format!("unknown call '{}'", call)),
+        }
+    }
+
+    /// The name [`Call::parse`] takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            Call::Open => "open",
+            Call::Read => "read",
+            Call::Write => "write",
+        }
+    }
+}
+
+/// Fails a fraction of calls with EINTR, as a signal arriving at the wrong time would, to
+/// exercise the retry loops of applications.
+#[derive(Debug)]
+pub struct Interrupts {
+    fraction: f64,
+    threshold: u64,
+    calls: Vec<Call>,
+    seed: u64,
+    count: AtomicU64,
+}
+
+impl Interrupts {
+    /// Interrupts about `fraction` of the `calls`, from 0 for none to 1 for all of them.
+    pub fn new(fraction: f64, calls: Vec<Call>) -> Interrupts {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let fraction = fraction.clamp(0.0, 1.0);
+        Interrupts {
+            fraction,
+            threshold: (fraction * u64::MAX as f64) as u64,
+            calls,
+            seed,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// Describes which calls are interrupted and how often as JSON.
+    pub fn to_json(&self) -> String {
+        let calls: Vec<String> = self
+            .calls
+            .iter()
+            .map(|call| format!("\"{}\"", call.name()))
+            .collect();
+        format!(
+            "{{\"fraction\":{},\"calls\":[{}]}}",
+            self.fraction,
+            calls.join(",")
+        )
+    }
+
+    /// Whether to interrupt this `call`.
+    pub fn interrupt(&self, call: Call) -> bool {
+        if self.threshold == 0 || !self.calls.contains(&call) {
+            return false;
+        }
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        splitmix(self.seed ^ count) < self.threshold || self.threshold == u64::MAX
+    }
+}
+
+/// Parses a fraction such as `0.1` or `10%`.
+pub fn parse_fraction(fraction: &str) -> Result<f64, String> {
+    let parsed = match fraction.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().map(|percent| percent / 100.0),
+        None => fraction.parse::<f64>(),
+    };
+    parsed
+        .ok()
+        .filter(|fraction| (0.0..=1.0).contains(fraction))
+        .ok_or_else(|| format!("invalid fraction '{}'", fraction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fractions() {
+        assert_eq!(parse_fraction("0.25"), Ok(0.25));
+        assert_eq!(parse_fraction("10%"), Ok(0.1));
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+        assert!(parse_fraction("1.5").is_err());
+        assert!(parse_fraction("-1%").is_err());
+        assert!(parse_fraction("half").is_err());
+    }
+}
@@ -0,0 +1,19 @@
+const fn ior(nr: u32, size: u32) -> u32 {
+    (2 << 30) | (size << 16) | ((b'N' as u32) << 8) | nr
+}
+
+const fn io(nr: u32) -> u32 {
+    ((b'N' as u32) << 8) | nr
+}
+
+/// Reads, through `ioctl(2)` on the open null file, the number of bytes written so far as a native endian `u64`.
+pub const GET_BYTES_WRITTEN: u32 = ior(1, 8);
+
+/// Zeroes the traffic counters.
+pub const RESET_STATS: u32 = io(2);
+
+/// Reads the configuration of the mount as NUL terminated JSON, failing with ERANGE when it
+/// takes more than `CONFIG_SIZE` bytes.
+pub const GET_CONFIG: u32 = ior(3, CONFIG_SIZE as u32);
+
+pub const CONFIG_SIZE: usize = 4096;
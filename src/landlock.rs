@@ -0,0 +1,137 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+
+// The same on every architecture, and missing from the libc crate.
+const SYS_CREATE_RULESET: libc::c_long = 444;
+const SYS_ADD_RULE: libc::c_long = 445;
+const SYS_RESTRICT_SELF: libc::c_long = 446;
+
+const CREATE_RULESET_VERSION: u32 = 1;
+const RULE_PATH_BENEATH: u32 = 1;
+
+const EXECUTE: u64 = 1 << 0;
+const WRITE_FILE: u64 = 1 << 1;
+const READ_FILE: u64 = 1 << 2;
+const READ_DIR: u64 = 1 << 3;
+const REMOVE_FILE: u64 = 1 << 5;
+const MAKE_REG: u64 = 1 << 8;
+const MAKE_SOCK: u64 = 1 << 9;
+const TRUNCATE: u64 = 1 << 14;
+
+/// Rights that may be granted on a file rather than a directory.
+const FILE_ACCESS: u64 = EXECUTE | WRITE_FILE | READ_FILE | TRUNCATE;
+
+/// What the process may still do beneath a path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Read files and list directories.
+    Read,
+    /// Read, create, overwrite and remove files and sockets.
+    Write,
+}
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Denies the calling thread, and the threads it spawns from now on, any filesystem access
+/// but `access` beneath each of `paths`, files opened already staying usable. Returns the
+/// Landlock ABI enforced, or `None` when the kernel does not support Landlock.
+pub fn restrict(paths: &[(&Path, Access)]) -> io::Result<Option<u32>> {
+    let abi = unsafe {
+        libc::syscall(
+            SYS_CREATE_RULESET,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    if abi < 1 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    // Every right this version knows of is handled, so anything not granted is denied.
+    let handled = match abi {
+        1 => (1 << 13) - 1,
+        2 => (1 << 14) - 1,
+        _ => (1 << 15) - 1,
+    };
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            SYS_CREATE_RULESET,
+            &attr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = unsafe { File::from_raw_fd(fd as i32) };
+
+    for &(path, access) in paths {
+        let name = CString::new(path.as_os_str().as_bytes())?;
+        let parent = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if parent < 0 {
+            let err = io::Error::last_os_error();
+            // Nothing is there to be granted.
+            if err.kind() == io::ErrorKind::NotFound {
+                continue;
+            }
+            return Err(err);
+        }
+        let parent = unsafe { File::from_raw_fd(parent) };
+        let mut allowed = match access {
+            Access::Read => READ_FILE | READ_DIR,
+            Access::Write => {
+                READ_FILE | READ_DIR | WRITE_FILE | MAKE_REG | MAKE_SOCK | REMOVE_FILE | TRUNCATE
+            }
+        } & handled;
+        if !path.is_dir() {
+            allowed &= FILE_ACCESS;
+        }
+        let rule = PathBeneathAttr {
+            allowed_access: allowed,
+            parent_fd: parent.as_raw_fd(),
+        };
+        let added = unsafe {
+            libc::syscall(
+                SYS_ADD_RULE,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &rule,
+                0,
+            )
+        };
+        if added != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == -1
+            || libc::syscall(SYS_RESTRICT_SELF, ruleset.as_raw_fd(), 0) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(Some(abi as u32))
+}
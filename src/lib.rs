@@ -0,0 +1,63 @@
+mod acl;
+mod audit;
+#[cfg(feature = "cli")]
+pub mod bench;
+pub mod callers;
+#[cfg(all(feature = "cli", target_os = "linux"))]
+pub mod capabilities;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod config;
+mod contents;
+#[cfg(feature = "cli")]
+pub mod control;
+pub mod crash;
+#[cfg(feature = "cli")]
+pub mod daemon;
+pub mod delay;
+mod fs;
+pub mod interrupt;
+pub mod ioctl;
+#[cfg(all(feature = "cli", target_os = "linux"))]
+pub mod landlock;
+mod locks;
+pub mod logger;
+pub mod names;
+mod namespace;
+#[cfg(feature = "servers")]
+pub mod nfs;
+#[cfg(feature = "servers")]
+pub mod ninep;
+pub mod options;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod pattern;
+mod pipe;
+mod pool;
+#[cfg(feature = "preload")]
+pub mod preload;
+#[cfg(feature = "cli")]
+pub mod privileges;
+#[cfg(all(feature = "cli", feature = "trace"))]
+pub mod replay;
+pub mod rules;
+#[cfg(all(feature = "cli", target_os = "linux"))]
+pub mod seccomp;
+pub mod shutdown;
+#[cfg(feature = "cli")]
+pub mod signal;
+pub mod sink;
+pub mod stats;
+pub mod systemd;
+pub mod trace;
+#[cfg(feature = "cli")]
+pub mod unit;
+#[cfg(feature = "cli")]
+pub mod unmount;
+
+pub use config::{Attrs, Config, SizeAccounting};
+pub use fs::NullFS;
+pub use namespace::Tree;
+pub use shutdown::Shutdown;
+pub use sink::{DataSink, DataSource, Registry};
+pub use stats::Stats;
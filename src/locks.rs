@@ -0,0 +1,119 @@
+use fuser::ReplyEmpty;
+use libc::{c_int, EAGAIN, F_UNLCK, F_WRLCK};
+
+/// Advisory locks held on byte ranges of the files, and the requests waiting for one.
+///
+/// BSD locks arrive as locks on the whole file, owned by the open file instead of the process.
+#[derive(Default)]
+pub struct LockTable {
+    locks: Vec<Lock>,
+    waiting: Vec<(Lock, ReplyEmpty)>,
+}
+
+/// A lock on the bytes from `start` to `end`, both included.
+#[derive(Clone, Copy, Debug)]
+pub struct Lock {
+    pub ino: u64,
+    pub owner: u64,
+    pub start: u64,
+    pub end: u64,
+    pub typ: c_int,
+    pub pid: u32,
+}
+
+impl Lock {
+    fn overlaps(&self, other: &Lock) -> bool {
+        self.ino == other.ino && self.start <= other.end && other.start <= self.end
+    }
+
+    fn conflicts(&self, other: &Lock) -> bool {
+        self.overlaps(other)
+            && self.owner != other.owner
+            && (self.typ == F_WRLCK as c_int || other.typ == F_WRLCK as c_int)
+    }
+}
+
+impl LockTable {
+    /// Returns a lock that keeps `lock` from being taken, if any.
+    pub fn get(&self, lock: &Lock) -> Option<Lock> {
+        self.locks.iter().find(|held| held.conflicts(lock)).copied()
+    }
+
+    /// Takes, changes or, with `F_UNLCK`, drops a lock.
+    ///
+    /// A conflicting lock fails the request with EAGAIN, unless `wait` is set, in which
+    /// case the reply is held back until the lock can be granted.
+    pub fn set(&mut self, lock: Lock, wait: bool, reply: ReplyEmpty) {
+        if lock.typ == F_UNLCK as c_int {
+            self.remove(&lock);
+            reply.ok();
+            self.wake();
+            return;
+        }
+
+        if self.get(&lock).is_some() {
+            if wait {
+                self.waiting.push((lock, reply));
+            } else {
+                reply.error(EAGAIN);
+            }
+            return;
+        }
+
+        self.grant(lock);
+        reply.ok();
+        // A downgrade may let readers in.
+        self.wake();
+    }
+
+    /// Drops every lock `owner` holds on `ino` and grants what was waiting for them.
+    pub fn release(&mut self, ino: u64, owner: u64) {
+        self.locks
+            .retain(|lock| lock.ino != ino || lock.owner != owner);
+        self.wake();
+    }
+
+    fn grant(&mut self, lock: Lock) {
+        self.remove(&lock);
+        self.locks.push(lock);
+    }
+
+    /// Cuts the range of `lock` out of the locks its owner holds.
+    fn remove(&mut self, lock: &Lock) {
+        let mut kept = Vec::with_capacity(self.locks.len());
+        for held in self.locks.drain(..) {
+            if held.owner != lock.owner || !held.overlaps(lock) {
+                kept.push(held);
+                continue;
+            }
+            if held.start < lock.start {
+                kept.push(Lock {
+                    end: lock.start - 1,
+                    ..held
+                });
+            }
+            if held.end > lock.end {
+                kept.push(Lock {
+                    start: lock.end + 1,
+                    ..held
+                });
+            }
+        }
+        self.locks = kept;
+    }
+
+    fn wake(&mut self) {
+        let mut i = 0;
+        while i < self.waiting.len() {
+            let lock = self.waiting[i].0;
+            if self.get(&lock).is_some() {
+                i += 1;
+            } else {
+                let (lock, reply) = self.waiting.remove(i);
+                self.grant(lock);
+                reply.ok();
+                i = 0;
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt::{self, Write as _};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use fuser::Request;
+use libc::c_int;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+#[cfg(feature = "otel")]
+use crate::otel;
+use crate::systemd;
+
+/// How log records are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One line of text per record, prefixed like the rest of the output.
+    #[default]
+    Text,
+    /// One JSON object per record.
+    Json,
+}
+
+/// Where log records go.
+pub enum Output {
+    /// Lines in the chosen format, written to stderr or a file.
+    Stream(Box<dyn Write + Send>),
+    /// Records sent to the systemd journal, with their fields and priority.
+    Journald,
+}
+
+struct Logger {
+    format: Format,
+    output: Mutex<Output>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+impl Logger {
+    fn write(&self, line: &str) {
+        if let Output::Stream(stream) = &mut *self.output.lock().unwrap() {
+            let _ = writeln!(stream, "{}", line);
+        }
+    }
+
+    fn is_journald(&self) -> bool {
+        matches!(*self.output.lock().unwrap(), Output::Journald)
+    }
+}
+
+/// Sends a record to the journal, at the syslog priority matching `level`.
+fn journal(level: Level, message: &str, fields: &[(&str, &str)]) {
+    let priority = match level {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    };
+    let mut record = vec![
+        ("MESSAGE", message),
+        ("PRIORITY", priority),
+        ("SYSLOG_IDENTIFIER", "nullfs"),
+    ];
+    record.extend_from_slice(fields);
+    // There is nowhere left to report a journal that went away.
+    let _ = systemd::journal(&record);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if self.is_journald() {
+            let message = record.args().to_string();
+            journal(record.level(), &message, &[("TARGET", record.target())]);
+            return;
+        }
+        match self.format {
+            Format::Text => self.write(&format!("nullfs: {}", record.args())),
+            Format::Json => self.write(&format!(
+                "{{\"time\":{},\"level\":{},\"target\":{},\"message\":{}}}",
+                now(),
+                json_string(record.level().as_str()),
+                json_string(record.target()),
+                json_string(&record.args().to_string())
+            )),
+        }
+    }
+
+    fn flush(&self) {
+        if let Output::Stream(stream) = &mut *self.output.lock().unwrap() {
+            let _ = stream.flush();
+        }
+    }
+}
+
+/// Installs the logger writing `format` to `output`, showing warnings and errors and more
+/// with every level of `verbosity`: informational messages, every request fuser receives,
+/// then the outcome of every operation reaching the sink or the source.
+pub fn init(verbosity: u64, format: Format, output: Output) -> Result<(), SetLoggerError> {
+    let logger = LOGGER.get_or_init(|| Logger {
+        format,
+        output: Mutex::new(output),
+    });
+    log::set_logger(logger)?;
+    log::set_max_level(match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    });
+    Ok(())
+}
+
+/// An operation reaching the sink or the source, logged with its outcome at trace level
+/// and exported as a span when OpenTelemetry is enabled.
+pub struct Op {
+    name: &'static str,
+    ino: u64,
+    pid: u32,
+    uid: u32,
+    range: Option<(i64, u64)>,
+    start: Instant,
+}
+
+impl Op {
+    /// Starts timing the operation `name` that `req` asks for on `ino`.
+    pub fn start(name: &'static str, req: &Request, ino: u64) -> Op {
+        Op {
+            name,
+            ino,
+            pid: req.pid(),
+            uid: req.uid(),
+            range: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the `size` bytes at `offset` the operation concerns.
+    pub fn range(self, offset: i64, size: u64) -> Op {
+        Op {
+            range: Some((offset, size)),
+            ..self
+        }
+    }
+
+    /// Logs the outcome: the bytes moved, or the errno the operation failed with.
+    pub fn done(self, result: Result<u64, c_int>) {
+        let latency = self.start.elapsed();
+        #[cfg(feature = "otel")]
+        if otel::is_enabled() {
+            self.export(result, latency);
+        }
+        if log::max_level() < Level::Trace {
+            return;
+        }
+        match LOGGER.get() {
+            Some(logger) if logger.is_journald() => self.journal(result, latency),
+            Some(logger) if logger.format == Format::Json => {
+                logger.write(&self.json(result, latency))
+            }
+            _ => log::trace!("{}", Outcome(&self, result, latency)),
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    fn export(&self, result: Result<u64, c_int>, latency: Duration) {
+        let end = SystemTime::now();
+        let mut attributes = vec![
+            ("nullfs.ino", self.ino as i64),
+            ("nullfs.caller.pid", self.pid as i64),
+            ("nullfs.caller.uid", self.uid as i64),
+            ("nullfs.result", signed(result)),
+        ];
+        if let Some((offset, size)) = self.range {
+            attributes.push(("nullfs.offset", offset));
+            attributes.push(("nullfs.size", size as i64));
+        }
+        otel::export(otel::Span {
+            name: self.name,
+            start: end - latency,
+            end,
+            attributes,
+            errno: result.err(),
+        });
+    }
+
+    fn journal(&self, result: Result<u64, c_int>, latency: Duration) {
+        let message = Outcome(self, result, latency).to_string();
+        let ino = self.ino.to_string();
+        let pid = self.pid.to_string();
+        let uid = self.uid.to_string();
+        let latency = latency.as_micros().to_string();
+        let result = signed(result).to_string();
+        let mut fields = vec![
+            ("NULLFS_OP", self.name),
+            ("NULLFS_INO", &ino),
+            ("NULLFS_CALLER_PID", &pid),
+            ("NULLFS_CALLER_UID", &uid),
+            ("NULLFS_LATENCY_US", &latency),
+            ("NULLFS_RESULT", &result),
+        ];
+        let range = self
+            .range
+            .map(|(offset, size)| (offset.to_string(), size.to_string()));
+        if let Some((offset, size)) = &range {
+            fields.push(("NULLFS_OFFSET", offset));
+            fields.push(("NULLFS_SIZE", size));
+        }
+        journal(Level::Trace, &message, &fields);
+    }
+
+    fn json(&self, result: Result<u64, c_int>, latency: Duration) -> String {
+        let mut json = format!(
+            "{{\"time\":{},\"op\":{},\"ino\":{},\"pid\":{},\"uid\":{}",
+            now(),
+            json_string(self.name),
+            self.ino,
+            self.pid,
+            self.uid
+        );
+        if let Some((offset, size)) = self.range {
+            let _ = write!(json, ",\"offset\":{},\"size\":{}", offset, size);
+        }
+        let _ = write!(
+            json,
+            ",\"latency_us\":{},\"result\":{}}}",
+            latency.as_micros(),
+            signed(result)
+        );
+        json
+    }
+}
+
+struct Outcome<'a>(&'a Op, Result<u64, c_int>, Duration);
+
+impl fmt::Display for Outcome<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Outcome(op, result, latency) = self;
+        write!(f, "{} ino {}", op.name, op.ino)?;
+        if let Some((offset, size)) = op.range {
+            write!(f, " offset {} size {}", offset, size)?;
+        }
+        write!(f, " pid {} uid {} in {:?}: ", op.pid, op.uid, latency)?;
+        match result {
+            Ok(bytes) => write!(f, "{} bytes", bytes),
+            Err(errno) => write!(f, "{}", std::io::Error::from_raw_os_error(*errno)),
+        }
+    }
+}
+
+/// Turns errors negative like in FUSE replies.
+fn signed(result: Result<u64, c_int>) -> i64 {
+    result.map_or_else(|errno| -(errno as i64), |bytes| bytes as i64)
+}
+
+/// Seconds since the epoch, with microseconds.
+pub(crate) fn now() -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:06}", now.as_secs(), now.subsec_micros())
+}
+
+/// Seconds, with nanoseconds.
+pub(crate) fn json_duration(duration: Duration) -> String {
+    format!("{}.{:09}", duration.as_secs(), duration.subsec_nanos())
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,102 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use libc::{c_int, EINVAL, ENAMETOOLONG};
+
+/// Restrictions on the names given to new files, like strict filesystems have.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NamePolicy {
+    /// Longest name in bytes, beyond which ENAMETOOLONG is returned.
+    pub max_length: Option<usize>,
+    /// Refuse names that are not valid UTF-8 with EINVAL.
+    pub utf8: bool,
+    /// Refuse names with control characters with EINVAL.
+    pub no_control: bool,
+}
+
+impl NamePolicy {
+    /// Parses a comma-separated list of `max-length=N`, `utf8` and `no-control`, or
+    /// `strict` for all of them with names of up to 255 bytes.
+    pub fn parse(policy: &str) -> Result<NamePolicy, String> {
+        let mut parsed = NamePolicy::default();
+        for part in policy.split(',').map(str::trim) {
+            match part.split_once('=') {
+                Some(("max-length", length)) => {
+                    let length = length
+                        .parse()
+                        .map_err(|_| format!("invalid length '{}'", length))?;
+                    parsed.max_length = Some(length);
+                }
+                None if part == "utf8" => parsed.utf8 = true,
+                None if part == "no-control" => parsed.no_control = true,
+                None if part == "strict" => {
+                    parsed = NamePolicy {
+                        max_length: Some(parsed.max_length.unwrap_or(255)),
+                        utf8: true,
+                        no_control: true,
+                    }
+                }
+                _ => return Err(format!("unknown name policy '{}'", part)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Checks a name given to a new file, returning the errno to refuse it with.
+    pub fn check(&self, name: &OsStr) -> Result<(), c_int> {
+        let bytes = name.as_bytes();
+        if self.max_length.is_some_and(|max| bytes.len() > max) {
+            return Err(ENAMETOOLONG);
+        }
+        if self.utf8 && name.to_str().is_none() {
+            return Err(EINVAL);
+        }
+        if self.no_control && bytes.iter().any(|&byte| byte < 0x20 || byte == 0x7f) {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(
+            NamePolicy::parse("max-length=8, utf8"),
+            Ok(NamePolicy {
+                max_length: Some(8),
+                utf8: true,
+                no_control: false,
+            })
+        );
+        assert_eq!(
+            NamePolicy::parse("strict"),
+            Ok(NamePolicy {
+                max_length: Some(255),
+                utf8: true,
+                no_control: true,
+            })
+        );
+        assert_eq!(
+            NamePolicy::parse("max-length=12,strict").map(|policy| policy.max_length),
+            Ok(Some(12))
+        );
+        assert!(NamePolicy::parse("max-length=long").is_err());
+        assert!(NamePolicy::parse("ascii").is_err());
+    }
+
+    #[test]
+    fn checks_names() {
+        let policy = NamePolicy::parse("strict,max-length=4").unwrap();
+        assert_eq!(policy.check(OsStr::new("abcd")), Ok(()));
+        assert_eq!(policy.check(OsStr::new("abcde")), Err(ENAMETOOLONG));
+        assert_eq!(policy.check(OsStr::from_bytes(b"a\xff")), Err(EINVAL));
+        assert_eq!(policy.check(OsStr::new("a\tb")), Err(EINVAL));
+        assert_eq!(NamePolicy::default().check(OsStr::new("a\tb")), Ok(()));
+    }
+}
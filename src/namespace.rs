@@ -0,0 +1,925 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use fuser::{FileAttr, FileType};
+#[cfg(not(target_os = "linux"))]
+use libc::ENOATTR as ENODATA;
+use libc::{c_int, EACCES, EEXIST, EINVAL, EISDIR, ENOENT, ENOTDIR, ENOTEMPTY, EPERM};
+#[cfg(target_os = "linux")]
+use libc::{ENODATA, RENAME_EXCHANGE, RENAME_NOREPLACE};
+#[cfg(target_os = "macos")]
+use libc::{RENAME_EXCL as RENAME_NOREPLACE, RENAME_SWAP as RENAME_EXCHANGE};
+#[cfg(not(target_os = "freebsd"))]
+use libc::{XATTR_CREATE, XATTR_REPLACE};
+
+use crate::acl;
+use crate::config::Config;
+use crate::contents::Contents;
+use crate::logger::json_string;
+use crate::pipe::Pipe;
+use crate::stats::{FileStats, Stats};
+
+// FreeBSD names neither, so the values the FUSE protocol carries from Linux are taken.
+#[cfg(target_os = "freebsd")]
+const RENAME_NOREPLACE: u32 = 1 << 0;
+#[cfg(target_os = "freebsd")]
+const RENAME_EXCHANGE: u32 = 1 << 1;
+#[cfg(target_os = "freebsd")]
+const XATTR_CREATE: i32 = 1;
+#[cfg(target_os = "freebsd")]
+const XATTR_REPLACE: i32 = 2;
+
+pub const ROOT: u64 = 1;
+pub const NULL: u64 = 2;
+
+/// Device number of /dev/null, 1:3, as the kernel encodes it for FUSE.
+pub const NULL_RDEV: u32 = 1 << 8 | 3;
+
+const DIR_ATTR: FileAttr = FileAttr {
+    ino: ROOT,
+    size: 0,
+    blocks: 0,
+    atime: SystemTime::UNIX_EPOCH,
+    mtime: SystemTime::UNIX_EPOCH,
+    ctime: SystemTime::UNIX_EPOCH,
+    crtime: SystemTime::UNIX_EPOCH,
+    kind: FileType::Directory,
+    perm: 0o777,
+    nlink: 2,
+    uid: 0,
+    gid: 0,
+    rdev: 0,
+    flags: 0,
+    blksize: 0,
+};
+
+const NULL_ATTR: FileAttr = FileAttr {
+    ino: NULL,
+    size: 0,
+    blocks: 1,
+    atime: SystemTime::UNIX_EPOCH,
+    mtime: SystemTime::UNIX_EPOCH,
+    ctime: SystemTime::UNIX_EPOCH,
+    crtime: SystemTime::UNIX_EPOCH,
+    kind: FileType::RegularFile,
+    perm: 0o666,
+    nlink: 1,
+    uid: 0,
+    gid: 0,
+    rdev: 0,
+    flags: 0,
+    blksize: 0,
+};
+
+pub struct Inode {
+    attr: FileAttr,
+    /// Bytes accounted to a regular file, shared with the writes in flight.
+    pub size: Arc<AtomicU64>,
+    /// What went through a regular file.
+    pub stats: Arc<FileStats>,
+    /// What was written to a regular file, as `--keep-under` or an echo rule keeps it.
+    pub contents: Arc<Contents>,
+    /// What was written to a regular file matching a pipe rule and not read yet.
+    pub pipe: Arc<Pipe>,
+    /// Directory the inode was created in; the root is its own parent.
+    pub parent: u64,
+    /// Name the inode was last given in `parent`.
+    pub name: OsString,
+    /// Names in a directory.
+    pub entries: BTreeMap<OsString, u64>,
+    /// Where a symbolic link points.
+    pub target: OsString,
+    /// Extended attributes by name.
+    pub xattrs: BTreeMap<OsString, Vec<u8>>,
+    /// When the inode was last backed up, which only macOS keeps track of.
+    pub bkuptime: SystemTime,
+    /// References the kernel holds through lookups it has not forgotten yet.
+    lookups: u64,
+}
+
+impl Inode {
+    pub fn attr(&self) -> FileAttr {
+        match self.attr.kind {
+            FileType::RegularFile => {
+                let size = self.size.load(Ordering::Relaxed);
+                FileAttr {
+                    // What is advertised beyond that is a hole.
+                    size: size.max(self.attr.size),
+                    // In the 512-byte units of st_blocks, as if every byte were stored.
+                    blocks: size.div_ceil(512),
+                    ..self.attr
+                }
+            }
+            _ => self.attr,
+        }
+    }
+
+    pub fn kind(&self) -> FileType {
+        self.attr.kind
+    }
+}
+
+/// Attributes to change on an inode, those left out staying as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetAttr {
+    /// Permission bits, with setuid, setgid and sticky.
+    pub perm: Option<u16>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Size of a regular file, as truncating or extending it would leave it.
+    pub size: Option<u64>,
+    pub atime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+}
+
+/// Inode table of the mount, starting out as the root directory holding the null file.
+pub struct Namespace {
+    inodes: HashMap<u64, Inode>,
+    next_ino: u64,
+    /// Generation of every inode number handed out by this mount, telling them apart from
+    /// the same numbers in earlier mounts.
+    generation: u64,
+    /// Resolve names regardless of case, keeping the case they were created with.
+    case_insensitive: bool,
+    /// Preferred I/O size every inode reports.
+    blksize: u32,
+    /// Size regular files report at least, whatever was written to them.
+    advertised_size: u64,
+    /// Hash what is written to every file.
+    digest: bool,
+    stats: Arc<Stats>,
+}
+
+impl Namespace {
+    /// Builds the namespace a mount configured by `config` starts out with.
+    pub fn new(stats: Arc<Stats>, config: &Config) -> Namespace {
+        let mut namespace = Namespace {
+            inodes: HashMap::new(),
+            next_ino: NULL + 1,
+            generation: 0,
+            case_insensitive: config.case_insensitive,
+            blksize: config.blksize,
+            advertised_size: config.advertise_size,
+            digest: config.digest,
+            stats,
+        };
+        // The initial nodes are born with the mount.
+        let crtime = SystemTime::now();
+        namespace.generation = crtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |age| age.as_secs());
+        let (dir, file) = (config.dir_attrs, config.file_attrs);
+        let root = FileAttr {
+            crtime,
+            perm: dir.perm,
+            uid: dir.uid,
+            gid: dir.gid,
+            ..DIR_ATTR
+        };
+        namespace.insert(ROOT, root, ROOT, OsStr::new(""));
+        let null = FileAttr {
+            crtime,
+            perm: file.perm,
+            uid: file.uid,
+            gid: file.gid,
+            ..NULL_ATTR
+        };
+        namespace.insert(NULL, null, ROOT, &config.name);
+        namespace
+            .get_mut(ROOT)
+            .unwrap()
+            .entries
+            .insert(config.name.clone(), NULL);
+        #[cfg(feature = "preload")]
+        if let Some(manifest) = &config.preload {
+            manifest.apply(&mut namespace);
+        }
+        namespace
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn get(&self, ino: u64) -> Option<&Inode> {
+        self.inodes.get(&ino)
+    }
+
+    fn get_mut(&mut self, ino: u64) -> Option<&mut Inode> {
+        self.inodes.get_mut(&ino)
+    }
+
+    /// Returns the path of `ino` within the mount, through the names it was last given.
+    pub fn path(&self, mut ino: u64) -> PathBuf {
+        let mut names = Vec::new();
+        // A removed directory may already be gone from above an open file.
+        while let Some(inode) = self.get(ino).filter(|_| ino != ROOT) {
+            names.push(inode.name.as_os_str());
+            ino = inode.parent;
+        }
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        path
+    }
+
+    pub fn attr(&self, ino: u64) -> Option<FileAttr> {
+        self.get(ino).map(Inode::attr)
+    }
+
+    /// Describes the tree as JSON, every name with its attributes and what went through the
+    /// regular files, directories listing their entries.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json, OsStr::new(""), ROOT);
+        json
+    }
+
+    /// Writes `ino` as an entry named `name`, since hard links have several.
+    fn write_json(&self, json: &mut String, name: &OsStr, ino: u64) {
+        let inode = self.get(ino).unwrap();
+        let attr = inode.attr();
+        let kind = match attr.kind {
+            FileType::Directory => "directory",
+            FileType::Symlink => "symlink",
+            FileType::CharDevice => "char_device",
+            _ => "file",
+        };
+        let _ = write!(
+            json,
+            "{{\"name\":{},\"ino\":{},\"type\":\"{}\",\"mode\":{},\"uid\":{},\"gid\":{},\"nlink\":{},\"size\":{},\"atime\":{},\"mtime\":{},\"ctime\":{}",
+            json_string(&name.to_string_lossy()),
+            ino,
+            kind,
+            attr.perm,
+            attr.uid,
+            attr.gid,
+            attr.nlink,
+            attr.size,
+            json_time(attr.atime),
+            json_time(attr.mtime),
+            json_time(attr.ctime),
+        );
+        match attr.kind {
+            FileType::RegularFile => {
+                let _ = write!(
+                    json,
+                    ",\"writes\":{},\"bytes_written\":{},\"digest\":{}",
+                    inode.stats.writes.load(Ordering::Relaxed),
+                    inode.stats.bytes_written.load(Ordering::Relaxed),
+                    match inode.stats.digest() {
+                        Some(digest) => format!("\"{:016x}\"", digest),
+                        None => "null".to_string(),
+                    }
+                );
+            }
+            FileType::Symlink => {
+                let target = json_string(&inode.target.to_string_lossy());
+                let _ = write!(json, ",\"target\":{}", target);
+            }
+            FileType::Directory => {
+                json.push_str(",\"entries\":[");
+                for (i, (name, child)) in inode.entries.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    self.write_json(json, name, *child);
+                }
+                json.push(']');
+            }
+            _ => {}
+        }
+        json.push('}');
+    }
+
+    pub fn is_kind(&self, ino: u64, kind: FileType) -> bool {
+        self.get(ino).is_some_and(|inode| inode.kind() == kind)
+    }
+
+    /// Returns the inode `name` refers to in `parent`, without counting a reference.
+    pub fn child(&self, parent: u64, name: &OsStr) -> Result<u64, c_int> {
+        let dir = self.get(parent).ok_or(ENOENT)?;
+        // The kernel resolves . and .. itself, except for file handles exported over NFS,
+        // which it looks up as . of any inode.
+        if name.as_bytes() == b"." {
+            return Ok(parent);
+        }
+        if dir.kind() != FileType::Directory {
+            return Err(ENOTDIR);
+        }
+        match name.as_bytes() {
+            b".." => Ok(dir.parent),
+            _ => self
+                .stored_name(dir, name)
+                .map(|name| dir.entries[&name])
+                .ok_or(ENOENT),
+        }
+    }
+
+    /// Returns the name an entry matching `name` is stored under in `dir`, which only
+    /// differs from `name` when resolving regardless of case.
+    fn stored_name(&self, dir: &Inode, name: &OsStr) -> Option<OsString> {
+        if dir.entries.contains_key(name) {
+            return Some(name.to_os_string());
+        }
+        if !self.case_insensitive {
+            return None;
+        }
+        let folded = fold(name);
+        dir.entries.keys().find(|key| fold(key) == folded).cloned()
+    }
+
+    /// Returns the name an entry matching `name` is stored under in `parent`, or `name`
+    /// itself if there is none.
+    fn spelling(&self, parent: u64, name: &OsStr) -> OsString {
+        self.get(parent)
+            .and_then(|dir| self.stored_name(dir, name))
+            .unwrap_or_else(|| name.to_os_string())
+    }
+
+    /// Resolves `name` in `parent` and counts the reference the kernel takes on it.
+    pub fn lookup(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr, c_int> {
+        let ino = self.child(parent, name)?;
+        Ok(self.remember(ino))
+    }
+
+    /// Counts a reference the kernel takes on `ino` from a reply other than lookup.
+    pub fn remember(&mut self, ino: u64) -> FileAttr {
+        let inode = self.get_mut(ino).unwrap();
+        inode.lookups += 1;
+        inode.attr()
+    }
+
+    /// Drops `nlookup` kernel references to `ino`, freeing it once it is unreachable.
+    pub fn forget(&mut self, ino: u64, nlookup: u64) {
+        if let Some(inode) = self.get_mut(ino) {
+            inode.lookups = inode.lookups.saturating_sub(nlookup);
+        }
+        self.free_if_unused(ino);
+    }
+
+    /// Creates `name` in `parent` and counts the reference the kernel takes on it.
+    pub fn create(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        kind: FileType,
+        perm: u16,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttr, c_int> {
+        match self.child(parent, name) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(err) => return Err(err),
+        }
+
+        let now = SystemTime::now();
+        let template = match kind {
+            FileType::Directory => DIR_ATTR,
+            FileType::CharDevice => FileAttr {
+                rdev: NULL_RDEV,
+                ..NULL_ATTR
+            },
+            _ => NULL_ATTR,
+        };
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        let attr = FileAttr {
+            ino,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind,
+            perm,
+            uid,
+            gid,
+            ..template
+        };
+        self.insert(ino, attr, parent, name);
+
+        let parent = self.get_mut(parent).unwrap();
+        parent.entries.insert(name.to_os_string(), ino);
+        parent.attr.mtime = now;
+        parent.attr.ctime = now;
+        if kind == FileType::Directory {
+            parent.attr.nlink += 1;
+        }
+        Ok(self.remember(ino))
+    }
+
+    /// Creates the symbolic link `name` to `target` in `parent` and counts the reference the
+    /// kernel takes on it.
+    pub fn symlink(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        target: &OsStr,
+        uid: u32,
+        gid: u32,
+    ) -> Result<FileAttr, c_int> {
+        let attr = self.create(parent, name, FileType::Symlink, 0o777, uid, gid)?;
+        let inode = self.get_mut(attr.ino).unwrap();
+        inode.target = target.to_os_string();
+        inode.attr.size = target.len() as u64;
+        Ok(inode.attr())
+    }
+
+    /// Adds `newname` in `newparent` as another name of `ino` and counts the reference the
+    /// kernel takes on it.
+    pub fn link(&mut self, ino: u64, newparent: u64, newname: &OsStr) -> Result<FileAttr, c_int> {
+        match self.get(ino) {
+            Some(inode) if inode.kind() == FileType::Directory => return Err(EPERM),
+            Some(_) => {}
+            None => return Err(ENOENT),
+        }
+        match self.child(newparent, newname) {
+            Ok(_) => return Err(EEXIST),
+            Err(ENOENT) => {}
+            Err(err) => return Err(err),
+        }
+
+        let now = SystemTime::now();
+        let dir = self.get_mut(newparent).unwrap();
+        dir.entries.insert(newname.to_os_string(), ino);
+        dir.attr.mtime = now;
+        dir.attr.ctime = now;
+        let inode = self.get_mut(ino).unwrap();
+        inode.attr.nlink += 1;
+        inode.attr.ctime = now;
+        Ok(self.remember(ino))
+    }
+
+    /// Sets the extended attribute `name` of `ino`, honouring `XATTR_CREATE` and `XATTR_REPLACE`.
+    pub fn setxattr(
+        &mut self,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+    ) -> Result<(), c_int> {
+        let inode = self.get_mut(ino).ok_or(ENOENT)?;
+        let exists = inode.xattrs.contains_key(name);
+        if flags & XATTR_CREATE != 0 && exists {
+            return Err(EEXIST);
+        }
+        if flags & XATTR_REPLACE != 0 && !exists {
+            return Err(ENODATA);
+        }
+        if name == acl::ACCESS {
+            let entries = acl::parse(value).ok_or(EINVAL)?;
+            inode.attr.perm = inode.attr.perm & !0o777 | acl::mode(&entries);
+        } else if name == acl::DEFAULT {
+            if inode.kind() != FileType::Directory {
+                return Err(EACCES);
+            }
+            acl::parse(value).ok_or(EINVAL)?;
+        }
+        inode.xattrs.insert(name.to_os_string(), value.to_vec());
+        inode.attr.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    /// Changes the attributes of `ino` that `changes` gives, its change time included.
+    pub fn set_attr(&mut self, ino: u64, changes: SetAttr) -> Result<FileAttr, c_int> {
+        let inode = self.get_mut(ino).ok_or(ENOENT)?;
+        if let Some(size) = changes.size {
+            match inode.kind() {
+                FileType::RegularFile => inode.size.store(size, Ordering::Relaxed),
+                FileType::Directory => return Err(EISDIR),
+                _ => return Err(EINVAL),
+            }
+            inode.attr.mtime = SystemTime::now();
+        }
+        if let Some(perm) = changes.perm {
+            inode.attr.perm = perm & 0o7777;
+        }
+        if let Some(uid) = changes.uid {
+            inode.attr.uid = uid;
+        }
+        if let Some(gid) = changes.gid {
+            inode.attr.gid = gid;
+        }
+        if let Some(atime) = changes.atime {
+            inode.attr.atime = atime;
+        }
+        if let Some(mtime) = changes.mtime {
+            inode.attr.mtime = mtime;
+        }
+        inode.attr.ctime = SystemTime::now();
+        Ok(inode.attr())
+    }
+
+    /// Marks the contents of `ino` as modified now.
+    pub fn modified(&mut self, ino: u64) {
+        if let Some(inode) = self.get_mut(ino) {
+            let now = SystemTime::now();
+            inode.attr.mtime = now;
+            inode.attr.ctime = now;
+        }
+    }
+
+    /// Sets the times and flags of `ino` which only macOS changes, its change time included.
+    pub fn set_xtimes(
+        &mut self,
+        ino: u64,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+    ) -> Result<FileAttr, c_int> {
+        let inode = self.get_mut(ino).ok_or(ENOENT)?;
+        if let Some(crtime) = crtime {
+            inode.attr.crtime = crtime;
+        }
+        if let Some(chgtime) = chgtime {
+            inode.attr.ctime = chgtime;
+        }
+        if let Some(bkuptime) = bkuptime {
+            inode.bkuptime = bkuptime;
+        }
+        if let Some(flags) = flags {
+            inode.attr.flags = flags;
+        }
+        Ok(inode.attr())
+    }
+
+    /// Removes the extended attribute `name` of `ino`.
+    pub fn removexattr(&mut self, ino: u64, name: &OsStr) -> Result<(), c_int> {
+        let inode = self.get_mut(ino).ok_or(ENOENT)?;
+        inode.xattrs.remove(name).ok_or(ENODATA)?;
+        inode.attr.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    /// Removes the name `name` of a non-directory in `parent`; the inode stays until forgotten.
+    pub fn unlink(&mut self, parent: u64, name: &OsStr) -> Result<(), c_int> {
+        let ino = self.child(parent, name)?;
+        let name = &self.spelling(parent, name);
+        if self.is_kind(ino, FileType::Directory) {
+            return Err(EISDIR);
+        }
+        self.remove(parent, name);
+        Ok(())
+    }
+
+    /// Removes the empty directory `name` in `parent`.
+    pub fn rmdir(&mut self, parent: u64, name: &OsStr) -> Result<(), c_int> {
+        let dir = self.get(self.child(parent, name)?).unwrap();
+        if dir.kind() != FileType::Directory {
+            return Err(ENOTDIR);
+        }
+        if !dir.entries.is_empty() {
+            return Err(ENOTEMPTY);
+        }
+        let name = &self.spelling(parent, name);
+        self.remove(parent, name);
+        Ok(())
+    }
+
+    fn remove(&mut self, parent: u64, name: &OsStr) {
+        let now = SystemTime::now();
+        self.unlink_entry(parent, name, now);
+        let parent = self.get_mut(parent).unwrap();
+        parent.attr.mtime = now;
+        parent.attr.ctime = now;
+    }
+
+    /// Moves `name` in `parent` to `newname` in `newparent`, as rename(2) and renameat2(2) do.
+    pub fn rename(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+    ) -> Result<(), c_int> {
+        let ino = self.child(parent, name)?;
+        let name = &self.spelling(parent, name);
+        let stored_newname = &self.spelling(newparent, newname);
+        let target = match self.child(newparent, newname) {
+            Ok(target) => Some(target),
+            Err(ENOENT) => None,
+            Err(err) => return Err(err),
+        };
+
+        let exchange = flags & RENAME_EXCHANGE != 0;
+        if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0
+            || (exchange && flags & RENAME_NOREPLACE != 0)
+        {
+            return Err(EINVAL);
+        }
+        if flags & RENAME_NOREPLACE != 0 && target.is_some() {
+            return Err(EEXIST);
+        }
+        if exchange && target.is_none() {
+            return Err(ENOENT);
+        }
+        if self.contains(ino, newparent)
+            || (exchange && self.contains(target.unwrap_or(ino), parent))
+        {
+            return Err(EINVAL);
+        }
+        let now = SystemTime::now();
+        if target == Some(ino) {
+            // The same name, or two hard links to one inode, which renaming leaves alone.
+            if exchange || parent != newparent || name != stored_newname || name == newname {
+                return Ok(());
+            }
+            // The same entry under a name differing only in case, which it takes on.
+            let dir = self.get_mut(parent).unwrap();
+            dir.entries.remove(name);
+            dir.entries.insert(newname.to_os_string(), ino);
+            dir.attr.mtime = now;
+            dir.attr.ctime = now;
+            let inode = self.get_mut(ino).unwrap();
+            inode.name = newname.to_os_string();
+            inode.attr.ctime = now;
+            return Ok(());
+        }
+
+        match target {
+            Some(target) if exchange => {
+                self.get_mut(parent)
+                    .unwrap()
+                    .entries
+                    .insert(name.to_os_string(), target);
+                self.get_mut(newparent)
+                    .unwrap()
+                    .entries
+                    .remove(stored_newname);
+                self.reparent(target, parent, name);
+                self.get_mut(target).unwrap().attr.ctime = now;
+            }
+            target => {
+                if let Some(target) = target {
+                    let is_dir = self.is_kind(ino, FileType::Directory);
+                    let target = self.get(target).unwrap();
+                    match (is_dir, target.kind() == FileType::Directory) {
+                        (true, false) => return Err(ENOTDIR),
+                        (false, true) => return Err(EISDIR),
+                        (true, true) if !target.entries.is_empty() => return Err(ENOTEMPTY),
+                        _ => {}
+                    }
+                    self.unlink_entry(newparent, stored_newname, now);
+                }
+                self.get_mut(parent).unwrap().entries.remove(name);
+            }
+        }
+        self.get_mut(newparent)
+            .unwrap()
+            .entries
+            .insert(newname.to_os_string(), ino);
+        self.reparent(ino, newparent, newname);
+        self.get_mut(ino).unwrap().attr.ctime = now;
+
+        for dir in [parent, newparent] {
+            let dir = self.get_mut(dir).unwrap();
+            dir.attr.mtime = now;
+            dir.attr.ctime = now;
+        }
+        Ok(())
+    }
+
+    /// Reports whether `ino` is `dir` or one of the directories above it.
+    fn contains(&self, ino: u64, mut dir: u64) -> bool {
+        loop {
+            if dir == ino {
+                return true;
+            }
+            if dir == ROOT {
+                return false;
+            }
+            // An ancestor removed meanwhile leaves nothing above to be `ino`.
+            match self.get(dir) {
+                Some(inode) => dir = inode.parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Records that `ino` now lives in `parent` as `name`, moving the .. link of a directory
+    /// along.
+    fn reparent(&mut self, ino: u64, parent: u64, name: &OsStr) {
+        let inode = self.get_mut(ino).unwrap();
+        inode.name = name.to_os_string();
+        let old = mem::replace(&mut inode.parent, parent);
+        if inode.kind() == FileType::Directory && old != parent {
+            self.get_mut(old).unwrap().attr.nlink -= 1;
+            self.get_mut(parent).unwrap().attr.nlink += 1;
+        }
+    }
+
+    /// Drops the name `name` in `parent`, freeing its inode once it is unreachable.
+    fn unlink_entry(&mut self, parent: u64, name: &OsStr, now: SystemTime) {
+        let ino = self.get_mut(parent).unwrap().entries.remove(name).unwrap();
+        let inode = self.get_mut(ino).unwrap();
+        let is_dir = inode.kind() == FileType::Directory;
+        inode.attr.nlink = if is_dir {
+            0
+        } else {
+            inode.attr.nlink.saturating_sub(1)
+        };
+        inode.attr.ctime = now;
+        if is_dir {
+            self.get_mut(parent).unwrap().attr.nlink -= 1;
+        }
+        self.free_if_unused(ino);
+    }
+
+    fn insert(&mut self, ino: u64, attr: FileAttr, parent: u64, name: &OsStr) {
+        self.inodes.insert(
+            ino,
+            Inode {
+                attr: FileAttr {
+                    blksize: self.blksize,
+                    size: match attr.kind {
+                        FileType::RegularFile => self.advertised_size,
+                        _ => attr.size,
+                    },
+                    ..attr
+                },
+                size: Arc::default(),
+                stats: Arc::new(FileStats::new(self.digest)),
+                contents: Arc::default(),
+                pipe: Arc::default(),
+                parent,
+                name: name.to_os_string(),
+                entries: BTreeMap::new(),
+                target: OsString::new(),
+                xattrs: BTreeMap::new(),
+                bkuptime: SystemTime::UNIX_EPOCH,
+                lookups: 0,
+            },
+        );
+        self.stats.inodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn free_if_unused(&mut self, ino: u64) {
+        let unused = self
+            .get(ino)
+            .is_some_and(|inode| inode.lookups == 0 && inode.attr.nlink == 0);
+        if unused && self.inodes.remove(&ino).is_some() {
+            self.stats.inodes.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Handle on the namespace of a mounted filesystem, to inspect it from other threads.
+#[derive(Clone)]
+pub struct Tree(Arc<Mutex<Namespace>>);
+
+impl Tree {
+    pub(crate) fn new(namespace: Arc<Mutex<Namespace>>) -> Tree {
+        Tree(namespace)
+    }
+
+    /// Describes the current tree as JSON.
+    pub fn to_json(&self) -> String {
+        self.0.lock().unwrap().to_json()
+    }
+}
+
+/// Seconds since the epoch, with nanoseconds.
+fn json_time(time: SystemTime) -> String {
+    let since = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:09}", since.as_secs(), since.subsec_nanos())
+}
+
+/// Folds the case of a name for comparisons regardless of case.
+fn fold(name: &OsStr) -> String {
+    name.to_string_lossy().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace(case_insensitive: bool) -> Namespace {
+        let config = Config {
+            case_insensitive,
+            ..Config::default()
+        };
+        Namespace::new(Arc::default(), &config)
+    }
+
+    fn create(namespace: &mut Namespace, parent: u64, name: &str, kind: FileType) -> u64 {
+        namespace
+            .create(parent, OsStr::new(name), kind, 0o755, 0, 0)
+            .unwrap()
+            .ino
+    }
+
+    fn names(namespace: &Namespace, dir: u64) -> Vec<&OsStr> {
+        let dir = namespace.get(dir).unwrap();
+        dir.entries.keys().map(OsString::as_os_str).collect()
+    }
+
+    #[test]
+    fn rename_moves_an_entry() {
+        let mut namespace = namespace(false);
+        let dir = create(&mut namespace, ROOT, "dir", FileType::Directory);
+        let file = create(&mut namespace, ROOT, "file", FileType::RegularFile);
+        namespace
+            .rename(ROOT, OsStr::new("file"), dir, OsStr::new("moved"), 0)
+            .unwrap();
+        assert_eq!(namespace.child(dir, OsStr::new("moved")), Ok(file));
+        assert_eq!(namespace.child(ROOT, OsStr::new("file")), Err(ENOENT));
+        assert_eq!(namespace.get(file).unwrap().parent, dir);
+    }
+
+    #[test]
+    fn rename_replaces_the_target() {
+        let mut namespace = namespace(false);
+        let a = create(&mut namespace, ROOT, "a", FileType::RegularFile);
+        create(&mut namespace, ROOT, "b", FileType::RegularFile);
+        namespace
+            .rename(ROOT, OsStr::new("a"), ROOT, OsStr::new("b"), 0)
+            .unwrap();
+        assert_eq!(namespace.child(ROOT, OsStr::new("b")), Ok(a));
+        assert_eq!(names(&namespace, ROOT), ["b", "null"]);
+    }
+
+    #[test]
+    fn rename_refuses_a_directory_into_itself() {
+        let mut namespace = namespace(false);
+        let dir = create(&mut namespace, ROOT, "dir", FileType::Directory);
+        let sub = create(&mut namespace, dir, "sub", FileType::Directory);
+        let renamed = namespace.rename(ROOT, OsStr::new("dir"), sub, OsStr::new("dir"), 0);
+        assert_eq!(renamed, Err(EINVAL));
+    }
+
+    #[test]
+    fn rename_noreplace_keeps_the_target() {
+        let mut namespace = namespace(false);
+        create(&mut namespace, ROOT, "a", FileType::RegularFile);
+        create(&mut namespace, ROOT, "b", FileType::RegularFile);
+        let renamed = namespace.rename(
+            ROOT,
+            OsStr::new("a"),
+            ROOT,
+            OsStr::new("b"),
+            RENAME_NOREPLACE,
+        );
+        assert_eq!(renamed, Err(EEXIST));
+    }
+
+    #[test]
+    fn rename_between_hard_links_does_nothing() {
+        let mut namespace = namespace(false);
+        let file = create(&mut namespace, ROOT, "a", FileType::RegularFile);
+        namespace.link(file, ROOT, OsStr::new("b")).unwrap();
+        namespace
+            .rename(ROOT, OsStr::new("a"), ROOT, OsStr::new("b"), 0)
+            .unwrap();
+        assert_eq!(names(&namespace, ROOT), ["a", "b", "null"]);
+    }
+
+    #[test]
+    fn rename_changes_the_case_of_a_name() {
+        let mut namespace = namespace(true);
+        let file = create(&mut namespace, ROOT, "foo", FileType::RegularFile);
+        namespace
+            .rename(ROOT, OsStr::new("foo"), ROOT, OsStr::new("FOO"), 0)
+            .unwrap();
+        assert_eq!(names(&namespace, ROOT), ["FOO", "null"]);
+        assert_eq!(namespace.child(ROOT, OsStr::new("foo")), Ok(file));
+        assert_eq!(namespace.get(file).unwrap().name, "FOO");
+    }
+
+    #[test]
+    fn exchange_keeps_one_entry_per_name() {
+        let mut namespace = namespace(true);
+        let a = create(&mut namespace, ROOT, "a", FileType::RegularFile);
+        let b = create(&mut namespace, ROOT, "B", FileType::RegularFile);
+        namespace
+            .rename(
+                ROOT,
+                OsStr::new("a"),
+                ROOT,
+                OsStr::new("b"),
+                RENAME_EXCHANGE,
+            )
+            .unwrap();
+        assert_eq!(names(&namespace, ROOT), ["a", "b", "null"]);
+        assert_eq!(namespace.child(ROOT, OsStr::new("a")), Ok(b));
+        assert_eq!(namespace.child(ROOT, OsStr::new("B")), Ok(a));
+        namespace.unlink(ROOT, OsStr::new("B")).unwrap();
+        assert_eq!(namespace.child(ROOT, OsStr::new("b")), Err(ENOENT));
+    }
+
+    #[test]
+    fn contains_stops_at_a_removed_ancestor() {
+        let namespace = namespace(false);
+        assert!(!namespace.contains(NULL + 1, NULL + 2));
+    }
+}
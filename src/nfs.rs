@@ -0,0 +1,1277 @@
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType};
+use libc::{
+    c_int, EACCES, EDQUOT, EEXIST, EFBIG, EINTR, EINVAL, EIO, EISDIR, EMLINK, ENAMETOOLONG, ENOENT,
+    ENOSPC, ENOTDIR, ENOTEMPTY, ENXIO, EOPNOTSUPP, EPERM, EROFS, ESTALE, EXDEV, R_OK, W_OK, X_OK,
+};
+
+use crate::acl;
+use crate::config::Config;
+use crate::contents::{Contents, Keep};
+use crate::fs::{account, endless, errno, interrupted, keep, pipe, truncate};
+use crate::interrupt::Call;
+use crate::namespace::{Namespace, SetAttr, NULL, NULL_RDEV, ROOT};
+use crate::pattern::Pattern;
+use crate::rules::{self, Action};
+use crate::sink::{DataSink, DataSource};
+use crate::stats::Stats;
+
+const MOUNT_PROGRAM: u32 = 100_005;
+const NFS_PROGRAM: u32 = 100_003;
+/// Both programs are served in version 3 only.
+const VERSION: u32 = 3;
+
+/// Largest read, write and directory listing offered to clients.
+const MAX_TRANSFER: u32 = 1 << 20;
+/// Largest call taken, a write of `MAX_TRANSFER` with room for its arguments.
+const MAX_CALL: usize = MAX_TRANSFER as usize + 4096;
+/// Who calls without credentials.
+const NOBODY: u32 = 65534;
+
+// ONC RPC, RFC 5531.
+const RPC_VERSION: u32 = 2;
+const CALL: u32 = 0;
+const REPLY: u32 = 1;
+const MSG_ACCEPTED: u32 = 0;
+const MSG_DENIED: u32 = 1;
+const SUCCESS: u32 = 0;
+const PROG_UNAVAIL: u32 = 1;
+const PROG_MISMATCH: u32 = 2;
+const PROC_UNAVAIL: u32 = 3;
+const GARBAGE_ARGS: u32 = 4;
+const RPC_MISMATCH: u32 = 0;
+const AUTH_ERROR: u32 = 1;
+const AUTH_BADCRED: u32 = 1;
+const AUTH_NONE: u32 = 0;
+const AUTH_UNIX: u32 = 1;
+
+// NFSv3 and its MOUNT protocol, RFC 1813.
+const MNT3_OK: u32 = 0;
+const MNT3ERR_NOENT: u32 = 2;
+const NFS3_OK: u32 = 0;
+const NFS3ERR_PERM: u32 = 1;
+const NFS3ERR_NOENT: u32 = 2;
+const NFS3ERR_IO: u32 = 5;
+const NFS3ERR_NXIO: u32 = 6;
+const NFS3ERR_ACCES: u32 = 13;
+const NFS3ERR_EXIST: u32 = 17;
+const NFS3ERR_XDEV: u32 = 18;
+const NFS3ERR_NOTDIR: u32 = 20;
+const NFS3ERR_ISDIR: u32 = 21;
+const NFS3ERR_INVAL: u32 = 22;
+const NFS3ERR_FBIG: u32 = 27;
+const NFS3ERR_NOSPC: u32 = 28;
+const NFS3ERR_ROFS: u32 = 30;
+const NFS3ERR_MLINK: u32 = 31;
+const NFS3ERR_NAMETOOLONG: u32 = 63;
+const NFS3ERR_NOTEMPTY: u32 = 66;
+const NFS3ERR_DQUOT: u32 = 69;
+const NFS3ERR_STALE: u32 = 70;
+const NFS3ERR_BADHANDLE: u32 = 10001;
+const NFS3ERR_NOT_SYNC: u32 = 10002;
+const NFS3ERR_NOTSUPP: u32 = 10004;
+const NFS3ERR_TOOSMALL: u32 = 10005;
+const NFS3ERR_SERVERFAULT: u32 = 10006;
+const NFS3ERR_BADTYPE: u32 = 10007;
+const NFS3ERR_JUKEBOX: u32 = 10008;
+
+const NF3REG: u32 = 1;
+const NF3DIR: u32 = 2;
+const NF3BLK: u32 = 3;
+const NF3CHR: u32 = 4;
+const NF3LNK: u32 = 5;
+const NF3SOCK: u32 = 6;
+const NF3FIFO: u32 = 7;
+
+const ACCESS3_READ: u32 = 0x01;
+const ACCESS3_LOOKUP: u32 = 0x02;
+const ACCESS3_MODIFY: u32 = 0x04;
+const ACCESS3_EXTEND: u32 = 0x08;
+const ACCESS3_DELETE: u32 = 0x10;
+const ACCESS3_EXECUTE: u32 = 0x20;
+
+const UNCHECKED: u32 = 0;
+const EXCLUSIVE: u32 = 2;
+const FILE_SYNC: u32 = 2;
+const SET_TO_SERVER_TIME: u32 = 1;
+const SET_TO_CLIENT_TIME: u32 = 2;
+
+const FSF3_LINK: u32 = 0x01;
+const FSF3_SYMLINK: u32 = 0x02;
+const FSF3_HOMOGENEOUS: u32 = 0x08;
+const FSF3_CANSETTIME: u32 = 0x10;
+
+/// Serves the null namespace over NFSv3 and its MOUNT protocol, both on one TCP port.
+///
+/// No portmapper is registered with, so clients are told the port themselves. Writes are
+/// stable once answered, the sink having taken them.
+pub struct Server {
+    config: Config,
+    sink: Arc<dyn DataSink>,
+    source: Arc<dyn DataSource>,
+    namespace: Mutex<Namespace>,
+    stats: Arc<Stats>,
+}
+
+/// Who a call comes from, by its AUTH_UNIX credentials.
+struct Caller {
+    uid: u32,
+    /// The primary group, then the supplementary ones.
+    groups: Vec<u32>,
+}
+
+impl Caller {
+    fn parse(flavor: u32, body: &[u8]) -> Option<Caller> {
+        match flavor {
+            AUTH_NONE => Some(Caller {
+                uid: NOBODY,
+                groups: vec![NOBODY],
+            }),
+            AUTH_UNIX => {
+                let mut body = Reader { buf: body };
+                // The stamp and the name of the client's machine.
+                body.u32()?;
+                body.opaque()?;
+                let uid = body.u32()?;
+                let mut groups = vec![body.u32()?];
+                for _ in 0..body.u32()?.min(16) {
+                    groups.push(body.u32()?);
+                }
+                Some(Caller { uid, groups })
+            }
+            _ => None,
+        }
+    }
+
+    fn gid(&self) -> u32 {
+        self.groups[0]
+    }
+}
+
+impl Server {
+    pub fn new(config: Config, sink: Box<dyn DataSink>, source: Box<dyn DataSource>) -> Server {
+        let stats = Arc::<Stats>::default();
+        let namespace = Namespace::new(stats.clone(), &config);
+        Server {
+            config,
+            sink: Arc::from(sink),
+            source: Arc::from(source),
+            namespace: Mutex::new(namespace),
+            stats,
+        }
+    }
+
+    /// Returns the counters of this server.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Answers the clients connecting to `listener`, each on a thread of its own.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming().flatten() {
+            let server = self.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr();
+                if let Err(err) = server.connection(stream) {
+                    match peer {
+                        Ok(peer) => log::warn!("NFS client {} dropped: {}", peer, err),
+                        Err(_) => log::warn!("NFS client dropped: {}", err),
+                    }
+                }
+            });
+        }
+    }
+
+    /// Closes the sink and the source.
+    pub fn close(&self) {
+        if let Err(err) = self.sink.close().and(self.source.close()) {
+            log::error!("failed to close: {}", err);
+        }
+    }
+
+    fn connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        while let Some(call) = read_record(&mut stream)? {
+            if let Some(crash) = &self.config.crash {
+                crash.check();
+            }
+            if let Some(reply) = self.call(&call) {
+                write_record(&mut stream, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers an RPC call, unless it is too mangled to be told apart from noise.
+    fn call(&self, call: &[u8]) -> Option<Vec<u8>> {
+        let mut args = Reader { buf: call };
+        let xid = args.u32()?;
+        if args.u32()? != CALL {
+            return None;
+        }
+        let mut reply = Writer::default();
+        reply.u32(xid);
+        reply.u32(REPLY);
+        if args.u32()? != RPC_VERSION {
+            reply.u32(MSG_DENIED);
+            reply.u32(RPC_MISMATCH);
+            reply.u32(RPC_VERSION);
+            reply.u32(RPC_VERSION);
+            return Some(reply.buf);
+        }
+        let (program, version, procedure) = (args.u32()?, args.u32()?, args.u32()?);
+        let Some(caller) = Caller::parse(args.u32()?, args.opaque()?) else {
+            reply.u32(MSG_DENIED);
+            reply.u32(AUTH_ERROR);
+            reply.u32(AUTH_BADCRED);
+            return Some(reply.buf);
+        };
+        // The caller's verifier, which AUTH_UNIX leaves empty.
+        args.u32()?;
+        args.opaque()?;
+
+        reply.u32(MSG_ACCEPTED);
+        reply.u32(AUTH_NONE);
+        reply.opaque(&[]);
+        let header = reply.buf.len();
+        reply.u32(SUCCESS);
+        let result = match program {
+            MOUNT_PROGRAM | NFS_PROGRAM if version != VERSION => Err(PROG_MISMATCH),
+            MOUNT_PROGRAM => self.mount(procedure, &mut args, &mut reply),
+            NFS_PROGRAM => self.nfs(procedure, &mut args, &caller, &mut reply),
+            _ => Err(PROG_UNAVAIL),
+        };
+        if let Err(stat) = result {
+            reply.buf.truncate(header);
+            reply.u32(stat);
+            if stat == PROG_MISMATCH {
+                reply.u32(VERSION);
+                reply.u32(VERSION);
+            }
+        }
+        Some(reply.buf)
+    }
+
+    /// Runs a procedure of the MOUNT protocol, which only exports the root.
+    fn mount(&self, procedure: u32, args: &mut Reader, out: &mut Writer) -> Result<(), u32> {
+        match procedure {
+            // NULL and UMNTALL; nothing is kept about clients.
+            0 | 4 => {}
+            // MNT
+            1 => {
+                let path = args.opaque().ok_or(GARBAGE_ARGS)?;
+                if path != b"/" {
+                    out.u32(MNT3ERR_NOENT);
+                    return Ok(());
+                }
+                out.u32(MNT3_OK);
+                out.opaque(&handle(&self.namespace.lock().unwrap(), ROOT));
+                out.u32(2);
+                out.u32(AUTH_UNIX);
+                out.u32(AUTH_NONE);
+            }
+            // DUMP, with no mounts to list.
+            2 => out.bool(false),
+            // UMNT
+            3 => {
+                args.opaque().ok_or(GARBAGE_ARGS)?;
+            }
+            // EXPORT, the root to anyone.
+            5 => {
+                out.bool(true);
+                out.opaque(b"/");
+                out.bool(false);
+                out.bool(false);
+            }
+            _ => return Err(PROC_UNAVAIL),
+        }
+        Ok(())
+    }
+
+    /// Runs a procedure of NFSv3.
+    fn nfs(
+        &self,
+        procedure: u32,
+        args: &mut Reader,
+        caller: &Caller,
+        out: &mut Writer,
+    ) -> Result<(), u32> {
+        let parsed = match procedure {
+            0 => Some(()),
+            1 => self.getattr(args, out),
+            2 => self.setattr(args, out),
+            3 => self.lookup(args, caller, out),
+            4 => self.access(args, caller, out),
+            5 => self.readlink(args, out),
+            6 => self.read(args, caller, out),
+            7 => self.write(args, caller, out),
+            8 => self.create(args, caller, out),
+            9 => self.mkdir(args, caller, out),
+            10 => self.symlink(args, caller, out),
+            11 => self.mknod(args, caller, out),
+            12 => self.remove(args, caller, out, false),
+            13 => self.remove(args, caller, out, true),
+            14 => self.rename(args, caller, out),
+            15 => self.link(args, caller, out),
+            16 => self.readdir(args, caller, out, false),
+            17 => self.readdir(args, caller, out, true),
+            18 => self.fsstat(args, out),
+            19 => self.fsinfo(args, out),
+            20 => self.pathconf(args, out),
+            21 => self.commit(args, out),
+            _ => return Err(PROC_UNAVAIL),
+        };
+        parsed.ok_or(GARBAGE_ARGS)
+    }
+
+    fn getattr(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let namespace = self.namespace.lock().unwrap();
+        match resolve(&namespace, fh) {
+            Ok(ino) => {
+                out.u32(NFS3_OK);
+                fattr(out, &namespace, &namespace.attr(ino).unwrap());
+            }
+            Err(status) => out.u32(status),
+        }
+        Some(())
+    }
+
+    /// Leaves the file as it is, like a mount does.
+    fn setattr(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let mut changes = sattr(args)?;
+        let guard = optional(args, time)?;
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let ino = resolve(&namespace, fh);
+        let result = ino.and_then(|ino| {
+            let ctime = nfstime(namespace.attr(ino).unwrap().ctime);
+            if guard.is_some_and(|guard| guard != ctime) {
+                return Err(NFS3ERR_NOT_SYNC);
+            }
+            if self.config.read_only {
+                return Err(NFS3ERR_ROFS);
+            }
+            if let Some(size) = changes.size.take() {
+                truncate(&self.config, &mut namespace, ino, size).map_err(status)?;
+            }
+            // Modes and owners always stay, times only with the rest of the metadata.
+            if !self.config.metadata {
+                (changes.atime, changes.mtime) = (None, None);
+            }
+            namespace.set_attr(ino, changes).map_err(status)?;
+            Ok(())
+        });
+        out.u32(status_of(&result));
+        wcc(out, &namespace, ino.ok());
+        Some(())
+    }
+
+    fn lookup(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, dir);
+        let result = dir.and_then(|dir| {
+            self.check_search(&namespace, dir, caller)?;
+            if let Some(Action::Errno(errno)) = rules::find(&self.config.rules, name) {
+                return Err(status(errno));
+            }
+            namespace.child(dir, name).map_err(status)
+        });
+        out.u32(status_of(&result));
+        if let Ok(ino) = result {
+            out.opaque(&handle(&namespace, ino));
+            post_op_attr(out, &namespace, Some(ino));
+        }
+        post_op_attr(out, &namespace, dir.ok());
+        Some(())
+    }
+
+    fn access(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (fh, requested) = (args.opaque()?, args.u32()?);
+        let namespace = self.namespace.lock().unwrap();
+        let ino = match resolve(&namespace, fh) {
+            Ok(ino) => ino,
+            Err(status) => {
+                out.u32(status);
+                post_op_attr(out, &namespace, None);
+                return Some(());
+            }
+        };
+
+        let mut granted = 0;
+        if self.config.callers.permits(caller.uid, caller.gid()) {
+            for (bits, mask) in [
+                (ACCESS3_READ, R_OK),
+                (ACCESS3_LOOKUP | ACCESS3_EXECUTE, X_OK),
+                (ACCESS3_MODIFY | ACCESS3_EXTEND | ACCESS3_DELETE, W_OK),
+            ] {
+                if mask == W_OK && self.config.read_only {
+                    continue;
+                }
+                if requested & bits != 0 && permits(&namespace, ino, caller, mask) {
+                    granted |= requested & bits;
+                }
+            }
+        }
+        out.u32(NFS3_OK);
+        post_op_attr(out, &namespace, Some(ino));
+        out.u32(granted);
+        Some(())
+    }
+
+    fn readlink(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let namespace = self.namespace.lock().unwrap();
+        let ino = resolve(&namespace, fh);
+        let result = ino.and_then(|ino| match namespace.get(ino).unwrap() {
+            inode if inode.kind() == FileType::Symlink => Ok(inode.target.as_bytes()),
+            _ => Err(NFS3ERR_INVAL),
+        });
+        out.u32(status_of(&result));
+        post_op_attr(out, &namespace, ino.ok());
+        if let Ok(target) = result {
+            out.opaque(target);
+        }
+        Some(())
+    }
+
+    fn read(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (fh, offset, count) = (args.opaque()?, args.u64()?, args.u32()?);
+        let checked = {
+            let namespace = self.namespace.lock().unwrap();
+            resolve(&namespace, fh).and_then(|ino| {
+                self.check_file(&namespace, ino, caller, R_OK)?;
+                if interrupted(&self.config, Call::Read) {
+                    return Err(NFS3ERR_JUKEBOX);
+                }
+                let offset = i64::try_from(offset).map_err(|_| NFS3ERR_INVAL)?;
+                let contents = self.kept(&namespace, ino);
+                let filled = self.filled(&namespace, ino);
+                let pipe = pipe(&self.config, namespace.get(ino).unwrap());
+                Ok((ino, offset, contents, filled, pipe))
+            })
+        };
+        // The source is not waited for with the namespace locked.
+        let result = checked.and_then(|(ino, offset, contents, filled, pipe)| {
+            let count = count.min(MAX_TRANSFER);
+            // Pipes are not waited for either, an empty one reading as its end.
+            let piped = pipe.map(|pipe| pipe.take(count));
+            let kept = contents.and_then(|(kept, _)| kept.read(offset as u64, count));
+            let fill =
+                |(pattern, len): (Arc<Pattern>, u64)| pattern.fill(offset as u64, count, len);
+            match piped.or(kept).or_else(|| filled.map(fill)) {
+                Some(data) => Ok(data),
+                None => self
+                    .source
+                    .read(ino, offset, count)
+                    .map_err(|err| status(errno(err))),
+            }
+        });
+
+        let namespace = self.namespace.lock().unwrap();
+        out.u32(status_of(&result));
+        post_op_attr(out, &namespace, resolve(&namespace, fh).ok());
+        if let Ok(data) = result {
+            self.stats.record_read(caller.uid, 0, data.len() as u64);
+            out.u32(data.len() as u32);
+            // A source only comes up short at its end.
+            out.bool(data.len() < count as usize);
+            out.opaque(&data);
+        }
+        Some(())
+    }
+
+    fn write(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (fh, offset, count) = (args.opaque()?, args.u64()?, args.u32()?);
+        // How stable the client asks the write to be, which every write is anyway.
+        args.u32()?;
+        let data = args.opaque()?;
+        let data = &data[..data.len().min(count as usize)];
+
+        let checked = {
+            let namespace = self.namespace.lock().unwrap();
+            resolve(&namespace, fh).and_then(|ino| {
+                if self.config.read_only {
+                    return Err(NFS3ERR_ROFS);
+                }
+                self.check_file(&namespace, ino, caller, W_OK)?;
+                if interrupted(&self.config, Call::Write) {
+                    return Err(NFS3ERR_JUKEBOX);
+                }
+                let end = offset.checked_add(data.len() as u64);
+                if end.is_none_or(|end| end > i64::MAX as u64) {
+                    return Err(NFS3ERR_FBIG);
+                }
+                let inode = namespace.get(ino).unwrap();
+                match rules::find(&self.config.rules, &inode.name) {
+                    Some(Action::Errno(errno)) => return Err(status(errno)),
+                    Some(Action::Quota(quota)) if offset + data.len() as u64 > quota => {
+                        return Err(NFS3ERR_DQUOT)
+                    }
+                    _ => {}
+                }
+                let contents = self.kept(&namespace, ino);
+                let pipe = pipe(&self.config, inode);
+                Ok((ino, inode.size.clone(), inode.stats.clone(), contents, pipe))
+            })
+        };
+        // Nor is the sink.
+        let result = checked.and_then(|(ino, size, file_stats, contents, pipe)| {
+            let written = self
+                .sink
+                .write(ino, offset as i64, data)
+                .map_err(|err| status(errno(err)))?;
+            account(&size, self.config.size_accounting, offset, written as u64);
+            file_stats.record_write(&data[..written.min(data.len())]);
+            if let Some((contents, keep)) = contents {
+                contents.write(keep, offset, &data[..written.min(data.len())]);
+            }
+            if let Some(pipe) = pipe {
+                pipe.write(&data[..written.min(data.len())]);
+            }
+            self.stats.record_write(caller.uid, 0, written as u64);
+            Ok(written)
+        });
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let ino = resolve(&namespace, fh).ok();
+        if let Some(ino) = ino.filter(|_| self.config.metadata && result.is_ok()) {
+            namespace.modified(ino);
+        }
+        out.u32(status_of(&result));
+        wcc(out, &namespace, ino);
+        if let Ok(written) = result {
+            out.u32(written as u32);
+            out.u32(FILE_SYNC);
+            out.fixed(&verifier(&namespace));
+        }
+        Some(())
+    }
+
+    fn create(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let how = args.u32()?;
+        let mode = match how {
+            // Exclusive creation carries a verifier instead, which is not kept.
+            EXCLUSIVE => {
+                args.fixed(8)?;
+                None
+            }
+            _ => sattr(args)?.perm,
+        };
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, dir);
+        let result = dir.and_then(|dir| {
+            let perm = mode.unwrap_or(0o644);
+            let kind = FileType::RegularFile;
+            match self.make_node(&mut namespace, dir, name, kind, perm, caller) {
+                // Unchecked creation takes the file that is there already.
+                Err(NFS3ERR_EXIST) if how == UNCHECKED => match namespace.child(dir, name) {
+                    Ok(ino) if namespace.is_kind(ino, kind) => Ok(ino),
+                    _ => Err(NFS3ERR_EXIST),
+                },
+                result => result,
+            }
+        });
+        created(out, &namespace, result, dir.ok());
+        Some(())
+    }
+
+    fn mkdir(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let mode = sattr(args)?.perm;
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, dir);
+        let result = dir.and_then(|dir| {
+            let perm = mode.unwrap_or(0o755);
+            self.make_node(&mut namespace, dir, name, FileType::Directory, perm, caller)
+        });
+        created(out, &namespace, result, dir.ok());
+        Some(())
+    }
+
+    fn symlink(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        sattr(args)?;
+        let target = OsStr::from_bytes(args.opaque()?);
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, dir);
+        let result = dir.and_then(|dir| {
+            self.check_modify(&namespace, dir, caller)?;
+            if !self.config.dynamic {
+                return Err(NFS3ERR_PERM);
+            }
+            self.check_new_name(name)?;
+            let attr = namespace
+                .symlink(dir, name, target, caller.uid, caller.gid())
+                .map_err(status)?;
+            namespace.forget(attr.ino, 1);
+            Ok(attr.ino)
+        });
+        created(out, &namespace, result, dir.ok());
+        Some(())
+    }
+
+    /// Makes another /dev/null, the only device there can be.
+    fn mknod(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let kind = args.u32()?;
+        let (mode, rdev) = match kind {
+            NF3CHR | NF3BLK => (sattr(args)?.perm, Some((args.u32()?, args.u32()?))),
+            NF3SOCK | NF3FIFO => (sattr(args)?.perm, None),
+            _ => (None, None),
+        };
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, dir);
+        let result = dir.and_then(|dir| match (kind, rdev) {
+            (NF3CHR, Some((major, minor))) if major << 8 | minor == NULL_RDEV => {
+                let perm = mode.unwrap_or(0o666);
+                self.make_node(
+                    &mut namespace,
+                    dir,
+                    name,
+                    FileType::CharDevice,
+                    perm,
+                    caller,
+                )
+            }
+            (NF3REG | NF3DIR | NF3LNK, _) => Err(NFS3ERR_BADTYPE),
+            _ => Err(NFS3ERR_PERM),
+        });
+        created(out, &namespace, result, dir.ok());
+        Some(())
+    }
+
+    fn remove(
+        &self,
+        args: &mut Reader,
+        caller: &Caller,
+        out: &mut Writer,
+        is_dir: bool,
+    ) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let mut namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, dir);
+        let result = dir.and_then(|dir| {
+            self.check_modify(&namespace, dir, caller)?;
+            if !self.config.dynamic {
+                return Err(NFS3ERR_PERM);
+            }
+            match is_dir {
+                true => namespace.rmdir(dir, name),
+                false => namespace.unlink(dir, name),
+            }
+            .map_err(status)
+        });
+        out.u32(status_of(&result));
+        wcc(out, &namespace, dir.ok());
+        Some(())
+    }
+
+    fn rename(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let (newdir, newname) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let mut namespace = self.namespace.lock().unwrap();
+        let (dir, newdir) = (resolve(&namespace, dir), resolve(&namespace, newdir));
+        let result = dir.and_then(|dir| {
+            let newdir = newdir?;
+            self.check_modify(&namespace, dir, caller)?;
+            self.check_modify(&namespace, newdir, caller)?;
+            if !self.config.dynamic {
+                return Err(NFS3ERR_PERM);
+            }
+            self.check_new_name(newname)?;
+            namespace
+                .rename(dir, name, newdir, newname, 0)
+                .map_err(status)
+        });
+        out.u32(status_of(&result));
+        wcc(out, &namespace, dir.ok());
+        wcc(out, &namespace, newdir.ok());
+        Some(())
+    }
+
+    fn link(&self, args: &mut Reader, caller: &Caller, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let (dir, name) = (args.opaque()?, OsStr::from_bytes(args.opaque()?));
+        let mut namespace = self.namespace.lock().unwrap();
+        let (ino, dir) = (resolve(&namespace, fh), resolve(&namespace, dir));
+        let result = ino.and_then(|ino| {
+            let dir = dir?;
+            self.check_modify(&namespace, dir, caller)?;
+            if !self.config.dynamic {
+                return Err(NFS3ERR_PERM);
+            }
+            self.check_new_name(name)?;
+            namespace.link(ino, dir, name).map_err(status)?;
+            namespace.forget(ino, 1);
+            Ok(())
+        });
+        out.u32(status_of(&result));
+        post_op_attr(out, &namespace, ino.ok());
+        wcc(out, &namespace, dir.ok());
+        Some(())
+    }
+
+    /// Lists a directory, . and .. first, the index of the next entry serving as cookie.
+    fn readdir(
+        &self,
+        args: &mut Reader,
+        caller: &Caller,
+        out: &mut Writer,
+        plus: bool,
+    ) -> Option<()> {
+        let (fh, cookie) = (args.opaque()?, args.u64()?);
+        // The cookie verifier, which stays zero as cookies never go stale.
+        args.fixed(8)?;
+        let (dircount, maxcount) = match plus {
+            true => (args.u32()?, args.u32()?),
+            false => {
+                let count = args.u32()?;
+                (count, count)
+            }
+        };
+
+        let namespace = self.namespace.lock().unwrap();
+        let dir = resolve(&namespace, fh);
+        let result = dir.and_then(|dir| {
+            if !namespace.is_kind(dir, FileType::Directory) {
+                return Err(NFS3ERR_NOTDIR);
+            }
+            match permits(&namespace, dir, caller, R_OK) {
+                true => Ok(dir),
+                false => Err(NFS3ERR_ACCES),
+            }
+        });
+        let dir = match result {
+            Ok(dir) => dir,
+            Err(status) => {
+                out.u32(status);
+                post_op_attr(out, &namespace, dir.ok());
+                return Some(());
+            }
+        };
+
+        let inode = namespace.get(dir).unwrap();
+        let entries = [(OsStr::new("."), dir), (OsStr::new(".."), inode.parent)]
+            .into_iter()
+            .chain(
+                inode
+                    .entries
+                    .iter()
+                    .map(|(name, ino)| (name.as_os_str(), *ino)),
+            );
+        let mut listed = Writer::default();
+        // The status, the attributes, the cookie verifier, the end of the list and eof.
+        let mut size = 4 + 88 + 8 + 4 + 4;
+        let mut names = 0;
+        let mut eof = true;
+        for (i, (name, ino)) in entries.enumerate().skip(cookie as usize) {
+            let entry = 8 + 4 + padded(name.len()) + 8;
+            names += entry;
+            // A handle and its attributes come along with every entry of READDIRPLUS.
+            size += 4 + entry + if plus { 88 + 24 } else { 0 };
+            if names > dircount as usize || size > maxcount as usize {
+                eof = false;
+                break;
+            }
+            listed.bool(true);
+            listed.u64(ino);
+            listed.opaque(name.as_bytes());
+            listed.u64(i as u64 + 1);
+            if plus {
+                post_op_attr(&mut listed, &namespace, Some(ino));
+                listed.bool(true);
+                listed.opaque(&handle(&namespace, ino));
+            }
+        }
+        if listed.buf.is_empty() && !eof {
+            out.u32(NFS3ERR_TOOSMALL);
+            post_op_attr(out, &namespace, Some(dir));
+            return Some(());
+        }
+
+        out.u32(NFS3_OK);
+        post_op_attr(out, &namespace, Some(dir));
+        out.fixed(&[0; 8]);
+        out.buf.extend_from_slice(&listed.buf);
+        out.bool(false);
+        out.bool(eof);
+        Some(())
+    }
+
+    /// Reports no space at all, there being none to run out of.
+    fn fsstat(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let namespace = self.namespace.lock().unwrap();
+        let ino = resolve(&namespace, fh);
+        out.u32(status_of(&ino));
+        post_op_attr(out, &namespace, ino.ok());
+        if ino.is_ok() {
+            for _ in 0..6 {
+                out.u64(0);
+            }
+            out.u32(0);
+        }
+        Some(())
+    }
+
+    fn fsinfo(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let namespace = self.namespace.lock().unwrap();
+        let ino = resolve(&namespace, fh);
+        out.u32(status_of(&ino));
+        post_op_attr(out, &namespace, ino.ok());
+        if ino.is_ok() {
+            // The largest, preferred and multiple sizes of reads, then of writes.
+            for size in [MAX_TRANSFER, MAX_TRANSFER, 4096] {
+                out.u32(size);
+            }
+            for size in [MAX_TRANSFER, MAX_TRANSFER, 4096] {
+                out.u32(size);
+            }
+            out.u32(MAX_TRANSFER);
+            out.u64(i64::MAX as u64);
+            // Times are kept to the nanosecond.
+            out.u32(0);
+            out.u32(1);
+            out.u32(FSF3_LINK | FSF3_SYMLINK | FSF3_HOMOGENEOUS | FSF3_CANSETTIME);
+        }
+        Some(())
+    }
+
+    fn pathconf(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        let namespace = self.namespace.lock().unwrap();
+        let ino = resolve(&namespace, fh);
+        out.u32(status_of(&ino));
+        post_op_attr(out, &namespace, ino.ok());
+        if ino.is_ok() {
+            let name_max = self.config.name_policy.max_length.unwrap_or(255);
+            out.u32(u32::MAX);
+            out.u32(name_max as u32);
+            // Long names are refused rather than truncated, and only root may chown.
+            out.bool(true);
+            out.bool(true);
+            out.bool(self.config.case_insensitive);
+            out.bool(true);
+        }
+        Some(())
+    }
+
+    fn commit(&self, args: &mut Reader, out: &mut Writer) -> Option<()> {
+        let fh = args.opaque()?;
+        // The range to commit, the whole file being flushed.
+        args.u64()?;
+        args.u32()?;
+        let ino = resolve(&self.namespace.lock().unwrap(), fh);
+        if let (Ok(_), Some(delay)) = (&ino, &self.config.fsync_delay) {
+            delay.wait();
+        }
+        let result = ino.and_then(|ino| self.sink.flush(ino).map_err(|err| status(errno(err))));
+
+        let namespace = self.namespace.lock().unwrap();
+        out.u32(status_of(&result));
+        wcc(out, &namespace, ino.ok());
+        if result.is_ok() {
+            out.fixed(&verifier(&namespace));
+            // Only answered once this call is, dying on the next one.
+            if let Some(crash) = &self.config.crash {
+                crash.fsynced();
+            }
+        }
+        Some(())
+    }
+
+    /// The contents kept of `ino` and how, if any are.
+    fn kept(&self, namespace: &Namespace, ino: u64) -> Option<(Arc<Contents>, Keep)> {
+        let inode = namespace.get(ino)?;
+        keep(&self.config, &inode.name).map(|keep| (inode.contents.clone(), keep))
+    }
+
+    /// The pattern `ino` reads as and the size it fills, unless reads go to the source.
+    fn filled(&self, namespace: &Namespace, ino: u64) -> Option<(Arc<Pattern>, u64)> {
+        let inode = namespace.get(ino)?;
+        if endless(&self.config, &inode.name) {
+            return Some((self.config.fill.clone().unwrap_or_default(), u64::MAX));
+        }
+        let pattern = self.config.fill.clone()?;
+        Some((pattern, inode.size.load(Ordering::Relaxed)))
+    }
+
+    /// Checks that `caller` may read or write the regular file `ino`, as `mask` says.
+    fn check_file(
+        &self,
+        namespace: &Namespace,
+        ino: u64,
+        caller: &Caller,
+        mask: c_int,
+    ) -> Result<(), u32> {
+        if !self.config.callers.permits(caller.uid, caller.gid()) {
+            return Err(NFS3ERR_ACCES);
+        }
+        match namespace.get(ino).unwrap().kind() {
+            FileType::RegularFile => {}
+            FileType::Directory => return Err(NFS3ERR_ISDIR),
+            _ => return Err(NFS3ERR_INVAL),
+        }
+        match permits(namespace, ino, caller, mask) {
+            true => Ok(()),
+            false => Err(NFS3ERR_ACCES),
+        }
+    }
+
+    /// Checks that `caller` may look names up in `dir`.
+    fn check_search(&self, namespace: &Namespace, dir: u64, caller: &Caller) -> Result<(), u32> {
+        if !namespace.is_kind(dir, FileType::Directory) {
+            return Err(NFS3ERR_NOTDIR);
+        }
+        match permits(namespace, dir, caller, X_OK) {
+            true => Ok(()),
+            false => Err(NFS3ERR_ACCES),
+        }
+    }
+
+    /// Checks that `caller` may add and remove names in `dir`.
+    fn check_modify(&self, namespace: &Namespace, dir: u64, caller: &Caller) -> Result<(), u32> {
+        if self.config.read_only {
+            return Err(NFS3ERR_ROFS);
+        }
+        if !self.config.callers.permits(caller.uid, caller.gid()) {
+            return Err(NFS3ERR_ACCES);
+        }
+        self.check_search(namespace, dir, caller)?;
+        match permits(namespace, dir, caller, W_OK) {
+            true => Ok(()),
+            false => Err(NFS3ERR_ACCES),
+        }
+    }
+
+    /// Checks a name given to a new file against the name policy and the rules.
+    fn check_new_name(&self, name: &OsStr) -> Result<(), u32> {
+        self.config.name_policy.check(name).map_err(status)?;
+        match rules::find(&self.config.rules, name) {
+            Some(Action::Errno(errno)) => Err(status(errno)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates a node in dynamic mode, or points at the null file in its place otherwise.
+    fn make_node(
+        &self,
+        namespace: &mut Namespace,
+        dir: u64,
+        name: &OsStr,
+        kind: FileType,
+        perm: u16,
+        caller: &Caller,
+    ) -> Result<u64, u32> {
+        self.check_modify(namespace, dir, caller)?;
+        self.check_new_name(name)?;
+        if !self.config.dynamic {
+            return match namespace.child(dir, name) {
+                Ok(NULL) if kind == FileType::RegularFile => Err(NFS3ERR_EXIST),
+                _ => Err(NFS3ERR_PERM),
+            };
+        }
+        let attr = namespace
+            .create(dir, name, kind, perm & 0o7777, caller.uid, caller.gid())
+            .map_err(status)?;
+        // Clients hold no references, so the inode lives as long as its names do.
+        namespace.forget(attr.ino, 1);
+        Ok(attr.ino)
+    }
+}
+
+/// Reads the next record of the stream, or returns `None` once the client hung up.
+fn read_record(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut record = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        match stream.read_exact(&mut header) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && record.is_empty() => {
+                return Ok(None)
+            }
+            result => result?,
+        }
+        let header = u32::from_be_bytes(header);
+        let len = (header & 0x7fff_ffff) as usize;
+        if record.len() + len > MAX_CALL {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "call too large"));
+        }
+        let start = record.len();
+        record.resize(start + len, 0);
+        stream.read_exact(&mut record[start..])?;
+        // The high bit marks the last fragment.
+        if header & 0x8000_0000 != 0 {
+            return Ok(Some(record));
+        }
+    }
+}
+
+/// Writes `reply` to the stream as a single fragment.
+fn write_record(stream: &mut TcpStream, reply: &[u8]) -> io::Result<()> {
+    let mut record = Vec::with_capacity(4 + reply.len());
+    record.extend_from_slice(&(0x8000_0000 | reply.len() as u32).to_be_bytes());
+    record.extend_from_slice(reply);
+    stream.write_all(&record)
+}
+
+/// Decodes XDR, coming up with `None` once the data runs out.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn fixed(&mut self, len: usize) -> Option<&'a [u8]> {
+        let data = self.buf.get(..len)?;
+        self.buf = self.buf.get(padded(len)..)?;
+        Some(data)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.fixed(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.fixed(8)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        Some(self.u32()? != 0)
+    }
+
+    fn opaque(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()?;
+        self.fixed(len as usize)
+    }
+}
+
+/// Encodes XDR.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn fixed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.buf
+            .resize(self.buf.len() + padded(data.len()) - data.len(), 0);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u32(value as u32);
+    }
+
+    fn opaque(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.fixed(data);
+    }
+}
+
+/// Rounds `len` up to the 4 bytes XDR aligns everything to.
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Returns the file handle of `ino`: the generation of the namespace, then the inode number.
+fn handle(namespace: &Namespace, ino: u64) -> [u8; 16] {
+    let mut fh = [0; 16];
+    fh[..8].copy_from_slice(&namespace.generation().to_be_bytes());
+    fh[8..].copy_from_slice(&ino.to_be_bytes());
+    fh
+}
+
+/// Returns the inode a file handle refers to, if it is still there.
+fn resolve(namespace: &Namespace, fh: &[u8]) -> Result<u64, u32> {
+    let fh: [u8; 16] = fh.try_into().map_err(|_| NFS3ERR_BADHANDLE)?;
+    let generation = u64::from_be_bytes(fh[..8].try_into().unwrap());
+    let ino = u64::from_be_bytes(fh[8..].try_into().unwrap());
+    // Handles of an earlier server are stale, even where the same inode exists again.
+    if generation != namespace.generation() || namespace.get(ino).is_none() {
+        return Err(NFS3ERR_STALE);
+    }
+    Ok(ino)
+}
+
+/// Returns the write verifier, which changes with every server so clients resend what
+/// the one before may not have committed.
+fn verifier(namespace: &Namespace) -> [u8; 8] {
+    namespace.generation().to_be_bytes()
+}
+
+/// Reports whether `caller` is granted every bit of `mask` on `ino`.
+fn permits(namespace: &Namespace, ino: u64, caller: &Caller, mask: c_int) -> bool {
+    let Some(inode) = namespace.get(ino) else {
+        return false;
+    };
+    let acl = inode.xattrs.get(OsStr::new(acl::ACCESS));
+    let entries = acl.and_then(|acl| acl::parse(acl));
+    acl::check(
+        entries.as_deref(),
+        &inode.attr(),
+        caller.uid,
+        &caller.groups,
+        mask as u32,
+    )
+}
+
+/// Reads the attributes to set, returning only the mode, which is all a new file takes.
+fn sattr(args: &mut Reader) -> Option<SetAttr> {
+    Some(SetAttr {
+        perm: optional(args, Reader::u32)?.map(|mode| mode as u16 & 0o7777),
+        uid: optional(args, Reader::u32)?,
+        gid: optional(args, Reader::u32)?,
+        size: optional(args, Reader::u64)?,
+        atime: set_time(args)?,
+        mtime: set_time(args)?,
+    })
+}
+
+/// Reads a value preceded by whether it is there.
+fn optional<'a, T>(
+    args: &mut Reader<'a>,
+    value: fn(&mut Reader<'a>) -> Option<T>,
+) -> Option<Option<T>> {
+    match args.bool()? {
+        true => value(args).map(Some),
+        false => Some(None),
+    }
+}
+
+fn set_time(args: &mut Reader) -> Option<Option<SystemTime>> {
+    match args.u32()? {
+        SET_TO_SERVER_TIME => Some(Some(SystemTime::now())),
+        SET_TO_CLIENT_TIME => {
+            let (seconds, nanoseconds) = time(args)?;
+            let since = Duration::new(seconds as u64, nanoseconds);
+            Some(Some(SystemTime::UNIX_EPOCH + since))
+        }
+        _ => Some(None),
+    }
+}
+
+fn time(args: &mut Reader) -> Option<(u32, u32)> {
+    Some((args.u32()?, args.u32()?))
+}
+
+/// Converts a time to the seconds and nanoseconds NFS counts since the epoch.
+fn nfstime(time: SystemTime) -> (u32, u32) {
+    let since = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    (since.as_secs() as u32, since.subsec_nanos())
+}
+
+fn fattr(out: &mut Writer, namespace: &Namespace, attr: &FileAttr) {
+    out.u32(match attr.kind {
+        FileType::RegularFile => NF3REG,
+        FileType::Directory => NF3DIR,
+        FileType::BlockDevice => NF3BLK,
+        FileType::CharDevice => NF3CHR,
+        FileType::Symlink => NF3LNK,
+        FileType::Socket => NF3SOCK,
+        FileType::NamedPipe => NF3FIFO,
+    });
+    out.u32(attr.perm as u32 & 0o7777);
+    out.u32(attr.nlink);
+    out.u32(attr.uid);
+    out.u32(attr.gid);
+    out.u64(attr.size);
+    out.u64(attr.blocks * 512);
+    out.u32(attr.rdev >> 8);
+    out.u32(attr.rdev & 0xff);
+    out.u64(namespace.generation());
+    out.u64(attr.ino);
+    for time in [attr.atime, attr.mtime, attr.ctime] {
+        let (secs, nanos) = nfstime(time);
+        out.u32(secs);
+        out.u32(nanos);
+    }
+}
+
+/// Writes the attributes of `ino`, if there is such an inode.
+fn post_op_attr(out: &mut Writer, namespace: &Namespace, ino: Option<u64>) {
+    match ino.and_then(|ino| namespace.attr(ino)) {
+        Some(attr) => {
+            out.bool(true);
+            fattr(out, namespace, &attr);
+        }
+        None => out.bool(false),
+    }
+}
+
+/// Writes the attributes of `ino` after a change, none having been kept from before it.
+fn wcc(out: &mut Writer, namespace: &Namespace, ino: Option<u64>) {
+    out.bool(false);
+    post_op_attr(out, namespace, ino);
+}
+
+/// Answers a call creating a node in `dir`.
+fn created(out: &mut Writer, namespace: &Namespace, result: Result<u64, u32>, dir: Option<u64>) {
+    out.u32(status_of(&result));
+    if let Ok(ino) = result {
+        out.bool(true);
+        out.opaque(&handle(namespace, ino));
+        post_op_attr(out, namespace, Some(ino));
+    }
+    wcc(out, namespace, dir);
+}
+
+fn status_of<T>(result: &Result<T, u32>) -> u32 {
+    match result {
+        Ok(_) => NFS3_OK,
+        Err(status) => *status,
+    }
+}
+
+/// Maps an errno to the NFS status closest to it.
+fn status(errno: c_int) -> u32 {
+    match errno {
+        EPERM => NFS3ERR_PERM,
+        ENOENT => NFS3ERR_NOENT,
+        EIO => NFS3ERR_IO,
+        ENXIO => NFS3ERR_NXIO,
+        EACCES => NFS3ERR_ACCES,
+        EEXIST => NFS3ERR_EXIST,
+        EXDEV => NFS3ERR_XDEV,
+        ENOTDIR => NFS3ERR_NOTDIR,
+        EISDIR => NFS3ERR_ISDIR,
+        EINVAL => NFS3ERR_INVAL,
+        EFBIG => NFS3ERR_FBIG,
+        ENOSPC => NFS3ERR_NOSPC,
+        EROFS => NFS3ERR_ROFS,
+        EMLINK => NFS3ERR_MLINK,
+        ENAMETOOLONG => NFS3ERR_NAMETOOLONG,
+        ENOTEMPTY => NFS3ERR_NOTEMPTY,
+        EDQUOT => NFS3ERR_DQUOT,
+        ESTALE => NFS3ERR_STALE,
+        EOPNOTSUPP => NFS3ERR_NOTSUPP,
+        // The closest to a call to try again, which clients do after a while.
+        EINTR => NFS3ERR_JUKEBOX,
+        _ => NFS3ERR_SERVERFAULT,
+    }
+}
@@ -0,0 +1,1025 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use fuser::{FileAttr, FileType};
+use libc::{
+    c_int, EACCES, EBADF, EDQUOT, EINTR, EINVAL, EISDIR, ENOENT, ENOTDIR, EOPNOTSUPP, EPERM, EROFS,
+};
+
+use crate::callers;
+use crate::config::Config;
+use crate::contents::{Contents, Keep};
+use crate::fs::{account, endless, errno, interrupted, keep, pipe, truncate};
+use crate::interrupt::Call;
+use crate::namespace::{Namespace, SetAttr, NULL, NULL_RDEV, ROOT};
+use crate::pattern::Pattern;
+use crate::rules::{self, Action};
+use crate::sink::{DataSink, DataSource};
+use crate::stats::Stats;
+
+/// Largest message offered to clients.
+const MAX_MSIZE: u32 = 1 << 20;
+/// Room taken by the header of a read or write, ahead of the data.
+const IOHDRSZ: u32 = 24;
+const VERSION: &[u8] = b"9P2000.L";
+/// Fid standing for none at all.
+const NOFID: u32 = !0;
+/// Most names a single walk may take.
+const MAX_WELEM: usize = 16;
+
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TSYMLINK: u8 = 16;
+const TMKNOD: u8 = 18;
+const TRENAME: u8 = 20;
+const TREADLINK: u8 = 22;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TXATTRWALK: u8 = 30;
+const TXATTRCREATE: u8 = 32;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLOCK: u8 = 52;
+const TGETLOCK: u8 = 54;
+const TLINK: u8 = 70;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TAUTH: u8 = 102;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+const RLERROR: u8 = 7;
+
+const QTDIR: u8 = 0x80;
+const QTSYMLINK: u8 = 0x02;
+const QTFILE: u8 = 0;
+
+// Modes and directory entry types as Linux has them, which the protocol carries.
+const S_IFDIR: u32 = 0o040000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFMT: u32 = 0o170000;
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+const O_ACCMODE: u32 = 3;
+const O_TRUNC: u32 = 0o1000;
+const AT_REMOVEDIR: u32 = 0x200;
+
+// Which attributes Tsetattr changes, and whether it gives the times or asks for the current one.
+const SETATTR_MODE: u32 = 0x1;
+const SETATTR_UID: u32 = 0x2;
+const SETATTR_GID: u32 = 0x4;
+const SETATTR_SIZE: u32 = 0x8;
+const SETATTR_ATIME: u32 = 0x10;
+const SETATTR_MTIME: u32 = 0x20;
+const SETATTR_ATIME_SET: u32 = 0x80;
+const SETATTR_MTIME_SET: u32 = 0x100;
+
+/// Every attribute Tgetattr may ask for but the generation and the data version.
+const GETATTR_VALID: u64 = 0xfff;
+const V9FS_MAGIC: u32 = 0x0102_1997;
+const LOCK_SUCCESS: u8 = 0;
+const F_UNLCK: u8 = 2;
+
+/// Serves the null namespace over 9P2000.L, for virtual machines to mount without FUSE.
+///
+/// Clients check permissions themselves, as they do with `access=user` or `access=client`;
+/// the server only refuses what a mount refuses regardless of who asks, and the callers
+/// the caller lists deny when they attach.
+pub struct Server {
+    config: Config,
+    sink: Arc<dyn DataSink>,
+    source: Arc<dyn DataSource>,
+    namespace: Mutex<Namespace>,
+    stats: Arc<Stats>,
+}
+
+/// What a fid of a connection refers to.
+#[derive(Clone, Copy)]
+struct Fid {
+    ino: u64,
+    /// The user that attached, on whose behalf the fid is used.
+    uid: u32,
+}
+
+/// State of a connection, released with it.
+struct Session {
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Server {
+    pub fn new(config: Config, sink: Box<dyn DataSink>, source: Box<dyn DataSource>) -> Server {
+        let stats = Arc::<Stats>::default();
+        let namespace = Namespace::new(stats.clone(), &config);
+        Server {
+            config,
+            sink: Arc::from(sink),
+            source: Arc::from(source),
+            namespace: Mutex::new(namespace),
+            stats,
+        }
+    }
+
+    /// Returns the counters of this server.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Answers the clients connecting to `listener`, each on a thread of its own.
+    pub fn serve_tcp(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_nodelay(true);
+            self.spawn(stream);
+        }
+    }
+
+    /// Answers the clients connecting to `listener`, such as a virtual machine through
+    /// a virtio-serial port backed by the socket.
+    pub fn serve_unix(self: Arc<Self>, listener: UnixListener) {
+        for stream in listener.incoming().flatten() {
+            self.spawn(stream);
+        }
+    }
+
+    /// Closes the sink and the source.
+    pub fn close(&self) {
+        if let Err(err) = self.sink.close().and(self.source.close()) {
+            log::error!("failed to close: {}", err);
+        }
+    }
+
+    fn spawn<S: Read + Write + Send + 'static>(self: &Arc<Self>, stream: S) {
+        let server = self.clone();
+        thread::spawn(move || {
+            let mut session = Session {
+                msize: MAX_MSIZE,
+                fids: HashMap::new(),
+            };
+            if let Err(err) = server.connection(stream, &mut session) {
+                log::warn!("9P client dropped: {}", err);
+            }
+            server.clunk_all(&mut session);
+        });
+    }
+
+    fn connection(&self, mut stream: impl Read + Write, session: &mut Session) -> io::Result<()> {
+        loop {
+            let mut size = [0; 4];
+            match stream.read_exact(&mut size) {
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                result => result?,
+            }
+            let size = u32::from_le_bytes(size);
+            if !(7..=MAX_MSIZE).contains(&size) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "bad message size",
+                ));
+            }
+            let mut message = vec![0; size as usize - 4];
+            stream.read_exact(&mut message)?;
+
+            if let Some(crash) = &self.config.crash {
+                crash.check();
+            }
+            let mut args = Reader { buf: &message };
+            let (kind, tag) = (args.u8().unwrap(), args.u16().unwrap());
+            let mut reply = Writer::default();
+            reply.u32(0);
+            reply.u8(kind.wrapping_add(1));
+            reply.u16(tag);
+            let result = self
+                .message(kind, &mut args, session, &mut reply)
+                .unwrap_or(Err(EINVAL));
+            if let Err(errno) = result {
+                reply.buf.truncate(7);
+                reply.buf[4] = RLERROR;
+                reply.u32(errno as u32);
+            }
+            let len = reply.buf.len() as u32;
+            reply.buf[..4].copy_from_slice(&len.to_le_bytes());
+            stream.write_all(&reply.buf)?;
+            if let (TFSYNC, Ok(()), Some(crash)) = (kind, result, &self.config.crash) {
+                crash.fsynced();
+            }
+        }
+    }
+
+    /// Answers a message, or returns `None` for one that is cut short.
+    fn message(
+        &self,
+        kind: u8,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        Some(match kind {
+            TVERSION => self.version(args, session, out)?,
+            TAUTH => Err(EOPNOTSUPP),
+            TATTACH => self.attach(args, session, out)?,
+            // Messages are answered in turn, so there is nothing left to flush.
+            TFLUSH => Ok(()),
+            TWALK => self.walk(args, session, out)?,
+            TCLUNK => {
+                let fid = args.u32()?;
+                self.clunk(session, fid)
+            }
+            TREMOVE => self.remove(args, session)?,
+            TLOPEN => self.lopen(args, session, out)?,
+            TLCREATE => self.lcreate(args, session, out)?,
+            TSYMLINK => self.symlink(args, session, out)?,
+            TMKNOD => self.mknod(args, session, out)?,
+            TMKDIR => self.mkdir(args, session, out)?,
+            TLINK => self.link(args, session)?,
+            TRENAME => self.rename(args, session)?,
+            TRENAMEAT => self.renameat(args, session)?,
+            TUNLINKAT => self.unlinkat(args, session)?,
+            TREADLINK => self.readlink(args, session, out)?,
+            TGETATTR => self.getattr(args, session, out)?,
+            TSETATTR => self.setattr(args, session)?,
+            TREADDIR => self.readdir(args, session, out)?,
+            TREAD => self.read(args, session, out)?,
+            TWRITE => self.write(args, session, out)?,
+            TFSYNC => self.fsync(args, session)?,
+            TSTATFS => self.statfs(args, session, out)?,
+            // Locks never stand in the way of one another.
+            TLOCK => fid_of(session, args.u32()?).map(|_| out.u8(LOCK_SUCCESS)),
+            TGETLOCK => self.getlock(args, session, out)?,
+            TXATTRWALK | TXATTRCREATE => Err(EOPNOTSUPP),
+            _ => Err(EOPNOTSUPP),
+        })
+    }
+
+    fn version(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (msize, version) = (args.u32()?, args.string()?);
+        // A new version starts the session over.
+        self.clunk_all(session);
+        session.msize = msize.clamp(IOHDRSZ + 1, MAX_MSIZE);
+        out.u32(session.msize);
+        match version.starts_with(VERSION) {
+            true => out.string(VERSION),
+            false => out.string(b"unknown"),
+        }
+        Some(Ok(()))
+    }
+
+    fn attach(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, _afid) = (args.u32()?, args.u32()?);
+        let (_uname, _aname, uid) = (args.string()?, args.string()?, args.u32()?);
+        if session.fids.contains_key(&fid) {
+            return Some(Err(EBADF));
+        }
+        // Only the user is known, its primary group standing in for the groups.
+        let gid = callers::primary_gid(uid).unwrap_or(u32::MAX);
+        if !self.config.callers.permits(uid, gid) {
+            return Some(Err(EACCES));
+        }
+        let mut namespace = self.namespace.lock().unwrap();
+        let attr = namespace.remember(ROOT);
+        session.fids.insert(fid, Fid { ino: ROOT, uid });
+        qid(out, &attr);
+        Some(Ok(()))
+    }
+
+    fn walk(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, newfid, count) = (args.u32()?, args.u32()?, args.u16()? as usize);
+        let mut names = Vec::new();
+        for _ in 0..count {
+            names.push(OsStr::from_bytes(args.string()?));
+        }
+        let from = match fid_of(session, fid) {
+            Ok(from) => from,
+            Err(errno) => return Some(Err(errno)),
+        };
+        if names.len() > MAX_WELEM || (newfid != fid && session.fids.contains_key(&newfid)) {
+            return Some(Err(EINVAL));
+        }
+
+        let mut namespace = self.namespace.lock().unwrap();
+        let mut ino = from.ino;
+        let mut qids = Vec::new();
+        for name in names {
+            let found = match rules::find(&self.config.rules, name) {
+                Some(Action::Errno(errno)) => Err(errno),
+                _ => namespace.child(ino, name),
+            };
+            match found {
+                Ok(child) => {
+                    ino = child;
+                    qids.push(namespace.attr(child).unwrap());
+                }
+                // Only the first name failing is an error.
+                Err(errno) if qids.is_empty() => return Some(Err(errno)),
+                Err(_) => break,
+            }
+        }
+        out.u16(qids.len() as u16);
+        for attr in &qids {
+            qid(out, attr);
+        }
+        // The new fid only comes to be once every name is walked.
+        if qids.len() == count {
+            namespace.remember(ino);
+            let replaced = session.fids.insert(newfid, Fid { ino, ..from });
+            if let Some(replaced) = replaced {
+                namespace.forget(replaced.ino, 1);
+            }
+        }
+        Some(Ok(()))
+    }
+
+    fn clunk(&self, session: &mut Session, fid: u32) -> Result<(), c_int> {
+        let fid = session.fids.remove(&fid).ok_or(EBADF)?;
+        self.namespace.lock().unwrap().forget(fid.ino, 1);
+        Ok(())
+    }
+
+    fn clunk_all(&self, session: &mut Session) {
+        let mut namespace = self.namespace.lock().unwrap();
+        for (_, fid) in session.fids.drain() {
+            namespace.forget(fid.ino, 1);
+        }
+    }
+
+    /// Removes the file a fid refers to and clunks the fid, whether removed or not.
+    fn remove(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let fid = args.u32()?;
+        let result = fid_of(session, fid).and_then(|Fid { ino, .. }| {
+            let mut namespace = self.namespace.lock().unwrap();
+            self.check_modify()?;
+            let inode = namespace.get(ino).unwrap();
+            let (parent, name) = (inode.parent, inode.name.clone());
+            match inode.kind() == FileType::Directory {
+                true => namespace.rmdir(parent, &name),
+                false => namespace.unlink(parent, &name),
+            }
+        });
+        let _ = self.clunk(session, fid);
+        Some(result)
+    }
+
+    fn lopen(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, flags) = (args.u32()?, args.u32()?);
+        Some(fid_of(session, fid).and_then(|fid| {
+            if interrupted(&self.config, Call::Open) {
+                return Err(EINTR);
+            }
+            let mut namespace = self.namespace.lock().unwrap();
+            let attr = namespace.attr(fid.ino).unwrap();
+            if flags & O_ACCMODE != 0 && self.config.read_only {
+                return Err(EROFS);
+            }
+            if flags & O_ACCMODE != 0 && attr.kind == FileType::Directory {
+                return Err(EISDIR);
+            }
+            if flags & O_TRUNC != 0 && attr.kind == FileType::RegularFile {
+                truncate(&self.config, &mut namespace, fid.ino, 0)?;
+            }
+            qid(out, &attr);
+            out.u32(session.msize - IOHDRSZ);
+            Ok(())
+        }))
+    }
+
+    /// Creates a file in the directory of a fid, which then refers to the file, opened.
+    fn lcreate(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, name) = (args.u32()?, OsStr::from_bytes(args.string()?));
+        let (_flags, mode, gid) = (args.u32()?, args.u32()?, args.u32()?);
+        let msize = session.msize;
+        Some(fid_of(session, fid).and_then(|dir| {
+            let mut namespace = self.namespace.lock().unwrap();
+            let attr =
+                self.make_node(&mut namespace, dir, name, FileType::RegularFile, mode, gid)?;
+            namespace.forget(dir.ino, 1);
+            session.fids.insert(
+                fid,
+                Fid {
+                    ino: attr.ino,
+                    ..dir
+                },
+            );
+            qid(out, &attr);
+            out.u32(msize - IOHDRSZ);
+            Ok(())
+        }))
+    }
+
+    fn symlink(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, name) = (args.u32()?, OsStr::from_bytes(args.string()?));
+        let (target, gid) = (OsStr::from_bytes(args.string()?), args.u32()?);
+        Some(fid_of(session, fid).and_then(|dir| {
+            let mut namespace = self.namespace.lock().unwrap();
+            self.check_modify()?;
+            if !self.config.dynamic {
+                return Err(EPERM);
+            }
+            self.check_new_name(name)?;
+            let attr = namespace.symlink(dir.ino, name, target, dir.uid, gid)?;
+            namespace.forget(attr.ino, 1);
+            qid(out, &attr);
+            Ok(())
+        }))
+    }
+
+    /// Makes another /dev/null, the only device there can be.
+    fn mknod(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, name) = (args.u32()?, OsStr::from_bytes(args.string()?));
+        let (mode, major, minor, gid) = (args.u32()?, args.u32()?, args.u32()?, args.u32()?);
+        Some(fid_of(session, fid).and_then(|dir| {
+            let kind = match mode & S_IFMT {
+                S_IFREG => FileType::RegularFile,
+                S_IFCHR if major << 8 | minor == NULL_RDEV => FileType::CharDevice,
+                _ => return Err(EPERM),
+            };
+            let mut namespace = self.namespace.lock().unwrap();
+            let attr = self.make_node(&mut namespace, dir, name, kind, mode, gid)?;
+            namespace.forget(attr.ino, 1);
+            qid(out, &attr);
+            Ok(())
+        }))
+    }
+
+    fn mkdir(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, name) = (args.u32()?, OsStr::from_bytes(args.string()?));
+        let (mode, gid) = (args.u32()?, args.u32()?);
+        Some(fid_of(session, fid).and_then(|dir| {
+            let mut namespace = self.namespace.lock().unwrap();
+            let attr = self.make_node(&mut namespace, dir, name, FileType::Directory, mode, gid)?;
+            namespace.forget(attr.ino, 1);
+            qid(out, &attr);
+            Ok(())
+        }))
+    }
+
+    fn link(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let (dir, fid, name) = (args.u32()?, args.u32()?, OsStr::from_bytes(args.string()?));
+        Some(fid_of(session, dir).and_then(|dir| {
+            let ino = fid_of(session, fid)?.ino;
+            let mut namespace = self.namespace.lock().unwrap();
+            self.check_modify()?;
+            if !self.config.dynamic {
+                return Err(EPERM);
+            }
+            self.check_new_name(name)?;
+            namespace.link(ino, dir.ino, name)?;
+            namespace.forget(ino, 1);
+            Ok(())
+        }))
+    }
+
+    /// Moves the file a fid refers to into another directory.
+    fn rename(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let (fid, dir, name) = (args.u32()?, args.u32()?, OsStr::from_bytes(args.string()?));
+        Some(fid_of(session, fid).and_then(|fid| {
+            let newdir = fid_of(session, dir)?.ino;
+            let mut namespace = self.namespace.lock().unwrap();
+            let inode = namespace.get(fid.ino).unwrap();
+            let (parent, oldname) = (inode.parent, inode.name.clone());
+            self.rename_entry(&mut namespace, parent, &oldname, newdir, name)
+        }))
+    }
+
+    fn renameat(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let (dir, name) = (args.u32()?, OsStr::from_bytes(args.string()?));
+        let (newdir, newname) = (args.u32()?, OsStr::from_bytes(args.string()?));
+        Some(fid_of(session, dir).and_then(|dir| {
+            let newdir = fid_of(session, newdir)?.ino;
+            let mut namespace = self.namespace.lock().unwrap();
+            self.rename_entry(&mut namespace, dir.ino, name, newdir, newname)
+        }))
+    }
+
+    fn unlinkat(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let (dir, name, flags) = (args.u32()?, OsStr::from_bytes(args.string()?), args.u32()?);
+        Some(fid_of(session, dir).and_then(|dir| {
+            let mut namespace = self.namespace.lock().unwrap();
+            self.check_modify()?;
+            match flags & AT_REMOVEDIR != 0 {
+                true => namespace.rmdir(dir.ino, name),
+                false => namespace.unlink(dir.ino, name),
+            }
+        }))
+    }
+
+    fn readlink(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let fid = args.u32()?;
+        Some(fid_of(session, fid).and_then(|fid| {
+            let namespace = self.namespace.lock().unwrap();
+            match namespace.get(fid.ino).unwrap() {
+                inode if inode.kind() == FileType::Symlink => {
+                    out.string(inode.target.as_bytes());
+                    Ok(())
+                }
+                _ => Err(EINVAL),
+            }
+        }))
+    }
+
+    fn getattr(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, _mask) = (args.u32()?, args.u64()?);
+        Some(fid_of(session, fid).map(|fid| {
+            let namespace = self.namespace.lock().unwrap();
+            let attr = namespace.attr(fid.ino).unwrap();
+            out.u64(GETATTR_VALID);
+            qid(out, &attr);
+            out.u32(mode(&attr));
+            out.u32(attr.uid);
+            out.u32(attr.gid);
+            out.u64(attr.nlink as u64);
+            out.u64(attr.rdev as u64);
+            out.u64(attr.size);
+            out.u64(attr.blksize as u64);
+            out.u64(attr.blocks);
+            for time in [attr.atime, attr.mtime, attr.ctime, attr.crtime] {
+                let since = time
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default();
+                out.u64(since.as_secs());
+                out.u64(since.subsec_nanos() as u64);
+            }
+            // The generation and the data version.
+            out.u64(0);
+            out.u64(0);
+        }))
+    }
+
+    /// Changes the mode and owner of a file, and its size and times as a mount does.
+    fn setattr(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let (fid, valid, mode, uid, gid, size) = (
+            args.u32()?,
+            args.u32()?,
+            args.u32()?,
+            args.u32()?,
+            args.u32()?,
+            args.u64()?,
+        );
+        let (atime, mtime) = (time(args)?, time(args)?);
+        let given = |bit: u32| valid & bit != 0;
+        let set_time = |time: SystemTime, set: u32, bit: u32| match (given(set), given(bit)) {
+            (true, true) => Some(time),
+            (true, false) => Some(SystemTime::now()),
+            _ => None,
+        };
+        let mut changes = SetAttr {
+            perm: given(SETATTR_MODE).then_some(mode as u16),
+            uid: given(SETATTR_UID).then_some(uid),
+            gid: given(SETATTR_GID).then_some(gid),
+            size: given(SETATTR_SIZE).then_some(size),
+            atime: set_time(atime, SETATTR_ATIME, SETATTR_ATIME_SET),
+            mtime: set_time(mtime, SETATTR_MTIME, SETATTR_MTIME_SET),
+        };
+        Some(fid_of(session, fid).and_then(|fid| {
+            self.check_modify()?;
+            let mut namespace = self.namespace.lock().unwrap();
+            if let Some(size) = changes.size.take() {
+                truncate(&self.config, &mut namespace, fid.ino, size)?;
+            }
+            // Modes and owners always stay, times only with the rest of the metadata.
+            if !self.config.metadata {
+                (changes.atime, changes.mtime) = (None, None);
+            }
+            namespace.set_attr(fid.ino, changes)?;
+            Ok(())
+        }))
+    }
+
+    /// Lists a directory, . and .. first, the index of the next entry serving as offset.
+    fn readdir(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, offset, count) = (args.u32()?, args.u64()?, args.u32()?);
+        let count = count.min(session.msize - IOHDRSZ) as usize;
+        Some(fid_of(session, fid).and_then(|fid| {
+            let namespace = self.namespace.lock().unwrap();
+            let dir = namespace.get(fid.ino).unwrap();
+            if dir.kind() != FileType::Directory {
+                return Err(ENOTDIR);
+            }
+            let entries = [(OsStr::new("."), fid.ino), (OsStr::new(".."), dir.parent)]
+                .into_iter()
+                .chain(
+                    dir.entries
+                        .iter()
+                        .map(|(name, ino)| (name.as_os_str(), *ino)),
+                );
+            let mut listed = Writer::default();
+            for (i, (name, ino)) in entries.enumerate().skip(offset as usize) {
+                if listed.buf.len() + 13 + 8 + 1 + 2 + name.len() > count {
+                    break;
+                }
+                let attr = namespace.attr(ino).unwrap();
+                qid(&mut listed, &attr);
+                listed.u64(i as u64 + 1);
+                listed.u8(match attr.kind {
+                    FileType::Directory => DT_DIR,
+                    FileType::Symlink => DT_LNK,
+                    FileType::CharDevice => DT_CHR,
+                    _ => DT_REG,
+                });
+                listed.string(name.as_bytes());
+            }
+            out.u32(listed.buf.len() as u32);
+            out.buf.extend_from_slice(&listed.buf);
+            Ok(())
+        }))
+    }
+
+    fn read(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, offset, count) = (args.u32()?, args.u64()?, args.u32()?);
+        let count = count.min(session.msize - IOHDRSZ);
+        Some(fid_of(session, fid).and_then(|fid| {
+            let offset = i64::try_from(offset).map_err(|_| EINVAL)?;
+            self.check_file(fid.ino)?;
+            if interrupted(&self.config, Call::Read) {
+                return Err(EINTR);
+            }
+            let (contents, filled, pipe) = {
+                let namespace = self.namespace.lock().unwrap();
+                (
+                    self.kept(&namespace, fid.ino),
+                    self.filled(&namespace, fid.ino),
+                    namespace
+                        .get(fid.ino)
+                        .and_then(|inode| pipe(&self.config, inode)),
+                )
+            };
+            // The source is not waited for with the namespace locked, nor are pipes, an empty
+            // one reading as its end.
+            let piped = pipe.map(|pipe| pipe.take(count));
+            let kept = contents.and_then(|(kept, _)| kept.read(offset as u64, count));
+            let fill =
+                |(pattern, len): (Arc<Pattern>, u64)| pattern.fill(offset as u64, count, len);
+            let data = match piped.or(kept).or_else(|| filled.map(fill)) {
+                Some(data) => data,
+                None => self.source.read(fid.ino, offset, count).map_err(errno)?,
+            };
+            self.stats.record_read(fid.uid, 0, data.len() as u64);
+            out.u32(data.len() as u32);
+            out.buf.extend_from_slice(&data);
+            Ok(())
+        }))
+    }
+
+    fn write(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let (fid, offset, count) = (args.u32()?, args.u64()?, args.u32()?);
+        let data = args.fixed(count as usize)?;
+        Some(fid_of(session, fid).and_then(|fid| {
+            if self.config.read_only {
+                return Err(EROFS);
+            }
+            let offset = i64::try_from(offset).map_err(|_| EINVAL)?;
+            self.check_file(fid.ino)?;
+            if interrupted(&self.config, Call::Write) {
+                return Err(EINTR);
+            }
+            let (size, file_stats, contents, pipe) = {
+                let namespace = self.namespace.lock().unwrap();
+                let inode = namespace.get(fid.ino).unwrap();
+                match rules::find(&self.config.rules, &inode.name) {
+                    Some(Action::Errno(errno)) => return Err(errno),
+                    Some(Action::Quota(quota)) if offset as u64 + data.len() as u64 > quota => {
+                        return Err(EDQUOT)
+                    }
+                    _ => {}
+                }
+                let contents = self.kept(&namespace, fid.ino);
+                let pipe = pipe(&self.config, inode);
+                (inode.size.clone(), inode.stats.clone(), contents, pipe)
+            };
+            let written = self.sink.write(fid.ino, offset, data).map_err(errno)?;
+            let accounting = self.config.size_accounting;
+            account(&size, accounting, offset as u64, written as u64);
+            file_stats.record_write(&data[..written.min(data.len())]);
+            if let Some((contents, keep)) = contents {
+                contents.write(keep, offset as u64, &data[..written.min(data.len())]);
+            }
+            if let Some(pipe) = pipe {
+                pipe.write(&data[..written.min(data.len())]);
+            }
+            self.stats.record_write(fid.uid, 0, written as u64);
+            if self.config.metadata {
+                self.namespace.lock().unwrap().modified(fid.ino);
+            }
+            out.u32(written as u32);
+            Ok(())
+        }))
+    }
+
+    fn fsync(&self, args: &mut Reader, session: &mut Session) -> Option<Result<(), c_int>> {
+        let fid = args.u32()?;
+        Some(fid_of(session, fid).and_then(|fid| {
+            if let Some(delay) = &self.config.fsync_delay {
+                delay.wait();
+            }
+            self.sink.flush(fid.ino).map_err(errno)
+        }))
+    }
+
+    /// Reports no space at all, there being none to run out of.
+    fn statfs(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let fid = args.u32()?;
+        Some(fid_of(session, fid).map(|_| {
+            let generation = self.namespace.lock().unwrap().generation();
+            out.u32(V9FS_MAGIC);
+            out.u32(4096);
+            for _ in 0..5 {
+                out.u64(0);
+            }
+            out.u64(generation);
+            out.u32(self.config.name_policy.max_length.unwrap_or(255) as u32);
+        }))
+    }
+
+    /// Reports every range unlocked.
+    fn getlock(
+        &self,
+        args: &mut Reader,
+        session: &mut Session,
+        out: &mut Writer,
+    ) -> Option<Result<(), c_int>> {
+        let fid = args.u32()?;
+        args.u8()?;
+        let (start, length, pid, client) = (args.u64()?, args.u64()?, args.u32()?, args.string()?);
+        Some(fid_of(session, fid).map(|_| {
+            out.u8(F_UNLCK);
+            out.u64(start);
+            out.u64(length);
+            out.u32(pid);
+            out.string(client);
+        }))
+    }
+
+    /// The contents kept of `ino` and how, if any are.
+    fn kept(&self, namespace: &Namespace, ino: u64) -> Option<(Arc<Contents>, Keep)> {
+        let inode = namespace.get(ino)?;
+        keep(&self.config, &inode.name).map(|keep| (inode.contents.clone(), keep))
+    }
+
+    /// The pattern `ino` reads as and the size it fills, unless reads go to the source.
+    fn filled(&self, namespace: &Namespace, ino: u64) -> Option<(Arc<Pattern>, u64)> {
+        let inode = namespace.get(ino)?;
+        if endless(&self.config, &inode.name) {
+            return Some((self.config.fill.clone().unwrap_or_default(), u64::MAX));
+        }
+        let pattern = self.config.fill.clone()?;
+        Some((pattern, inode.size.load(Ordering::Relaxed)))
+    }
+
+    /// Checks that `ino` is a regular file, which only can be read and written.
+    fn check_file(&self, ino: u64) -> Result<(), c_int> {
+        match self
+            .namespace
+            .lock()
+            .unwrap()
+            .attr(ino)
+            .map(|attr| attr.kind)
+        {
+            Some(FileType::RegularFile) => Ok(()),
+            Some(FileType::Directory) => Err(EISDIR),
+            Some(_) => Err(EINVAL),
+            None => Err(ENOENT),
+        }
+    }
+
+    fn check_modify(&self) -> Result<(), c_int> {
+        match self.config.read_only {
+            true => Err(EROFS),
+            false => Ok(()),
+        }
+    }
+
+    /// Checks a name given to a new file against the name policy and the rules.
+    fn check_new_name(&self, name: &OsStr) -> Result<(), c_int> {
+        self.config.name_policy.check(name)?;
+        match rules::find(&self.config.rules, name) {
+            Some(Action::Errno(errno)) => Err(errno),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates a node in dynamic mode, or hands out the null file in its place otherwise,
+    /// counting the reference of a fid either way.
+    fn make_node(
+        &self,
+        namespace: &mut Namespace,
+        dir: Fid,
+        name: &OsStr,
+        kind: FileType,
+        mode: u32,
+        gid: u32,
+    ) -> Result<FileAttr, c_int> {
+        self.check_modify()?;
+        self.check_new_name(name)?;
+        if !self.config.dynamic {
+            return match namespace.child(dir.ino, name) {
+                Ok(NULL) if kind == FileType::RegularFile => Ok(namespace.remember(NULL)),
+                _ => Err(EPERM),
+            };
+        }
+        let perm = (mode & 0o7777) as u16;
+        namespace.create(dir.ino, name, kind, perm, dir.uid, gid)
+    }
+
+    fn rename_entry(
+        &self,
+        namespace: &mut Namespace,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> Result<(), c_int> {
+        self.check_modify()?;
+        if !self.config.dynamic {
+            return Err(EPERM);
+        }
+        self.check_new_name(newname)?;
+        namespace.rename(parent, name, newparent, newname, 0)
+    }
+}
+
+/// Returns what `fid` refers to.
+fn fid_of(session: &Session, fid: u32) -> Result<Fid, c_int> {
+    match fid {
+        NOFID => Err(EBADF),
+        fid => session.fids.get(&fid).copied().ok_or(EBADF),
+    }
+}
+
+/// Decodes the little-endian fields of 9P, coming up with `None` once the data runs out.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn fixed(&mut self, len: usize) -> Option<&'a [u8]> {
+        let data = self.buf.get(..len)?;
+        self.buf = &self.buf[len..];
+        Some(data)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.fixed(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.fixed(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.fixed(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.fixed(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()?;
+        self.fixed(len as usize)
+    }
+}
+
+/// Encodes the little-endian fields of 9P.
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &[u8]) {
+        self.u16(value.len() as u16);
+        self.buf.extend_from_slice(value);
+    }
+}
+
+/// Writes the qid of a file: its type, a version that never changes, and its inode number.
+fn qid(out: &mut Writer, attr: &FileAttr) {
+    out.u8(match attr.kind {
+        FileType::Directory => QTDIR,
+        FileType::Symlink => QTSYMLINK,
+        _ => QTFILE,
+    });
+    out.u32(0);
+    out.u64(attr.ino);
+}
+
+/// Returns the mode of a file as Linux encodes it.
+fn mode(attr: &FileAttr) -> u32 {
+    let kind = match attr.kind {
+        FileType::Directory => S_IFDIR,
+        FileType::Symlink => S_IFLNK,
+        FileType::CharDevice => S_IFCHR,
+        _ => S_IFREG,
+    };
+    kind | attr.perm as u32
+}
+
+/// Reads a time as seconds and nanoseconds since the epoch.
+fn time(args: &mut Reader) -> Option<SystemTime> {
+    let (seconds, nanoseconds) = (args.u64()?, args.u64()?);
+    let since = Duration::new(seconds, nanoseconds.min(999_999_999) as u32);
+    Some(SystemTime::UNIX_EPOCH + since)
+}
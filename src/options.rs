@@ -0,0 +1,440 @@
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+use fuser::MountOption;
+
+const CONFLICTS: &[(MountOption, MountOption)] = &[
+    (MountOption::AllowOther, MountOption::AllowRoot),
+    (MountOption::Dev, MountOption::NoDev),
+    (MountOption::Suid, MountOption::NoSuid),
+    (MountOption::RO, MountOption::RW),
+    (MountOption::Exec, MountOption::NoExec),
+    (MountOption::Atime, MountOption::NoAtime),
+    (MountOption::Sync, MountOption::Async),
+];
+
+/// Options passed on as they are which the platform defaults to, such as keeping Finder
+/// from writing `._` files next to every file.
+#[cfg(target_os = "macos")]
+const PLATFORM_DEFAULTS: &[&str] = &["noappledouble"];
+#[cfg(not(target_os = "macos"))]
+const PLATFORM_DEFAULTS: &[&str] = &[];
+
+/// Adds the baseline options unless the same option, or its opposite, is already present.
+pub fn with_defaults(mut options: Vec<MountOption>) -> Vec<MountOption> {
+    let defaults = [MountOption::DefaultPermissions, MountOption::NoAtime];
+
+    for default in defaults {
+        let overridden = options.iter().any(|option| {
+            mem::discriminant(option) == mem::discriminant(&default)
+                || CONFLICTS.contains(&(option.clone(), default.clone()))
+                || CONFLICTS.contains(&(default.clone(), option.clone()))
+        });
+        if !overridden {
+            options.push(default);
+        }
+    }
+    for default in PLATFORM_DEFAULTS {
+        let default = MountOption::CUSTOM(default.to_string());
+        if !options.contains(&default) {
+            options.push(default);
+        }
+    }
+    options
+}
+
+/// Names the mount `fsname` of type `fuse.nullfs`, or the volume `fsname` on macOS, unless the
+/// options already name it.
+pub fn identify(mut options: Vec<MountOption>, fsname: &str) -> Vec<MountOption> {
+    if !options
+        .iter()
+        .any(|option| matches!(option, MountOption::FSName(_)))
+    {
+        options.push(MountOption::FSName(fsname.to_string()));
+    }
+    // macFUSE has no subtypes, but shows a volume name in Finder instead.
+    #[cfg(not(target_os = "macos"))]
+    if !options
+        .iter()
+        .any(|option| matches!(option, MountOption::Subtype(_)))
+    {
+        options.push(MountOption::Subtype("nullfs".to_string()));
+    }
+    #[cfg(target_os = "macos")]
+    if !options.iter().any(
+        |option| matches!(option, MountOption::CUSTOM(custom) if custom.starts_with("volname=")),
+    ) {
+        options.push(MountOption::CUSTOM(format!("volname={}", fsname)));
+    }
+    options
+}
+
+/// Parses a comma separated list of mount options as given to `-o`.
+pub fn parse(options: &str) -> io::Result<Vec<MountOption>> {
+    options
+        .split(',')
+        .filter(|option| !option.is_empty())
+        .map(parse_one)
+        .collect()
+}
+
+fn parse_one(option: &str) -> io::Result<MountOption> {
+    let parsed = match option.split_once('=') {
+        None => match option {
+            "allow_other" => MountOption::AllowOther,
+            "allow_root" => MountOption::AllowRoot,
+            "auto_unmount" => MountOption::AutoUnmount,
+            "default_permissions" => MountOption::DefaultPermissions,
+            "dev" => MountOption::Dev,
+            "nodev" => MountOption::NoDev,
+            "suid" => MountOption::Suid,
+            "nosuid" => MountOption::NoSuid,
+            "ro" => MountOption::RO,
+            "rw" => MountOption::RW,
+            "exec" => MountOption::Exec,
+            "noexec" => MountOption::NoExec,
+            "atime" => MountOption::Atime,
+            "noatime" => MountOption::NoAtime,
+            "dirsync" => MountOption::DirSync,
+            "sync" => MountOption::Sync,
+            "async" => MountOption::Async,
+            #[cfg(target_os = "macos")]
+            "noappledouble" | "nobrowse" => MountOption::CUSTOM(option.to_string()),
+            #[cfg(target_os = "freebsd")]
+            "intr" | "push_symlinks_in" | "private" | "neglect_shares" => {
+                MountOption::CUSTOM(option.to_string())
+            }
+            _ => return Err(invalid(format!("unknown mount option '{}'", option))),
+        },
+        Some(("fsname", value)) => MountOption::FSName(value.to_string()),
+        Some(("subtype", value)) => MountOption::Subtype(value.to_string()),
+        // Passed on to the kernel as they are.
+        Some(("max_read", value)) | Some(("blksize", value)) => {
+            if value.parse::<u32>().is_err() {
+                return Err(invalid(format!("mount option '{}' needs a number", option)));
+            }
+            MountOption::CUSTOM(option.to_string())
+        }
+        #[cfg(target_os = "macos")]
+        Some(("volname", "")) => {
+            return Err(invalid(format!("mount option '{}' needs a value", option)))
+        }
+        #[cfg(target_os = "macos")]
+        Some(("volname", _)) => MountOption::CUSTOM(option.to_string()),
+        Some(_) => return Err(invalid(format!("unknown mount option '{}'", option))),
+    };
+
+    match parsed {
+        MountOption::FSName(ref value) | MountOption::Subtype(ref value) if value.is_empty() => {
+            Err(invalid(format!("mount option '{}' needs a value", option)))
+        }
+        // Left to fusermount or the Linux kernel.
+        #[cfg(target_os = "freebsd")]
+        MountOption::Dev | MountOption::NoDev | MountOption::DirSync | MountOption::AutoUnmount => {
+            Err(invalid(format!(
+                "mount option '{}' is not supported on FreeBSD",
+                option
+            )))
+        }
+        _ => Ok(parsed),
+    }
+}
+
+/// Parses a byte count such as `4096`, `128K`, `1MiB` or `1T`, where the suffixes are powers of
+/// 1024.
+pub fn parse_size(size: &str) -> io::Result<u64> {
+    let number = size.strip_suffix("iB").unwrap_or(size);
+    let (number, shift) = match number.as_bytes().last() {
+        Some(b'k' | b'K') => (&number[..number.len() - 1], 10),
+        Some(b'm' | b'M') => (&number[..number.len() - 1], 20),
+        Some(b'g' | b'G') => (&number[..number.len() - 1], 30),
+        Some(b't' | b'T') => (&number[..number.len() - 1], 40),
+        _ if number.len() != size.len() => ("", 0),
+        _ => (number, 0),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| invalid(format!("invalid size '{}'", size)))
+}
+
+/// Parses permission bits written in octal, such as `0644` or `1777`.
+pub fn parse_mode(mode: &str) -> io::Result<u16> {
+    u16::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| mode & !0o7777 == 0)
+        .ok_or_else(|| invalid(format!("invalid mode '{}'", mode)))
+}
+
+/// Parses a duration such as `0`, `500ms`, `1h30m` or `infinite`; bare numbers are seconds.
+pub fn parse_duration(duration: &str) -> io::Result<Duration> {
+    if duration == "infinite" {
+        return Ok(Duration::MAX);
+    }
+    let error = || invalid(format!("invalid duration '{}'", duration));
+
+    let mut total = Duration::ZERO;
+    let mut rest = duration.trim();
+    if rest.is_empty() {
+        return Err(error());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let number: u64 = rest[..digits].parse().map_err(|_| error())?;
+        rest = &rest[digits..];
+        let letters = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let part = match rest[..letters].trim() {
+            "" | "s" | "sec" => Duration::from_secs(number),
+            "ns" => Duration::from_nanos(number),
+            "us" => Duration::from_micros(number),
+            "ms" => Duration::from_millis(number),
+            "m" | "min" => Duration::from_secs(number)
+                .checked_mul(60)
+                .ok_or_else(error)?,
+            "h" => Duration::from_secs(number)
+                .checked_mul(60 * 60)
+                .ok_or_else(error)?,
+            "d" => Duration::from_secs(number)
+                .checked_mul(24 * 60 * 60)
+                .ok_or_else(error)?,
+            _ => return Err(error()),
+        };
+        total = total.checked_add(part).ok_or_else(error)?;
+        rest = &rest[letters..];
+    }
+    Ok(total)
+}
+
+/// Rejects option lists which contain both an option and its opposite.
+pub fn check_conflicts(options: &[MountOption]) -> io::Result<()> {
+    for (a, b) in CONFLICTS {
+        if options.contains(a) && options.contains(b) {
+            return Err(invalid(format!(
+                "conflicting mount options '{}' and '{}'",
+                name(a),
+                name(b)
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn name(option: &MountOption) -> &'static str {
+    match option {
+        MountOption::AllowOther => "allow_other",
+        MountOption::AllowRoot => "allow_root",
+        MountOption::Dev => "dev",
+        MountOption::NoDev => "nodev",
+        MountOption::Suid => "suid",
+        MountOption::NoSuid => "nosuid",
+        MountOption::RO => "ro",
+        MountOption::RW => "rw",
+        MountOption::Exec => "exec",
+        MountOption::NoExec => "noexec",
+        MountOption::Atime => "atime",
+        MountOption::NoAtime => "noatime",
+        MountOption::Sync => "sync",
+        MountOption::Async => "async",
+        _ => "?",
+    }
+}
+
+/// Turns a configuration file into the command line flags it stands for.
+///
+/// The file is a subset of TOML: every `key = value` names a long flag, with strings,
+/// integers and `true` passed as they are and arrays repeating the flag once per item.
+/// Tables only group settings, `false` leaves a switch off, and `#` starts a comment.
+pub fn parse_config_file(text: &str) -> io::Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| invalid(format!("line {}: {}", number + 1, message));
+        let line = strip_comment(line).trim();
+        if line.is_empty() || line.starts_with('[') && line.ends_with(']') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let key = key.trim().trim_matches('"').replace('_', "-");
+        let value = value.trim();
+        let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(items) => split_array(items)
+                .into_iter()
+                .filter(|item| !item.is_empty())
+                .map(|item| parse_value(item).ok_or_else(|| error("invalid value")))
+                .collect::<io::Result<Vec<_>>>()?,
+            None => vec![parse_value(value).ok_or_else(|| error("invalid value"))?],
+        };
+        for value in values {
+            match value {
+                Value::Switch(false) => {}
+                Value::Switch(true) => args.push(format!("--{}", key)),
+                Value::Text(text) => args.push(format!("--{}={}", key, text)),
+            }
+        }
+    }
+    Ok(args)
+}
+
+enum Value {
+    Switch(bool),
+    Text(String),
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    let value = value.trim();
+    match value {
+        "true" => return Some(Value::Switch(true)),
+        "false" => return Some(Value::Switch(false)),
+        _ => {}
+    }
+    if let Some(literal) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return Some(Value::Text(literal.to_string()));
+    }
+    if let Some(basic) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        let mut text = String::new();
+        let mut chars = basic.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    c @ ('"' | '\\') => text.push(c),
+                    _ => return None,
+                },
+                c => text.push(c),
+            }
+        }
+        return Some(Value::Text(text));
+    }
+    let number = value.replace('_', "");
+    number
+        .parse::<i64>()
+        .ok()
+        .map(|number| Value::Text(number.to_string()))
+}
+
+/// Cuts a line at a `#` outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits the items of a one-line array at commas outside of quotes.
+fn split_array(items: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in items.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => {
+                parts.push(items[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(items[start..].trim());
+    parts
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("128K").unwrap(), 128 << 10);
+        assert_eq!(parse_size("1MiB").unwrap(), 1 << 20);
+        assert_eq!(parse_size("2g").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("12iB").is_err());
+        assert!(parse_size("1P").is_err());
+        assert!(parse_size("16777216T").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("2 min").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration("infinite").unwrap(), Duration::MAX);
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("3 fortnights").is_err());
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(parse_mode("0644").unwrap(), 0o644);
+        assert_eq!(parse_mode("1777").unwrap(), 0o1777);
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("0800").is_err());
+    }
+
+    #[test]
+    fn parses_mount_options() {
+        let options = parse("ro,fsname=null,max_read=4096").unwrap();
+        assert_eq!(options[0], MountOption::RO);
+        assert_eq!(options[1], MountOption::FSName("null".to_string()));
+        assert!(parse("bogus").is_err());
+        assert!(parse("fsname").is_err());
+        assert!(check_conflicts(&parse("ro,rw").unwrap()).is_err());
+        assert!(check_conflicts(&parse("ro,nodev").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn turns_config_files_into_flags() {
+        let args = parse_config_file(
+            r#"
+            # A comment
+            [mount]
+            read_only = true
+            dynamic = false
+            name = "out # not a comment"
+            keep_under = 65_536
+            rule = ['*.tmp => discard', "*.lock => EPERM"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--read-only",
+                "--name=out # not a comment",
+                "--keep-under=65536",
+                "--rule=*.tmp => discard",
+                "--rule=*.lock => EPERM",
+            ]
+        );
+        assert!(parse_config_file("name").is_err());
+        assert!(parse_config_file("fill-byte = 0xAA").is_err());
+        assert!(parse_config_file("name = \"\\q\"").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use libc::c_int;
+
+use crate::logger::json_string;
+
+/// Largest number of spans sent in one export.
+const BATCH: usize = 512;
+
+/// How long a span may wait for its batch to fill up.
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Spans waiting for the exporter, beyond which new ones are dropped.
+const QUEUE: usize = 4096;
+
+/// Where spans are sent, as OTLP over HTTP with JSON encoding.
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    host: String,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parses `http://HOST:PORT[/PATH]`, the path defaulting to `/v1/traces`.
+    pub fn parse(url: &str) -> Result<Endpoint, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or("only http:// endpoints are supported")?;
+        let (host, path) = match rest.find('/') {
+            Some(i) if rest.len() > i + 1 => (&rest[..i], &rest[i..]),
+            Some(i) => (&rest[..i], "/v1/traces"),
+            None => (rest, "/v1/traces"),
+        };
+        if host.is_empty() {
+            return Err("missing host".to_string());
+        }
+        let host = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{}:4318", host),
+        };
+        Ok(Endpoint {
+            host,
+            path: path.to_string(),
+        })
+    }
+
+    fn post(&self, body: &str) -> io::Result<()> {
+        let mut stream = TcpStream::connect(&self.host)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        )?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "collector replied {}",
+                status.trim_end()
+            ))),
+        }
+    }
+}
+
+/// A finished request, as it is exported.
+pub struct Span {
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, i64)>,
+    pub errno: Option<c_int>,
+}
+
+enum Message {
+    Span(Span),
+    Flush(mpsc::Sender<()>),
+}
+
+static ENDPOINT: OnceLock<Endpoint> = OnceLock::new();
+static EXPORTER: OnceLock<SyncSender<Message>> = OnceLock::new();
+
+/// Exports a span for every request to `endpoint` from now on.
+///
+/// The exporting thread starts with the first span, so it survives daemonizing.
+pub fn init(endpoint: Endpoint) {
+    let _ = ENDPOINT.set(endpoint);
+}
+
+pub fn is_enabled() -> bool {
+    ENDPOINT.get().is_some()
+}
+
+/// Queues `span` for export, dropping it if the collector cannot keep up.
+pub fn export(span: Span) {
+    if let Some(exporter) = exporter() {
+        let _ = exporter.try_send(Message::Span(span));
+    }
+}
+
+/// Sends the spans still queued, waiting at most `timeout` for them to go out.
+pub fn flush(timeout: Duration) {
+    if let Some(exporter) = EXPORTER.get() {
+        let (done, wait) = mpsc::channel();
+        if exporter.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv_timeout(timeout);
+        }
+    }
+}
+
+fn exporter() -> Option<&'static SyncSender<Message>> {
+    let endpoint = ENDPOINT.get()?;
+    Some(EXPORTER.get_or_init(|| {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        thread::spawn(move || run(endpoint, receiver));
+        sender
+    }))
+}
+
+fn run(endpoint: &Endpoint, receiver: Receiver<Message>) {
+    let mut batch = Vec::new();
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let message = match deadline {
+            Some(deadline) => {
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            None => match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return,
+            },
+        };
+        let flushed = match message {
+            Some(Message::Span(span)) => {
+                deadline.get_or_insert_with(|| Instant::now() + BATCH_DELAY);
+                batch.push(span);
+                if batch.len() < BATCH {
+                    continue;
+                }
+                None
+            }
+            Some(Message::Flush(done)) => Some(done),
+            None => None,
+        };
+        if !batch.is_empty() {
+            if let Err(err) = endpoint.post(&encode(&batch)) {
+                log::warn!("failed to export {} spans: {}", batch.len(), err);
+            }
+            batch.clear();
+        }
+        deadline = None;
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Encodes spans as an OTLP export request in JSON.
+fn encode(spans: &[Span]) -> String {
+    let mut json = String::from(
+        "{\"resourceSpans\":[{\"resource\":{\"attributes\":[{\"key\":\"service.name\",\
+         \"value\":{\"stringValue\":\"nullfs\"}}]},\"scopeSpans\":[{\"scope\":{\"name\":\"nullfs\"},\
+         \"spans\":[",
+    );
+    for (i, span) in spans.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"traceId\":\"{}\",\"spanId\":\"{}\",\"name\":{},\"kind\":2,\
+             \"startTimeUnixNano\":\"{}\",\"endTimeUnixNano\":\"{}\",\"attributes\":[",
+            random_id(16),
+            random_id(8),
+            json_string(span.name),
+            unix_nanos(span.start),
+            unix_nanos(span.end)
+        );
+        for (j, (key, value)) in span.attributes.iter().enumerate() {
+            if j > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"key\":{},\"value\":{{\"intValue\":\"{}\"}}}}",
+                json_string(key),
+                value
+            );
+        }
+        json.push(']');
+        match span.errno {
+            Some(errno) => {
+                let message = io::Error::from_raw_os_error(errno).to_string();
+                let _ = write!(
+                    json,
+                    ",\"status\":{{\"code\":2,\"message\":{}}}",
+                    json_string(&message)
+                );
+            }
+            None => json.push_str(",\"status\":{\"code\":1}"),
+        }
+        json.push('}');
+    }
+    json.push_str("]}]}]}");
+    json
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// A random identifier of `len` bytes, in hex.
+fn random_id(len: usize) -> String {
+    let mut bytes = [0u8; 16];
+    let bytes = &mut bytes[..len];
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::getrandom(bytes.as_mut_ptr().cast(), len, 0)
+    };
+    #[cfg(not(target_os = "linux"))]
+    unsafe {
+        libc::arc4random_buf(bytes.as_mut_ptr().cast(), len)
+    };
+    bytes.iter().fold(String::new(), |mut id, byte| {
+        let _ = write!(id, "{:02x}", byte);
+        id
+    })
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Bytes the null files read as, repeated over their whole size.
+#[derive(Debug)]
+pub struct Pattern(Vec<u8>);
+
+impl Pattern {
+    /// Reads the bytes of a template file, which must not be empty.
+    pub fn read(path: &Path) -> io::Result<Pattern> {
+        let bytes = fs::read(path)?;
+        if bytes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is empty", path.display()),
+            ));
+        }
+        Ok(Pattern(bytes))
+    }
+
+    /// Parses a pattern written in hexadecimal such as `0xDEADBEEF`, its bytes in the
+    /// order they are written.
+    pub fn parse(pattern: &str) -> io::Result<Pattern> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid pattern '{}'", pattern),
+            )
+        };
+        let digits = pattern
+            .strip_prefix("0x")
+            .or_else(|| pattern.strip_prefix("0X"))
+            .unwrap_or(pattern);
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes = (0..digits.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(digits.get(at..at + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        Ok(Pattern(bytes))
+    }
+
+    /// Parses a single byte such as `0xAA` or `170`.
+    pub fn parse_byte(byte: &str) -> io::Result<Pattern> {
+        let value = match byte.strip_prefix("0x").or_else(|| byte.strip_prefix("0X")) {
+            Some(digits) => u8::from_str_radix(digits, 16),
+            None => byte.parse(),
+        };
+        value.map(|value| Pattern(vec![value])).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid byte '{}'", byte),
+            )
+        })
+    }
+
+    /// Writes the pattern in hexadecimal as [`Pattern::parse`] takes it.
+    pub fn to_hex(&self) -> String {
+        let digits: String = self.0.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("0x{}", digits)
+    }
+
+    /// Returns up to `size` bytes at `offset` of a file `len` bytes long.
+    pub fn fill(&self, offset: u64, size: u32, len: u64) -> Vec<u8> {
+        let end = len.min(offset.saturating_add(size as u64));
+        let mut data = Vec::with_capacity(end.saturating_sub(offset) as usize);
+        let mut at = offset;
+        while at < end {
+            let start = (at % self.0.len() as u64) as usize;
+            let count = (self.0.len() - start).min((end - at) as usize);
+            data.extend_from_slice(&self.0[start..start + count]);
+            at += count as u64;
+        }
+        data
+    }
+}
+
+impl Default for Pattern {
+    /// Zeros, as /dev/zero reads.
+    fn default() -> Pattern {
+        Pattern(vec![0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!(
+            Pattern::parse("0xDEADbeef").unwrap().0,
+            [0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(Pattern::parse("00ff").unwrap().0, [0x00, 0xff]);
+        assert!(Pattern::parse("0x").is_err());
+        assert!(Pattern::parse("0xabc").is_err());
+        assert!(Pattern::parse("0xzz").is_err());
+        assert_eq!(Pattern::parse_byte("0xAA").unwrap().0, [0xaa]);
+        assert_eq!(Pattern::parse_byte("170").unwrap().0, [170]);
+        assert!(Pattern::parse_byte("256").is_err());
+        assert_eq!(Pattern::parse("0x0a0B").unwrap().to_hex(), "0x0A0B");
+    }
+
+    #[test]
+    fn fills_from_any_offset() {
+        let pattern = Pattern::parse("0x010203").unwrap();
+        assert_eq!(pattern.fill(2, 5, 100), [3, 1, 2, 3, 1]);
+        assert_eq!(pattern.fill(4, 5, 6), [2, 3]);
+        assert_eq!(pattern.fill(8, 5, 6), []);
+    }
+}
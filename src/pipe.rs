@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+type Reader = Box<dyn FnOnce(Vec<u8>) + Send>;
+
+/// What was written to a file and not read yet, each byte read once and in order, with
+/// the reads that came while there was nothing waiting for more.
+#[derive(Default)]
+pub struct Pipe(Mutex<State>);
+
+#[derive(Default)]
+struct State {
+    data: VecDeque<u8>,
+    readers: VecDeque<(u32, Reader)>,
+}
+
+impl Pipe {
+    /// Queues `data`, handing it to the readers waiting for some in the order they came.
+    pub fn write(&self, data: &[u8]) {
+        let mut answered = Vec::new();
+        {
+            let mut state = self.0.lock().unwrap();
+            state.data.extend(data);
+            while !state.data.is_empty() {
+                let Some((size, reader)) = state.readers.pop_front() else {
+                    break;
+                };
+                answered.push((reader, state.take(size)));
+            }
+        }
+        // Readers answer requests, which is not done with the pipe locked.
+        for (reader, data) in answered {
+            reader(data);
+        }
+    }
+
+    /// Hands up to `size` bytes to `reader`, waiting for a write if nothing is queued.
+    pub fn read(&self, size: u32, reader: impl FnOnce(Vec<u8>) + Send + 'static) {
+        let mut state = self.0.lock().unwrap();
+        if size > 0 && (state.data.is_empty() || !state.readers.is_empty()) {
+            state.readers.push_back((size, Box::new(reader)));
+            return;
+        }
+        let data = state.take(size);
+        drop(state);
+        reader(data);
+    }
+
+    /// Takes up to `size` bytes without waiting, nothing when none are queued.
+    pub fn take(&self, size: u32) -> Vec<u8> {
+        self.0.lock().unwrap().take(size)
+    }
+}
+
+impl State {
+    fn take(&mut self, size: u32) -> Vec<u8> {
+        let count = self.data.len().min(size as usize);
+        self.data.drain(..count).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn reads_consume_writes() {
+        let pipe = Pipe::default();
+        pipe.write(b"hello");
+        assert_eq!(pipe.take(2), b"he");
+        assert_eq!(pipe.take(8), b"llo");
+        assert_eq!(pipe.take(8), b"");
+    }
+
+    #[test]
+    fn readers_wait_for_writes_in_order() {
+        let pipe = Pipe::default();
+        let read = Arc::new(Mutex::new(Vec::new()));
+        for (reader, size) in [(1, 2), (2, 8)] {
+            let read = read.clone();
+            pipe.read(size, move |data| read.lock().unwrap().push((reader, data)));
+        }
+        assert!(read.lock().unwrap().is_empty());
+        pipe.write(b"abc");
+        assert_eq!(
+            *read.lock().unwrap(),
+            [(1, b"ab".to_vec()), (2, b"c".to_vec())]
+        );
+    }
+
+    #[test]
+    fn empty_reads_do_not_wait() {
+        let pipe = Pipe::default();
+        let read = Arc::new(Mutex::new(None));
+        let done = read.clone();
+        pipe.read(0, move |data| *done.lock().unwrap() = Some(data));
+        assert_eq!(*read.lock().unwrap(), Some(Vec::new()));
+    }
+}
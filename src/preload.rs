@@ -0,0 +1,629 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fuser::FileType;
+use libc::{c_int, EEXIST, ENOENT};
+
+use crate::config::Attrs;
+use crate::namespace::{Namespace, SetAttr, ROOT};
+
+const BLOCK: usize = 512;
+
+/// Files a mount starts out with, read from a tree dumped as JSON or from a tar archive
+/// whose data is skipped.
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    /// Path below the root, empty for the root itself.
+    path: PathBuf,
+    kind: Kind,
+    changes: SetAttr,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Directory,
+    File,
+    Symlink(OsString),
+    /// Another name of the file at the path.
+    Link(PathBuf),
+}
+
+impl Manifest {
+    /// Reads a JSON tree as `nullfs dump-tree` prints it, or an uncompressed tar archive.
+    pub fn read(path: &Path) -> io::Result<Manifest> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut start = [0; 1];
+        let is_json =
+            file.read(&mut start)? == 1 && (start[0] == b'{' || start[0].is_ascii_whitespace());
+        file.seek(SeekFrom::Start(0))?;
+        if is_json {
+            let mut json = String::new();
+            file.read_to_string(&mut json)?;
+            from_json(&json)
+        } else {
+            from_tar(file)
+        }
+    }
+
+    /// Number of files and directories listed, the root included if it is.
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Adds null files with `file` attributes at `paths` below the root, such as
+    /// `logs/out`, and the directories leading to them not listed yet with `dir` ones.
+    pub fn add_files<'a>(
+        &mut self,
+        paths: impl IntoIterator<Item = &'a Path>,
+        file: Attrs,
+        dir: Attrs,
+    ) -> io::Result<()> {
+        let changes = |attrs: Attrs| SetAttr {
+            perm: Some(attrs.perm),
+            uid: Some(attrs.uid),
+            gid: Some(attrs.gid),
+            ..SetAttr::default()
+        };
+        for path in paths {
+            let path = relative(path)?;
+            if path.as_os_str().is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "empty file name",
+                ));
+            }
+            let mut parents = path.ancestors().skip(1).collect::<Vec<_>>();
+            // From the root down, which is not listed.
+            parents.reverse();
+            for parent in parents.into_iter().skip(1) {
+                if !self.entries.iter().any(|entry| entry.path == parent) {
+                    self.entries.push(Entry {
+                        path: parent.to_path_buf(),
+                        kind: Kind::Directory,
+                        changes: changes(dir),
+                    });
+                }
+            }
+            self.entries.push(Entry {
+                path,
+                kind: Kind::File,
+                changes: changes(file),
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds the files to `namespace`, creating the directories leading to them if need be.
+    pub(crate) fn apply(&self, namespace: &mut Namespace) {
+        let mut created = Vec::new();
+        for entry in &self.entries {
+            match entry.create(namespace) {
+                Ok(Some(ino)) => created.push((ino, entry.changes)),
+                Ok(None) => {}
+                Err(err) => {
+                    let err = io::Error::from_raw_os_error(err);
+                    log::warn!("failed to preload /{}: {}", entry.path.display(), err);
+                }
+            }
+        }
+        // Only once every entry is in place, so that none moves the times of its directory.
+        for (ino, changes) in created {
+            let _ = namespace.set_attr(ino, changes);
+        }
+    }
+}
+
+impl Entry {
+    /// Creates the entry, or finds it already there, and returns the inode its attributes
+    /// belong to, none for another name of a file.
+    fn create(&self, namespace: &mut Namespace) -> Result<Option<u64>, c_int> {
+        let Some(name) = self.path.file_name() else {
+            return Ok(Some(ROOT));
+        };
+        let parent = directory(namespace, self.path.parent().unwrap())?;
+
+        let existing = namespace.child(parent, name).ok();
+        let ino = match (&self.kind, existing) {
+            (Kind::Directory, Some(ino)) if namespace.is_kind(ino, FileType::Directory) => ino,
+            (Kind::File, Some(ino)) if namespace.is_kind(ino, FileType::RegularFile) => ino,
+            (_, Some(_)) => return Err(EEXIST),
+            (Kind::Directory, None) => created(namespace, |namespace| {
+                namespace.create(parent, name, FileType::Directory, 0o755, 0, 0)
+            })?,
+            (Kind::File, None) => created(namespace, |namespace| {
+                namespace.create(parent, name, FileType::RegularFile, 0o644, 0, 0)
+            })?,
+            (Kind::Symlink(target), None) => created(namespace, |namespace| {
+                namespace.symlink(parent, name, target, 0, 0)
+            })?,
+            (Kind::Link(target), None) => {
+                let ino = resolve(namespace, target)?;
+                created(namespace, |namespace| namespace.link(ino, parent, name))?;
+                return Ok(None);
+            }
+        };
+        Ok(Some(ino))
+    }
+}
+
+/// Runs `create` and drops the reference it counts for the kernel, which knows nothing of
+/// the file yet.
+fn created(
+    namespace: &mut Namespace,
+    create: impl FnOnce(&mut Namespace) -> Result<fuser::FileAttr, c_int>,
+) -> Result<u64, c_int> {
+    let ino = create(namespace)?.ino;
+    namespace.forget(ino, 1);
+    Ok(ino)
+}
+
+fn resolve(namespace: &Namespace, path: &Path) -> Result<u64, c_int> {
+    path.iter()
+        .try_fold(ROOT, |dir, name| namespace.child(dir, name))
+}
+
+/// Resolves the directory at `path`, creating what is missing of it.
+fn directory(namespace: &mut Namespace, path: &Path) -> Result<u64, c_int> {
+    let mut dir = ROOT;
+    for name in path.iter() {
+        dir = match namespace.child(dir, name) {
+            Ok(ino) => ino,
+            Err(ENOENT) => created(namespace, |namespace| {
+                namespace.create(dir, name, FileType::Directory, 0o755, 0, 0)
+            })?,
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(dir)
+}
+
+/// Turns a path from a manifest into one below the root, refusing any that leaves it.
+fn relative(path: &Path) -> io::Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                let message = format!("path {} leaves the root", path.display());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        }
+    }
+    Ok(relative)
+}
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", what))
+}
+
+fn from_json(json: &str) -> io::Result<Manifest> {
+    let mut parser = Parser {
+        json: json.as_bytes(),
+        at: 0,
+    };
+    let root = parser.value().ok_or_else(|| malformed("JSON tree"))?;
+    parser.skip_whitespace();
+    if parser.at != json.len() {
+        return Err(malformed("JSON tree"));
+    }
+
+    let mut manifest = Manifest::default();
+    let mut links = HashMap::new();
+    add_json(&mut manifest, &mut links, PathBuf::new(), &root)?;
+    Ok(manifest)
+}
+
+/// Adds the file `node` describes at `path`, and what it contains.
+fn add_json(
+    manifest: &mut Manifest,
+    links: &mut HashMap<u64, PathBuf>,
+    path: PathBuf,
+    node: &Json,
+) -> io::Result<()> {
+    let number = |name| node.get(name).and_then(Json::number);
+    let time = |name| {
+        number(name)
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .map(|since| SystemTime::UNIX_EPOCH + since)
+    };
+    let kind = match node.get("type").and_then(Json::string) {
+        Some("directory") => Kind::Directory,
+        Some("symlink") => {
+            let target = node.get("target").and_then(Json::string).unwrap_or("");
+            Kind::Symlink(target.into())
+        }
+        None if node.get("entries").is_some() => Kind::Directory,
+        _ => Kind::File,
+    };
+    let kind = match (kind, number("ino").map(|ino| ino as u64)) {
+        // Hard links share their inode number.
+        (Kind::File, Some(ino)) => match links.get(&ino) {
+            Some(target) => Kind::Link(target.clone()),
+            None => {
+                links.insert(ino, path.clone());
+                Kind::File
+            }
+        },
+        (kind, _) => kind,
+    };
+    let changes = SetAttr {
+        perm: number("mode").map(|mode| mode as u16),
+        uid: number("uid").map(|uid| uid as u32),
+        gid: number("gid").map(|gid| gid as u32),
+        size: number("size")
+            .map(|size| size as u64)
+            .filter(|_| matches!(kind, Kind::File)),
+        atime: time("atime"),
+        mtime: time("mtime"),
+    };
+    let is_dir = matches!(kind, Kind::Directory);
+    manifest.entries.push(Entry {
+        path: path.clone(),
+        kind,
+        changes,
+    });
+
+    if is_dir {
+        for child in node.get("entries").and_then(Json::array).unwrap_or(&[]) {
+            let name = child.get("name").and_then(Json::string).unwrap_or("");
+            let child_path = relative(&path.join(name))?;
+            if name.is_empty() || child_path.parent() != Some(&path) {
+                return Err(malformed("entry name"));
+            }
+            add_json(manifest, links, child_path, child)?;
+        }
+    }
+    Ok(())
+}
+
+/// A JSON value, numbers kept as written.
+enum Json {
+    Null,
+    Bool,
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => number.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn string(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    json: &'a [u8],
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.json.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+    }
+
+    /// Consumes `token` if it comes next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.json[self.at..].starts_with(token.as_bytes());
+        if found {
+            self.at += token.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match *self.json.get(self.at)? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => self.string().map(Json::String),
+            _ if self.eat("null") => Some(Json::Null),
+            _ if self.eat("true") || self.eat("false") => Some(Json::Bool),
+            _ => self.number(),
+        }
+    }
+
+    fn object(&mut self) -> Option<Json> {
+        self.eat("{");
+        let mut fields = Vec::new();
+        if self.eat("}") {
+            return Some(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            if !self.eat(":") {
+                return None;
+            }
+            fields.push((name, self.value()?));
+            if self.eat("}") {
+                return Some(Json::Object(fields));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Json> {
+        self.eat("[");
+        let mut values = Vec::new();
+        if self.eat("]") {
+            return Some(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            if self.eat("]") {
+                return Some(Json::Array(values));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let start = self.at;
+        while self
+            .json
+            .get(self.at)
+            .is_some_and(|c| c.is_ascii_digit() || b"+-.eE".contains(c))
+        {
+            self.at += 1;
+        }
+        let number = std::str::from_utf8(&self.json[start..self.at]).ok()?;
+        number
+            .parse::<f64>()
+            .ok()
+            .map(|_| Json::Number(number.to_string()))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if self.json.get(self.at) != Some(&b'"') {
+            return None;
+        }
+        self.at += 1;
+        let mut string = Vec::new();
+        loop {
+            match *self.json.get(self.at)? {
+                b'"' => break,
+                b'\\' => {
+                    self.at += 1;
+                    match *self.json.get(self.at)? {
+                        b'b' => string.push(8),
+                        b'f' => string.push(12),
+                        b'n' => string.push(b'\n'),
+                        b'r' => string.push(b'\r'),
+                        b't' => string.push(b'\t'),
+                        b'u' => {
+                            let mut unit = self.unit()?;
+                            // A surrogate pair spells a character beyond the first plane.
+                            if (0xd800..0xdc00).contains(&unit)
+                                && self.json[self.at + 1..].starts_with(b"\\u")
+                            {
+                                self.at += 2;
+                                let low = self.unit()?;
+                                unit = 0x10000
+                                    + ((unit - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            let c = char::from_u32(unit).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            string.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        c => string.push(c),
+                    }
+                }
+                c => string.push(c),
+            }
+            self.at += 1;
+        }
+        self.at += 1;
+        String::from_utf8(string).ok()
+    }
+
+    /// Reads the four hexadecimal digits of a \u escape, leaving the last one current.
+    fn unit(&mut self) -> Option<u32> {
+        let digits = self.json.get(self.at + 1..self.at + 5)?;
+        self.at += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+fn from_tar(mut tar: impl Read + Seek) -> io::Result<Manifest> {
+    let mut manifest = Manifest::default();
+    let mut header = [0; BLOCK];
+    // Names too long for the header, given by the GNU or pax entry before.
+    let (mut long_name, mut long_link) = (None, None);
+    loop {
+        match tar.read_exact(&mut header) {
+            Ok(()) => {}
+            // Archives are not always padded to the end.
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        if header.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        let size = octal(&header[124..136]).ok_or_else(|| malformed("tar header"))?;
+        let padded = size.div_ceil(BLOCK as u64) * BLOCK as u64;
+        let typeflag = header[156];
+
+        let special = match typeflag {
+            b'L' | b'K' => {
+                let mut name = read_data(&mut tar, size, padded)?;
+                while name.last() == Some(&0) {
+                    name.pop();
+                }
+                let name = PathBuf::from(OsString::from_vec(name));
+                match typeflag {
+                    b'L' => long_name = Some(name),
+                    _ => long_link = Some(name),
+                }
+                true
+            }
+            b'x' => {
+                let records = read_data(&mut tar, size, padded)?;
+                for (key, value) in pax_records(&records) {
+                    match key {
+                        b"path" => long_name = Some(PathBuf::from(OsStr::from_bytes(value))),
+                        b"linkpath" => long_link = Some(PathBuf::from(OsStr::from_bytes(value))),
+                        _ => {}
+                    }
+                }
+                true
+            }
+            b'g' => {
+                tar.seek(SeekFrom::Current(padded as i64))?;
+                true
+            }
+            _ => false,
+        };
+        if special {
+            continue;
+        }
+
+        let path = match long_name.take() {
+            Some(path) => path,
+            None => {
+                let mut path = PathBuf::from(OsStr::from_bytes(field(&header[345..500])));
+                path.push(OsStr::from_bytes(field(&header[..100])));
+                path
+            }
+        };
+        let link = long_link
+            .take()
+            .unwrap_or_else(|| PathBuf::from(OsStr::from_bytes(field(&header[157..257]))));
+        let path = relative(&path)?;
+        let kind = match typeflag {
+            b'0' | 0 | b'7' => Kind::File,
+            b'1' => Kind::Link(relative(&link)?),
+            b'2' => Kind::Symlink(link.into_os_string()),
+            b'5' => Kind::Directory,
+            _ => {
+                log::warn!(
+                    "skipping /{}, of a type that cannot be preloaded",
+                    path.display()
+                );
+                tar.seek(SeekFrom::Current(padded as i64))?;
+                continue;
+            }
+        };
+        let number = |range: std::ops::Range<usize>| octal(&header[range]);
+        let changes = SetAttr {
+            perm: number(100..108).map(|mode| mode as u16 & 0o7777),
+            uid: number(108..116).map(|uid| uid as u32),
+            gid: number(116..124).map(|gid| gid as u32),
+            size: matches!(kind, Kind::File).then_some(size),
+            atime: None,
+            mtime: number(136..148)
+                .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)),
+        };
+        manifest.entries.push(Entry {
+            path,
+            kind,
+            changes,
+        });
+        // Only files carry data, which is not kept.
+        if typeflag != b'1' && typeflag != b'2' && typeflag != b'5' {
+            tar.seek(SeekFrom::Current(padded as i64))?;
+        }
+    }
+    Ok(manifest)
+}
+
+/// Reads the data of an entry that describes the next one.
+fn read_data(tar: &mut impl Read, size: u64, padded: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    tar.take(padded).read_to_end(&mut data)?;
+    if (data.len() as u64) < padded {
+        return Err(malformed("tar archive"));
+    }
+    data.truncate(size as usize);
+    Ok(data)
+}
+
+/// Splits pax extended header records of the form "LENGTH KEY=VALUE\n".
+fn pax_records(mut records: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut pairs = Vec::new();
+    while let Some(space) = records.iter().position(|byte| *byte == b' ') {
+        let length = std::str::from_utf8(&records[..space])
+            .ok()
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|length| *length > space && *length <= records.len());
+        let Some(length) = length else {
+            break;
+        };
+        let record = &records[space + 1..length - 1];
+        if let Some(equals) = record.iter().position(|byte| *byte == b'=') {
+            pairs.push((&record[..equals], &record[equals + 1..]));
+        }
+        records = &records[length..];
+    }
+    pairs
+}
+
+/// A string field of a header, ending at the first NUL.
+fn field(bytes: &[u8]) -> &[u8] {
+    let end = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+/// A numeric field of a header, in octal or, with the high bit set, in base 256.
+fn octal(bytes: &[u8]) -> Option<u64> {
+    if bytes[0] & 0x80 != 0 {
+        return Some(
+            bytes[1..]
+                .iter()
+                .fold(0, |value, byte| value << 8 | *byte as u64),
+        );
+    }
+    let digits = std::str::from_utf8(field(bytes)).ok()?.trim();
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
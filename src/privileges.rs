@@ -0,0 +1,47 @@
+use std::io;
+
+use crate::callers;
+
+/// An unprivileged account the daemon switches to once it has mounted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Account {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Account {
+    /// Parses `USER[:GROUP]` by ids or names, the group defaulting to the primary one of the user.
+    pub fn parse(account: &str) -> Result<Account, String> {
+        let (user, group) = match account.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (account, None),
+        };
+        let uid = callers::parse_uid(user)?;
+        let gid = match group {
+            Some(group) => callers::parse_gid(group)?,
+            None => primary_gid(uid).ok_or_else(|| format!("no group given for '{}'", user))?,
+        };
+        Ok(Account { uid, gid })
+    }
+
+    /// Switches every thread of the process to the account, leaving only its group.
+    pub fn switch(&self) -> io::Result<()> {
+        unsafe {
+            if libc::setgroups(1, &self.gid) == -1
+                || libc::setgid(self.gid) == -1
+                || libc::setuid(self.uid) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if self.uid != 0 && libc::setuid(0) == 0 {
+                return Err(io::Error::other("root privileges could be regained"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn primary_gid(uid: u32) -> Option<u32> {
+    let passwd = unsafe { libc::getpwuid(uid) };
+    (!passwd.is_null()).then(|| unsafe { (*passwd).pw_gid })
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, DirBuilderExt, FileExt, OpenOptionsExt};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+use libc::{O_ACCMODE, O_CREAT, O_RDWR};
+
+use crate::trace::{Operation, Reader, Record};
+
+/// How a replay went.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub replayed: u64,
+    pub failed: u64,
+}
+
+/// Replays the trace in `trace` against the directory `target`, which stands in for
+/// the root of the recorded mount.
+///
+/// Requests are spaced out like they were recorded, divided by `speed`; a speed of
+/// zero replays them back to back. Requests that fail are logged and skipped.
+pub fn replay(trace: &Path, target: &Path, speed: f64) -> io::Result<Summary> {
+    let mut replay = Replay {
+        target: target.to_path_buf(),
+        files: HashMap::new(),
+    };
+    let mut summary = Summary::default();
+    let start = Instant::now();
+    for record in Reader::open(trace)? {
+        let record = record?;
+        if speed > 0.0 {
+            let due = record.time.div_f64(speed);
+            thread::sleep(due.saturating_sub(start.elapsed()));
+        }
+        match replay.apply(&record) {
+            Ok(()) => summary.replayed += 1,
+            Err(err) => {
+                log::warn!(
+                    "failed to replay {:?} at {:?}: {}",
+                    record.operation,
+                    record.time,
+                    err
+                );
+                summary.failed += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+struct Replay {
+    target: PathBuf,
+    /// Files opened by the replay, by the handle they had when recorded.
+    files: HashMap<u64, File>,
+}
+
+impl Replay {
+    /// Maps a path within the recorded mount to the target.
+    fn path(&self, path: &Path) -> PathBuf {
+        self.target.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    fn file(&self, fh: u64) -> io::Result<&File> {
+        self.files
+            .get(&fh)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file was not opened"))
+    }
+
+    fn apply(&mut self, record: &Record) -> io::Result<()> {
+        let path = self.path(&record.path);
+        match record.operation {
+            Operation::Open => {
+                let flags = record.mode as i32;
+                let file = OpenOptions::new()
+                    .read(flags & O_ACCMODE != libc::O_WRONLY)
+                    .write(flags & O_ACCMODE != libc::O_RDONLY)
+                    .custom_flags(flags & !(O_ACCMODE | O_CREAT))
+                    .open(path)?;
+                self.files.insert(record.fh, file);
+            }
+            Operation::Create => {
+                let file = OpenOptions::new()
+                    .custom_flags(O_RDWR | O_CREAT)
+                    .read(true)
+                    .write(true)
+                    .mode(record.mode & 0o7777)
+                    .open(path)?;
+                self.files.insert(record.fh, file);
+            }
+            Operation::Read => {
+                let mut buf = vec![0; record.size as usize];
+                self.file(record.fh)?
+                    .read_at(&mut buf, record.offset as u64)?;
+            }
+            Operation::Write => {
+                let data = match record.data.is_empty() {
+                    true => vec![0; record.size as usize],
+                    false => record.data.clone(),
+                };
+                self.file(record.fh)?
+                    .write_all_at(&data, record.offset as u64)?;
+            }
+            // Flushes come with every close of a descriptor, which the replay
+            // does once on release.
+            Operation::Flush => {
+                self.file(record.fh)?;
+            }
+            Operation::Fsync => self.file(record.fh)?.sync_all()?,
+            Operation::Release => {
+                self.files.remove(&record.fh);
+            }
+            Operation::Mkdir => DirBuilder::new().mode(record.mode & 0o7777).create(path)?,
+            Operation::Unlink => fs::remove_file(path)?,
+            Operation::Rmdir => fs::remove_dir(path)?,
+            Operation::Rename => rename(&path, &self.path(&record.target), record.mode)?,
+            Operation::Link => fs::hard_link(path, self.path(&record.target))?,
+            // The target of a symbolic link is kept as it was.
+            Operation::Symlink => symlink(&record.target, path)?,
+            Operation::Truncate => OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(record.size)?,
+            Operation::Fallocate => fallocate(
+                self.file(record.fh)?,
+                record.mode,
+                record.offset,
+                record.size,
+            )?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn fallocate(file: &File, mode: u32, offset: i64, len: u64) -> io::Result<()> {
+    if unsafe { libc::fallocate(file.as_raw_fd(), mode as i32, offset, len as i64) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallocate(_file: &File, _mode: u32, _offset: i64, _len: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "fallocate is only replayed on Linux",
+    ))
+}
+
+/// Renames with the flags of renameat2, such as RENAME_NOREPLACE, or of renamex_np on macOS.
+fn rename(from: &Path, to: &Path, flags: u32) -> io::Result<()> {
+    if flags == 0 {
+        return fs::rename(from, to);
+    }
+    rename_with_flags(
+        &CString::new(from.as_os_str().as_bytes())?,
+        &CString::new(to.as_os_str().as_bytes())?,
+        flags,
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn rename_with_flags(from: &CString, to: &CString, flags: u32) -> io::Result<()> {
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            flags,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn rename_with_flags(from: &CString, to: &CString, flags: u32) -> io::Result<()> {
+    if unsafe { libc::renamex_np(from.as_ptr(), to.as_ptr(), flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn rename_with_flags(_from: &CString, _to: &CString, _flags: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "renames with flags are only replayed on Linux and macOS",
+    ))
+}
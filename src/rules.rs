@@ -0,0 +1,204 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+
+use libc::c_int;
+
+use crate::logger::json_string;
+use crate::options;
+
+/// Errors a rule can answer with, by name.
+const ERRNOS: &[(&str, c_int)] = &[
+    ("EPERM", libc::EPERM),
+    ("ENOENT", libc::ENOENT),
+    ("EIO", libc::EIO),
+    ("EACCES", libc::EACCES),
+    ("EEXIST", libc::EEXIST),
+    ("EFBIG", libc::EFBIG),
+    ("ENOSPC", libc::ENOSPC),
+    ("EROFS", libc::EROFS),
+    ("ENAMETOOLONG", libc::ENAMETOOLONG),
+    ("EDQUOT", libc::EDQUOT),
+];
+
+/// What happens to files whose name matches a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Serve them like any other null file.
+    Discard,
+    /// Fail lookups, creation and writes with an errno.
+    Errno(c_int),
+    /// Fail writes reaching beyond this many bytes with EDQUOT.
+    Quota(u64),
+    /// Answer every read in full, like /dev/zero.
+    Endless,
+    /// Read back the last write, up to this many bytes of it.
+    Echo(u64),
+    /// Have reads consume what was written, waiting for a write when nothing is left.
+    Pipe,
+}
+
+impl fmt::Display for Action {
+    /// Writes the action as [`Rule::parse`] takes it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Discard => f.write_str("discard"),
+            Action::Errno(errno) => match ERRNOS.iter().find(|(_, known)| known == errno) {
+                Some((name, _)) => f.write_str(name),
+                None => write!(f, "{}", errno),
+            },
+            Action::Quota(size) => write!(f, "quota {}", size),
+            Action::Endless => f.write_str("endless"),
+            Action::Echo(size) => write!(f, "echo {}", size),
+            Action::Pipe => f.write_str("pipe"),
+        }
+    }
+}
+
+/// A glob pattern of names and what happens to the files they match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pattern: String,
+    action: Action,
+}
+
+impl Rule {
+    /// Parses `PATTERN => ACTION`, the action being `discard`, `endless`, `pipe`, an errno
+    /// such as `EPERM`, `quota SIZE` or `echo SIZE`.
+    pub fn parse(rule: &str) -> Result<Rule, String> {
+        let (pattern, action) = rule
+            .split_once("=>")
+            .ok_or_else(|| format!("expected PATTERN => ACTION in '{}'", rule))?;
+        let pattern = pattern.trim();
+        let action = action.trim();
+        if pattern.is_empty() {
+            return Err(format!("missing pattern in '{}'", rule));
+        }
+        let action = match action.split_once(char::is_whitespace) {
+            Some(("quota", size)) => {
+                Action::Quota(options::parse_size(size.trim()).map_err(|err| err.to_string())?)
+            }
+            Some(("echo", size)) => {
+                Action::Echo(options::parse_size(size.trim()).map_err(|err| err.to_string())?)
+            }
+            _ if action == "discard" => Action::Discard,
+            _ if action == "endless" => Action::Endless,
+            _ if action == "pipe" => Action::Pipe,
+            _ => match ERRNOS.iter().find(|(name, _)| *name == action) {
+                Some((_, errno)) => Action::Errno(*errno),
+                None => return Err(format!("unknown action '{}'", action)),
+            },
+        };
+        Ok(Rule {
+            pattern: pattern.to_string(),
+            action,
+        })
+    }
+
+    /// Describes the rule as JSON.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"pattern\":{},\"action\":{}}}",
+            json_string(&self.pattern),
+            json_string(&self.action.to_string())
+        )
+    }
+}
+
+/// Returns the action of the first rule matching `name`, if any does.
+pub fn find(rules: &[Rule], name: &OsStr) -> Option<Action> {
+    rules
+        .iter()
+        .find(|rule| glob(rule.pattern.as_bytes(), name.as_bytes()))
+        .map(|rule| rule.action)
+}
+
+/// Matches `name` against a shell pattern with `*`, `?` and `[...]` classes.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+        Some((b'[', rest)) => {
+            let Some(end) = rest.iter().skip(1).position(|&c| c == b']').map(|i| i + 1) else {
+                return name.first() == Some(&b'[') && glob(rest, &name[1..]);
+            };
+            let Some((&c, name)) = name.split_first() else {
+                return false;
+            };
+            let (negated, class) = match rest[..end].split_first() {
+                Some((b'!' | b'^', class)) => (true, class),
+                _ => (false, &rest[..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && glob(&rest[end + 1..], name)
+        }
+        Some((&c, rest)) => name.first() == Some(&c) && glob(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert!(glob(b"*.log", b"app.log"));
+        assert!(glob(b"*.log", b".log"));
+        assert!(!glob(b"*.log", b"app.log.1"));
+        assert!(glob(b"a?c", b"abc"));
+        assert!(!glob(b"a?c", b"ac"));
+        assert!(glob(b"[a-c]x", b"bx"));
+        assert!(!glob(b"[!a-c]x", b"bx"));
+        assert!(glob(b"[^a-c]x", b"dx"));
+        assert!(glob(b"[x", b"[x"));
+        assert!(glob(b"*", b""));
+    }
+
+    #[test]
+    fn parses_actions() {
+        let action = |rule: &str| Rule::parse(rule).map(|rule| rule.action);
+        assert_eq!(action("*.tmp => discard"), Ok(Action::Discard));
+        assert_eq!(action("*.lock=>EPERM"), Ok(Action::Errno(libc::EPERM)));
+        assert_eq!(action("big => quota 1MiB"), Ok(Action::Quota(1 << 20)));
+        assert_eq!(action("echo => echo 4K"), Ok(Action::Echo(4096)));
+        assert_eq!(action("fifo => pipe"), Ok(Action::Pipe));
+        assert!(action("*.tmp").is_err());
+        assert!(action(" => discard").is_err());
+        assert!(action("x => EWHATEVER").is_err());
+        assert!(action("x => quota lots").is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let rules = [
+            Rule::parse("*.lock => EPERM").unwrap(),
+            Rule::parse("* => endless").unwrap(),
+        ];
+        assert_eq!(
+            find(&rules, OsStr::new("db.lock")),
+            Some(Action::Errno(libc::EPERM))
+        );
+        assert_eq!(find(&rules, OsStr::new("db")), Some(Action::Endless));
+        assert_eq!(find(&rules[..1], OsStr::new("db")), None);
+    }
+
+    #[test]
+    fn actions_read_back() {
+        for rule in ["a => EROFS", "a => quota 10", "a => echo 3", "a => endless"] {
+            let action = Rule::parse(rule).unwrap().action;
+            let again = Rule::parse(&format!("a => {}", action)).unwrap().action;
+            assert_eq!(action, again);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::io;
+
+use libc::{c_long, sock_filter, sock_fprog};
+
+/// Audit architecture the filter is written for, checked before the syscall numbers.
+#[cfg(target_arch = "x86_64")]
+const ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ARCH: Option<u32> = None;
+
+/// Offsets into `seccomp_data`.
+const NR: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+const FIRST_ARG: u32 = 16;
+
+/// Syscalls serving /dev/fuse, the control socket, logging, spans, stats and shutdown.
+const ALLOWED: &[c_long] = &[
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_preadv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_pwrite64,
+    libc::SYS_pwritev,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_lseek,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_unlinkat,
+    libc::SYS_umount2,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_brk,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_uname,
+    libc::SYS_getrandom,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigtimedwait,
+    libc::SYS_sigaltstack,
+    libc::SYS_tgkill,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_accept,
+    libc::SYS_accept4,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockopt,
+    libc::SYS_shutdown,
+    libc::SYS_ppoll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+];
+
+/// Confines every thread of the process to the syscalls it needs once mounted, the
+/// others failing with EPERM. New threads may still be spawned, but no process or
+/// program started.
+pub fn install() -> io::Result<()> {
+    let arch = ARCH.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no syscall filter for this architecture",
+        )
+    })?;
+    let mut filter = vec![
+        load(ARCH_OFFSET),
+        jump(libc::BPF_JEQ, arch, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(NR),
+    ];
+    for &nr in ALLOWED {
+        filter.push(jump(libc::BPF_JEQ, nr as u32, 0, 1));
+        filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    }
+    // Threads share their parent's memory; anything else would be a new process.
+    filter.push(jump(libc::BPF_JEQ, libc::SYS_clone as u32, 0, 4));
+    filter.push(load(FIRST_ARG));
+    filter.push(jump(libc::BPF_JSET, libc::CLONE_THREAD as u32, 0, 1));
+    filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+    // Its flags are out of reach, so the C library is made to fall back to clone.
+    filter.push(jump(libc::BPF_JEQ, libc::SYS_clone3 as u32, 0, 1));
+    filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32));
+    filter.push(ret(libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+
+    let program = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &program,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        // Synchronizing the threads failed on the one returned.
+        thread => Err(io::Error::other(format!(
+            "thread {} could not be confined",
+            thread
+        ))),
+    }
+}
+
+fn load(offset: u32) -> sock_filter {
+    sock_filter {
+        code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+        jt: 0,
+        jf: 0,
+        k: offset,
+    }
+}
+
+fn jump(condition: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: (libc::BPF_JMP | condition | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn ret(action: u32) -> sock_filter {
+    sock_filter {
+        code: (libc::BPF_RET | libc::BPF_K) as u16,
+        jt: 0,
+        jf: 0,
+        k: action,
+    }
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::ptr;
+#[cfg(target_os = "macos")]
+use std::thread;
+use std::time::Duration;
+#[cfg(target_os = "macos")]
+use std::time::Instant;
+
+use libc::{c_int, sigset_t};
+
+/// How often pending signals are looked for where they cannot be waited for with a timeout.
+#[cfg(target_os = "macos")]
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A set of signals that are blocked in the calling thread and every thread
+/// spawned from it, so they can be received synchronously with [`SignalSet::wait`].
+#[derive(Clone, Copy)]
+pub struct SignalSet {
+    set: sigset_t,
+}
+
+impl SignalSet {
+    pub fn block(signals: &[c_int]) -> io::Result<SignalSet> {
+        let mut set = MaybeUninit::<sigset_t>::uninit();
+        unsafe {
+            libc::sigemptyset(set.as_mut_ptr());
+            for &signal in signals {
+                libc::sigaddset(set.as_mut_ptr(), signal);
+            }
+        }
+        let set = unsafe { set.assume_init() };
+
+        match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) } {
+            0 => Ok(SignalSet { set }),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+
+    pub fn wait(&self) -> io::Result<c_int> {
+        let mut signal: c_int = 0;
+        match unsafe { libc::sigwait(&self.set, &mut signal) } {
+            0 => Ok(signal),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+
+    /// Like [`SignalSet::wait`], but gives up after `timeout`.
+    #[cfg(not(target_os = "macos"))]
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<Option<c_int>> {
+        let timeout = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        match unsafe { libc::sigtimedwait(&self.set, ptr::null_mut(), &timeout) } {
+            -1 => match io::Error::last_os_error() {
+                err if err.raw_os_error() == Some(libc::EAGAIN) => Ok(None),
+                err => Err(err),
+            },
+            signal => Ok(Some(signal)),
+        }
+    }
+    /// Like [`SignalSet::wait`], but gives up after `timeout`. Lacking sigtimedwait, the
+    /// pending signals are polled for.
+    #[cfg(target_os = "macos")]
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<Option<c_int>> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let mut pending = MaybeUninit::<sigset_t>::uninit();
+            if unsafe { libc::sigpending(pending.as_mut_ptr()) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let pending = unsafe { pending.assume_init() };
+            // Signal numbers of macOS stop short of 32.
+            if (1..32).any(|signal| unsafe {
+                libc::sigismember(&self.set, signal) == 1
+                    && libc::sigismember(&pending, signal) == 1
+            }) {
+                return self.wait().map(Some);
+            }
+
+            let now = Instant::now();
+            match deadline {
+                Some(deadline) if now >= deadline => return Ok(None),
+                Some(deadline) => thread::sleep(POLL_INTERVAL.min(deadline - now)),
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
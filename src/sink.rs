@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::io;
+
+/// Destination of the data written to the filesystem.
+/// Writes can arrive from several threads at once.
+pub trait DataSink: Send + Sync {
+    /// Consumes `data` written to `ino` at `offset` and returns the number of bytes accepted.
+    fn write(&self, ino: u64, offset: i64, data: &[u8]) -> io::Result<usize>;
+
+    fn flush(&self, _ino: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Flushes and releases everything held by the sink when the filesystem shuts down.
+    fn close(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Origin of the data read from the filesystem.
+/// Reads can arrive from several threads at once.
+pub trait DataSource: Send + Sync {
+    /// Returns up to `size` bytes of `ino` at `offset`, or nothing at the end of the file.
+    fn read(&self, ino: u64, offset: i64, size: u32) -> io::Result<Vec<u8>>;
+
+    /// Releases everything held by the source when the filesystem shuts down.
+    fn close(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Throws every byte away.
+pub struct Discard;
+
+impl DataSink for Discard {
+    fn write(&self, _ino: u64, _offset: i64, data: &[u8]) -> io::Result<usize> {
+        Ok(data.len())
+    }
+}
+
+/// Reports the end of the file on every read.
+pub struct Empty;
+
+impl DataSource for Empty {
+    fn read(&self, _ino: u64, _offset: i64, _size: u32) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+type SinkFactory = Box<dyn Fn(Option<&str>) -> io::Result<Box<dyn DataSink>>>;
+type SourceFactory = Box<dyn Fn(Option<&str>) -> io::Result<Box<dyn DataSource>>>;
+
+/// Named sinks and sources that can be selected with a `name[:argument]` specification.
+pub struct Registry {
+    sinks: BTreeMap<String, SinkFactory>,
+    sources: BTreeMap<String, SourceFactory>,
+}
+
+impl Registry {
+    /// Creates a registry containing the built-in `discard` sink and `empty` source.
+    pub fn new() -> Registry {
+        let mut registry = Registry {
+            sinks: BTreeMap::new(),
+            sources: BTreeMap::new(),
+        };
+        registry.register_sink("discard", |_| Ok(Box::new(Discard)));
+        registry.register_source("empty", |_| Ok(Box::new(Empty)));
+        registry
+    }
+
+    /// Registers a sink, replacing any sink previously registered under the same name.
+    pub fn register_sink<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(Option<&str>) -> io::Result<Box<dyn DataSink>> + 'static,
+    {
+        self.sinks.insert(name.to_string(), Box::new(factory));
+    }
+
+    /// Registers a source, replacing any source previously registered under the same name.
+    pub fn register_source<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(Option<&str>) -> io::Result<Box<dyn DataSource>> + 'static,
+    {
+        self.sources.insert(name.to_string(), Box::new(factory));
+    }
+
+    pub fn sink_names(&self) -> impl Iterator<Item = &str> {
+        self.sinks.keys().map(String::as_str)
+    }
+
+    pub fn source_names(&self) -> impl Iterator<Item = &str> {
+        self.sources.keys().map(String::as_str)
+    }
+
+    pub fn sink(&self, spec: &str) -> io::Result<Box<dyn DataSink>> {
+        let (name, argument) = split_spec(spec);
+        match self.sinks.get(name) {
+            Some(factory) => factory(argument),
+            None => Err(unknown("sink", name, self.sink_names())),
+        }
+    }
+
+    pub fn source(&self, spec: &str) -> io::Result<Box<dyn DataSource>> {
+        let (name, argument) = split_spec(spec);
+        match self.sources.get(name) {
+            Some(factory) => factory(argument),
+            None => Err(unknown("source", name, self.source_names())),
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (spec, None),
+    }
+}
+
+fn unknown<'a>(kind: &str, name: &str, names: impl Iterator<Item = &'a str>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "unknown {} '{}' (available: {})",
+            kind,
+            name,
+            names.collect::<Vec<_>>().join(", ")
+        ),
+    )
+}
@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Buckets of the write size histogram, one per power of two up to 2GiB.
+const SIZE_BUCKETS: usize = 32;
+
+/// Pids whose traffic is kept apart at most. Past that, those that exited are forgotten,
+/// then the one that moved the fewest bytes.
+const MAX_PIDS: usize = 1024;
+
+/// Counters kept by a mounted filesystem.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// File and directory handles handed out and not released yet.
+    pub open_handles: AtomicU64,
+    /// Files and directories in the namespace, including removed ones still referenced.
+    pub inodes: AtomicU64,
+    pub reads: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub writes: AtomicU64,
+    pub bytes_written: AtomicU64,
+    /// Writes counted by their size rounded up to a power of two, from 1 byte to 2GiB.
+    write_sizes: [AtomicU64; SIZE_BUCKETS],
+    /// Reads and writes broken down by the uid that asked for them.
+    by_uid: RwLock<BTreeMap<u32, Traffic>>,
+    /// Reads and writes broken down by the pid that asked for them, for up to `MAX_PIDS`.
+    by_pid: RwLock<BTreeMap<u32, Traffic>>,
+}
+
+/// Reads and writes of a single caller.
+#[derive(Debug, Default)]
+struct Traffic {
+    reads: AtomicU64,
+    bytes_read: AtomicU64,
+    writes: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Traffic {
+    fn bytes(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed) + self.bytes_written.load(Ordering::Relaxed)
+    }
+}
+
+impl Stats {
+    /// Counts a read of `bytes` asked for by `uid` and `pid`.
+    pub fn record_read(&self, uid: u32, pid: u32, bytes: u64) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        self.by_caller(uid, pid, |traffic| {
+            traffic.reads.fetch_add(1, Ordering::Relaxed);
+            traffic.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        });
+    }
+
+    /// Counts a write of `bytes` asked for by `uid` and `pid`.
+    pub fn record_write(&self, uid: u32, pid: u32, bytes: u64) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        let bucket = bytes.next_power_of_two().trailing_zeros() as usize;
+        self.write_sizes[bucket.min(SIZE_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.by_caller(uid, pid, |traffic| {
+            traffic.writes.fetch_add(1, Ordering::Relaxed);
+            traffic.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        });
+    }
+
+    /// Takes the traffic counters as they are now, to tell throughput over time.
+    pub fn sample(&self) -> Sample {
+        Sample {
+            at: Instant::now(),
+            reads: self.reads.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns every counter by name: the totals, then the write size histogram as
+    /// `write_size.BYTES` for writes of up to that many bytes, then the counters of every
+    /// caller as `uid.UID.name` and `pid.PID.name`.
+    pub fn counters(&self) -> Vec<(String, u64)> {
+        let mut counters: Vec<(String, u64)> = [
+            ("open_handles", &self.open_handles),
+            ("inodes", &self.inodes),
+            ("reads", &self.reads),
+            ("bytes_read", &self.bytes_read),
+            ("writes", &self.writes),
+            ("bytes_written", &self.bytes_written),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name.to_string(), counter.load(Ordering::Relaxed)))
+        .collect();
+        for (bucket, count) in self.write_sizes.iter().enumerate() {
+            let count = count.load(Ordering::Relaxed);
+            if count > 0 {
+                counters.push((format!("write_size.{}", 1u64 << bucket), count));
+            }
+        }
+        for (kind, callers) in [("uid", &self.by_uid), ("pid", &self.by_pid)] {
+            for (id, traffic) in callers.read().unwrap().iter() {
+                let traffic = [
+                    ("reads", &traffic.reads),
+                    ("bytes_read", &traffic.bytes_read),
+                    ("writes", &traffic.writes),
+                    ("bytes_written", &traffic.bytes_written),
+                ];
+                for (name, value) in traffic {
+                    let value = value.load(Ordering::Relaxed);
+                    counters.push((format!("{}.{}.{}", kind, id, name), value));
+                }
+            }
+        }
+        counters
+    }
+
+    /// Adds the counters saved in `path` to these, so totals carry over from earlier
+    /// mounts. A missing file is a fresh start.
+    pub fn load(&self, path: &Path) -> io::Result<()> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed stats file");
+        let body = json
+            .trim()
+            .strip_prefix('{')
+            .and_then(|body| body.strip_suffix('}'))
+            .ok_or_else(invalid)?;
+        for field in body.split(',').filter(|field| !field.trim().is_empty()) {
+            let (name, value) = field.split_once(':').ok_or_else(invalid)?;
+            let name = name.trim().trim_matches('"');
+            let value: u64 = value.trim().parse().map_err(|_| invalid())?;
+            if let Some(counter) = self.persistent().find(|(n, _)| n == name).map(|(_, c)| c) {
+                counter.fetch_add(value, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the traffic counters to `path` as a JSON object, replacing the file at once.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut json = String::from("{");
+        for (i, (name, counter)) in self.persistent().enumerate() {
+            let separator = if i > 0 { "," } else { "" };
+            let value = counter.load(Ordering::Relaxed);
+            let _ = write!(json, "{}\n  \"{}\": {}", separator, name, value);
+        }
+        json.push_str("\n}\n");
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, json)?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Counters that add up across mounts, by name.
+    fn persistent(&self) -> impl Iterator<Item = (String, &AtomicU64)> {
+        [
+            ("reads", &self.reads),
+            ("bytes_read", &self.bytes_read),
+            ("writes", &self.writes),
+            ("bytes_written", &self.bytes_written),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name.to_string(), counter))
+        .chain(
+            self.write_sizes
+                .iter()
+                .enumerate()
+                .map(|(bucket, counter)| (format!("write_size.{}", 1u64 << bucket), counter)),
+        )
+    }
+
+    fn by_caller(&self, uid: u32, pid: u32, count: impl Fn(&Traffic)) {
+        count_for(&self.by_uid, uid, &count, |_| {});
+        count_for(&self.by_pid, pid, &count, make_room);
+    }
+
+    /// Zeroes the traffic counters; open handles are left alone as they are still open.
+    pub fn reset(&self) {
+        for counter in [
+            &self.reads,
+            &self.bytes_read,
+            &self.writes,
+            &self.bytes_written,
+        ]
+        .into_iter()
+        .chain(&self.write_sizes)
+        {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.by_uid.write().unwrap().clear();
+        self.by_pid.write().unwrap().clear();
+    }
+}
+
+/// Counts traffic of the caller `id`, only locking `callers` for writing the first time
+/// it shows up, after `make_room` had a chance to forget others.
+fn count_for(
+    callers: &RwLock<BTreeMap<u32, Traffic>>,
+    id: u32,
+    count: impl Fn(&Traffic),
+    make_room: impl FnOnce(&mut BTreeMap<u32, Traffic>),
+) {
+    if let Some(traffic) = callers.read().unwrap().get(&id) {
+        return count(traffic);
+    }
+    let mut callers = callers.write().unwrap();
+    if !callers.contains_key(&id) {
+        make_room(&mut callers);
+    }
+    count(callers.entry(id).or_default());
+}
+
+/// Keeps `by_pid` under `MAX_PIDS`, forgetting the pids that exited first, then the one
+/// that moved the fewest bytes.
+fn make_room(by_pid: &mut BTreeMap<u32, Traffic>) {
+    if by_pid.len() < MAX_PIDS {
+        return;
+    }
+    by_pid.retain(|pid, _| Path::new(&format!("/proc/{}", pid)).exists());
+    if by_pid.len() < MAX_PIDS {
+        return;
+    }
+    let quietest = by_pid.iter().min_by_key(|(_, traffic)| traffic.bytes());
+    if let Some(pid) = quietest.map(|(pid, _)| *pid) {
+        by_pid.remove(&pid);
+    }
+}
+
+impl fmt::Display for Stats {
+    /// Writes one `name value` line per counter.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in self.counters() {
+            writeln!(f, "{} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Traffic counters at a point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    at: Instant,
+    reads: u64,
+    bytes_read: u64,
+    writes: u64,
+    bytes_written: u64,
+}
+
+impl Sample {
+    /// Time since the sample was taken.
+    pub fn age(&self) -> Duration {
+        self.at.elapsed()
+    }
+
+    /// Returns the throughput between `earlier` and this sample.
+    pub fn since(&self, earlier: &Sample) -> Throughput {
+        let secs = self.at.duration_since(earlier.at).as_secs_f64();
+        let rate = |now: u64, then: u64| match secs > 0.0 {
+            true => now.saturating_sub(then) as f64 / secs,
+            false => 0.0,
+        };
+        Throughput {
+            read_bytes: rate(self.bytes_read, earlier.bytes_read),
+            read_ops: rate(self.reads, earlier.reads),
+            write_bytes: rate(self.bytes_written, earlier.bytes_written),
+            write_ops: rate(self.writes, earlier.writes),
+        }
+    }
+}
+
+/// Bytes and operations per second.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Throughput {
+    pub read_bytes: f64,
+    pub read_ops: f64,
+    pub write_bytes: f64,
+    pub write_ops: f64,
+}
+
+impl Throughput {
+    /// Keeps the higher of each rate.
+    pub fn max(self, other: Throughput) -> Throughput {
+        Throughput {
+            read_bytes: self.read_bytes.max(other.read_bytes),
+            read_ops: self.read_ops.max(other.read_ops),
+            write_bytes: self.write_bytes.max(other.write_bytes),
+            write_ops: self.write_ops.max(other.write_ops),
+        }
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "write {:.1} MB/s {:.0} IOPS, read {:.1} MB/s {:.0} IOPS",
+            self.write_bytes / 1e6,
+            self.write_ops,
+            self.read_bytes / 1e6,
+            self.read_ops
+        )
+    }
+}
+
+/// Traffic through a single file, exposed as its `user.nullfs.*` extended attributes.
+#[derive(Debug)]
+pub struct FileStats {
+    pub writes: AtomicU64,
+    pub bytes_written: AtomicU64,
+    /// 64-bit FNV-1a hash of the bytes written, in the order the writes were served, if
+    /// it is being computed.
+    digest: Option<Mutex<u64>>,
+}
+
+impl FileStats {
+    /// Starts the counters of a file, hashing what is written to it if `digest` is set.
+    pub fn new(digest: bool) -> FileStats {
+        FileStats {
+            writes: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            digest: digest.then(|| Mutex::new(FNV_OFFSET_BASIS)),
+        }
+    }
+
+    /// Counts a write of `data`.
+    pub fn record_write(&self, data: &[u8]) {
+        if let Some(digest) = &self.digest {
+            let mut digest = digest.lock().unwrap();
+            for byte in data {
+                *digest = (*digest ^ *byte as u64).wrapping_mul(FNV_PRIME);
+            }
+        }
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn digest(&self) -> Option<u64> {
+        self.digest.as_ref().map(|digest| *digest.lock().unwrap())
+    }
+}
+
+impl Default for FileStats {
+    fn default() -> FileStats {
+        FileStats::new(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callers_are_counted_apart() {
+        let stats = Stats::default();
+        stats.record_write(1000, 10, 4096);
+        stats.record_read(1000, 11, 512);
+        stats.record_read(0, 10, 1);
+        let counters = stats.counters();
+        let get = |name: &str| counters.iter().find(|(n, _)| n == name).map(|(_, v)| *v);
+        assert_eq!(get("bytes_written"), Some(4096));
+        assert_eq!(get("write_size.4096"), Some(1));
+        assert_eq!(get("uid.1000.bytes_read"), Some(512));
+        assert_eq!(get("uid.0.reads"), Some(1));
+        assert_eq!(get("pid.10.bytes_written"), Some(4096));
+        assert_eq!(get("pid.10.bytes_read"), Some(1));
+        assert_eq!(get("pid.11.reads"), Some(1));
+    }
+
+    #[test]
+    fn totals_carry_over() {
+        let path = std::env::temp_dir().join(format!("nullfs-stats-{}.json", std::process::id()));
+        let stats = Stats::default();
+        stats.record_write(0, 1, 100);
+        stats.record_read(0, 1, 7);
+        stats.save(&path).unwrap();
+
+        let loaded = Stats::default();
+        loaded.record_write(0, 1, 1);
+        loaded.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.writes.load(Ordering::Relaxed), 2);
+        assert_eq!(loaded.bytes_written.load(Ordering::Relaxed), 101);
+        assert_eq!(loaded.bytes_read.load(Ordering::Relaxed), 7);
+        assert_eq!(loaded.write_sizes[7].load(Ordering::Relaxed), 1);
+        assert_eq!(loaded.write_sizes[0].load(Ordering::Relaxed), 1);
+
+        // A missing file is a fresh start, a garbled one an error.
+        assert!(loaded.load(&path).is_ok());
+        fs::write(&path, "{\"reads\": many}").unwrap();
+        assert!(loaded.load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pids_are_bounded() {
+        let stats = Stats::default();
+        // Beyond the largest pid_max, so none of them is running.
+        let pids = (0..MAX_PIDS as u32 * 2).map(|i| (1 << 23) + i);
+        for pid in pids {
+            stats.record_write(0, pid, 1);
+        }
+        assert!(stats.by_pid.read().unwrap().len() <= MAX_PIDS);
+        let writes = &stats.by_uid.read().unwrap()[&0].writes;
+        assert_eq!(writes.load(Ordering::Relaxed), MAX_PIDS as u64 * 2);
+    }
+}
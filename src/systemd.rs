@@ -0,0 +1,97 @@
+use std::env;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Component, Path};
+
+/// Where journald receives records in its native protocol.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sends `state` to the service manager, if it asked for notifications through `$NOTIFY_SOCKET`.
+pub fn notify(state: &str) -> io::Result<()> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends a record made of `fields` to the journal over its native protocol.
+pub fn journal(fields: &[(&str, &str)]) -> io::Result<()> {
+    let mut message = Vec::new();
+    for (name, value) in fields {
+        message.extend_from_slice(name.as_bytes());
+        // Values spanning lines are sent with their length instead.
+        if value.contains('\n') {
+            message.push(b'\n');
+            message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            message.push(b'=');
+        }
+        message.extend_from_slice(value.as_bytes());
+        message.push(b'\n');
+    }
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(&message, JOURNAL_SOCKET)?;
+    Ok(())
+}
+
+/// Whether the process was started by a service manager waiting for `READY=1`.
+pub fn is_notify_service() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Escapes a path the way `systemd-escape --path` does, as needed for unit names.
+pub fn escape_path(path: &Path) -> String {
+    let components: Vec<&[u8]> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.as_bytes()),
+            _ => None,
+        })
+        .collect();
+    if components.is_empty() {
+        return "-".to_string();
+    }
+
+    let mut escaped = String::new();
+    for (i, component) in components.iter().enumerate() {
+        if i > 0 {
+            escaped.push('-');
+        }
+        for (j, &byte) in component.iter().enumerate() {
+            match byte {
+                b'.' if i == 0 && j == 0 => escaped.push_str("\\x2e"),
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' | b'.' => {
+                    escaped.push(byte as char)
+                }
+                _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+            }
+        }
+    }
+    escaped
+}
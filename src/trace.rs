@@ -0,0 +1,282 @@
+#[cfg(feature = "trace")]
+use std::ffi::OsStr;
+#[cfg(feature = "trace")]
+use std::fs::File;
+#[cfg(feature = "trace")]
+use std::io::{self, BufReader, BufWriter, Read, Write};
+#[cfg(feature = "trace")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(feature = "trace")]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "trace")]
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(feature = "trace")]
+use std::time::Instant;
+
+/// First bytes of a trace file, ending in the format version.
+#[cfg(feature = "trace")]
+const MAGIC: &[u8; 4] = b"NFR\x01";
+
+/// Requests kept in a trace, the ones that can be replayed through system calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Operation {
+    Open = 1,
+    Create,
+    Read,
+    Write,
+    Flush,
+    Fsync,
+    Release,
+    Mkdir,
+    Unlink,
+    Rmdir,
+    Rename,
+    Link,
+    Symlink,
+    Truncate,
+    Fallocate,
+}
+
+#[cfg(feature = "trace")]
+impl Operation {
+    const ALL: [Operation; 15] = [
+        Operation::Open,
+        Operation::Create,
+        Operation::Read,
+        Operation::Write,
+        Operation::Flush,
+        Operation::Fsync,
+        Operation::Release,
+        Operation::Mkdir,
+        Operation::Unlink,
+        Operation::Rmdir,
+        Operation::Rename,
+        Operation::Link,
+        Operation::Symlink,
+        Operation::Truncate,
+        Operation::Fallocate,
+    ];
+
+    fn from_u8(value: u8) -> Option<Operation> {
+        Operation::ALL.into_iter().find(|op| *op as u8 == value)
+    }
+}
+
+/// One recorded request.
+///
+/// Files are named by their path within the mount when opened or created, and by
+/// the handle they were given afterwards.
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// Time since the recording started.
+    pub time: Duration,
+    pub operation: Operation,
+    pub fh: u64,
+    pub offset: i64,
+    pub size: u64,
+    /// Open flags, or the mode of a created node.
+    pub mode: u32,
+    pub path: PathBuf,
+    /// Where a rename or a link goes, or where a symbolic link points.
+    pub target: PathBuf,
+    /// What a write carried, if payloads are recorded.
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    pub fn new(operation: Operation, path: PathBuf) -> Record {
+        Record {
+            time: Duration::ZERO,
+            operation,
+            fh: 0,
+            offset: 0,
+            size: 0,
+            mode: 0,
+            path,
+            target: PathBuf::new(),
+            data: Vec::new(),
+        }
+    }
+
+    /// A request on the open file `fh`.
+    pub fn on_handle(operation: Operation, fh: u64) -> Record {
+        Record {
+            fh,
+            ..Record::new(operation, PathBuf::new())
+        }
+    }
+
+    pub fn handle(self, fh: u64) -> Record {
+        Record { fh, ..self }
+    }
+
+    pub fn range(self, offset: i64, size: u64) -> Record {
+        Record {
+            offset,
+            size,
+            ..self
+        }
+    }
+
+    pub fn mode(self, mode: u32) -> Record {
+        Record { mode, ..self }
+    }
+
+    pub fn target(self, target: PathBuf) -> Record {
+        Record { target, ..self }
+    }
+
+    pub fn data(self, data: Vec<u8>) -> Record {
+        Record { data, ..self }
+    }
+
+    #[cfg(feature = "trace")]
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&(self.time.as_nanos() as u64).to_le_bytes());
+        body.push(self.operation as u8);
+        body.extend_from_slice(&self.fh.to_le_bytes());
+        body.extend_from_slice(&self.offset.to_le_bytes());
+        body.extend_from_slice(&self.size.to_le_bytes());
+        body.extend_from_slice(&self.mode.to_le_bytes());
+        for path in [&self.path, &self.target] {
+            let path = path.as_os_str().as_bytes();
+            body.extend_from_slice(&(path.len() as u16).to_le_bytes());
+            body.extend_from_slice(path);
+        }
+        body.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&self.data);
+
+        let mut record = (body.len() as u32).to_le_bytes().to_vec();
+        record.append(&mut body);
+        record
+    }
+
+    #[cfg(feature = "trace")]
+    fn decode(body: &[u8]) -> Option<Record> {
+        let mut body = body;
+        let mut take = |len: usize| {
+            let (field, rest) = body.split_at_checked(len)?;
+            body = rest;
+            Some(field)
+        };
+        let time = Duration::from_nanos(u64::from_le_bytes(take(8)?.try_into().ok()?));
+        let operation = Operation::from_u8(take(1)?[0])?;
+        let fh = u64::from_le_bytes(take(8)?.try_into().ok()?);
+        let offset = i64::from_le_bytes(take(8)?.try_into().ok()?);
+        let size = u64::from_le_bytes(take(8)?.try_into().ok()?);
+        let mode = u32::from_le_bytes(take(4)?.try_into().ok()?);
+        let mut path = || {
+            let len = u16::from_le_bytes(take(2)?.try_into().ok()?);
+            Some(PathBuf::from(OsStr::from_bytes(take(len as usize)?)))
+        };
+        let (path, target) = (path()?, path()?);
+        let len = u32::from_le_bytes(take(4)?.try_into().ok()?);
+        let data = take(len as usize)?.to_vec();
+        Some(Record {
+            time,
+            operation,
+            fh,
+            offset,
+            size,
+            mode,
+            path,
+            target,
+            data,
+        })
+    }
+}
+
+/// Reads back the records of a trace file, in the order they were recorded.
+#[cfg(feature = "trace")]
+pub struct Reader {
+    file: BufReader<File>,
+}
+
+#[cfg(feature = "trace")]
+impl Reader {
+    pub fn open(path: &Path) -> io::Result<Reader> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a nullfs trace"));
+        }
+        Ok(Reader { file })
+    }
+}
+
+#[cfg(feature = "trace")]
+impl Iterator for Reader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        let mut len = [0; 4];
+        match self.file.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        }
+        let mut body = vec![0; u32::from_le_bytes(len) as usize];
+        if let Err(err) = self.file.read_exact(&mut body) {
+            return Some(Err(err));
+        }
+        Some(Record::decode(&body).ok_or_else(|| invalid("malformed trace record")))
+    }
+}
+
+#[cfg(feature = "trace")]
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes the requests a mount receives to a trace file.
+#[cfg(feature = "trace")]
+pub struct Recorder {
+    file: Mutex<BufWriter<File>>,
+    start: Instant,
+    payload: bool,
+}
+
+#[cfg(feature = "trace")]
+impl Recorder {
+    /// Starts a trace in `path`, replacing whatever it held, keeping write payloads if
+    /// `payload` is set.
+    pub fn create(path: &Path, payload: bool) -> io::Result<Recorder> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        Ok(Recorder {
+            file: Mutex::new(file),
+            start: Instant::now(),
+            payload,
+        })
+    }
+
+    /// Whether writes should come with what they carried.
+    pub fn keeps_payload(&self) -> bool {
+        self.payload
+    }
+
+    /// Appends `record`, stamped with the time since the trace started.
+    pub fn record(&self, record: Record) {
+        let record = Record {
+            time: self.start.elapsed(),
+            ..record
+        };
+        if let Err(err) = self.file.lock().unwrap().write_all(&record.encode()) {
+            log::error!("failed to record the trace: {}", err);
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.file.get_mut().unwrap().flush() {
+            log::error!("failed to record the trace: {}", err);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use crate::systemd;
+
+/// A systemd unit file.
+pub struct Unit {
+    pub name: String,
+    pub contents: String,
+}
+
+/// A service running `exe` in the foreground with `args` to mount `mountpoint`.
+pub fn service(exe: &Path, mountpoint: &Path, args: &[String]) -> Unit {
+    let mut command = vec![
+        exe.to_string_lossy().into_owned(),
+        "--foreground".to_string(),
+    ];
+    command.extend(args.iter().cloned());
+    command.push(mountpoint.to_string_lossy().into_owned());
+    let command: Vec<String> = command.iter().map(|arg| quote(arg)).collect();
+
+    Unit {
+        name: format!("nullfs-{}.service", systemd::escape_path(mountpoint)),
+        contents: format!(
+            "[Unit]\n\
+             Description=nullfs on {mountpoint}\n\
+             RequiresMountsFor={requires}\n\
+             \n\
+             [Service]\n\
+             Type=notify\n\
+             ExecStart={command}\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n",
+            mountpoint = mountpoint.display(),
+            requires = quote(&mountpoint.to_string_lossy()),
+            command = command.join(" "),
+        ),
+    }
+}
+
+/// A mount unit mounting `mountpoint` through the `mount.nullfs` helper.
+pub fn mount(mountpoint: &Path, options: &[String]) -> Unit {
+    let mut contents = format!(
+        "[Unit]\n\
+         Description=nullfs on {mountpoint}\n\
+         \n\
+         [Mount]\n\
+         What=nullfs\n\
+         Where={mountpoint}\n\
+         Type=nullfs\n",
+        mountpoint = mountpoint.display(),
+    );
+    if !options.is_empty() {
+        contents.push_str(&format!("Options={}\n", options.join(",")));
+    }
+    contents.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+
+    Unit {
+        name: format!("{}.mount", systemd::escape_path(mountpoint)),
+        contents,
+    }
+}
+
+/// Quotes a word for a systemd command line.
+fn quote(word: &str) -> String {
+    let word = word.replace('%', "%%").replace('$', "$$");
+    if !word.is_empty()
+        && !word
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\' || c == ';')
+    {
+        return word;
+    }
+
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,144 @@
+use std::io;
+use std::path::Path;
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+use bsd as platform;
+#[cfg(target_os = "linux")]
+use linux as platform;
+
+/// Unmounts `mountpoint`. A lazy unmount detaches the mount even while it is busy,
+/// or forces it where the platform cannot detach it.
+pub fn unmount(mountpoint: &Path, lazy: bool) -> io::Result<()> {
+    platform::unmount(mountpoint, lazy)
+}
+
+/// Whether something is mounted on `mountpoint`, according to the mount table.
+pub fn is_mounted(mountpoint: &Path) -> io::Result<bool> {
+    platform::is_mounted(mountpoint)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::ffi::CString;
+    use std::fs;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Goes through fusermount when not privileged to unmount directly.
+    pub fn unmount(mountpoint: &Path, lazy: bool) -> io::Result<()> {
+        let path = CString::new(mountpoint.as_os_str().as_bytes())?;
+        let flags = if lazy { libc::MNT_DETACH } else { 0 };
+        if unsafe { libc::umount2(path.as_ptr(), flags) } == 0 {
+            return Ok(());
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::PermissionDenied {
+            return Err(err);
+        }
+
+        let mut last = err;
+        for fusermount in ["fusermount3", "fusermount"] {
+            let mut command = Command::new(fusermount);
+            command.arg("-u").arg("-q");
+            if lazy {
+                command.arg("-z");
+            }
+            match command.arg("--").arg(mountpoint).status() {
+                Ok(status) if status.success() => return Ok(()),
+                Ok(status) => {
+                    return Err(io::Error::other(format!(
+                        "{} failed with {}",
+                        fusermount, status
+                    )))
+                }
+                Err(err) => last = err,
+            }
+        }
+        Err(last)
+    }
+
+    pub fn is_mounted(mountpoint: &Path) -> io::Result<bool> {
+        let table = fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(table
+            .lines()
+            .filter_map(|line| line.split(' ').nth(4))
+            .any(|field| Path::new(&unescape(field)) == mountpoint))
+    }
+
+    /// Undoes the octal escapes of whitespace and backslashes in the mount table.
+    fn unescape(field: &str) -> String {
+        let mut unescaped = String::new();
+        let mut rest = field;
+        while let Some(i) = rest.find('\\') {
+            unescaped.push_str(&rest[..i]);
+            match rest
+                .get(i + 1..i + 4)
+                .and_then(|o| u8::from_str_radix(o, 8).ok())
+            {
+                Some(byte) => {
+                    unescaped.push(byte as char);
+                    rest = &rest[i + 4..];
+                }
+                None => {
+                    unescaped.push('\\');
+                    rest = &rest[i + 1..];
+                }
+            }
+        }
+        unescaped.push_str(rest);
+        unescaped
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn unescapes_mount_points() {
+            assert_eq!(unescape("/mnt/plain"), "/mnt/plain");
+            assert_eq!(unescape("/mnt/two\\040words"), "/mnt/two words");
+            assert_eq!(
+                unescape("/mnt/tab\\011back\\134slash"),
+                "/mnt/tab\tback\\slash"
+            );
+            assert_eq!(unescape("/mnt/trailing\\"), "/mnt/trailing\\");
+            assert_eq!(unescape("/mnt/not\\9octal"), "/mnt/not\\9octal");
+        }
+    }
+}
+
+/// macOS and FreeBSD, which have no fusermount but let the user who mounted a
+/// filesystem unmount it, FreeBSD with `vfs.usermount` set.
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+mod bsd {
+    use std::ffi::{CStr, CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    pub fn unmount(mountpoint: &Path, lazy: bool) -> io::Result<()> {
+        let path = CString::new(mountpoint.as_os_str().as_bytes())?;
+        let flags = if lazy { libc::MNT_FORCE } else { 0 };
+        if unsafe { libc::unmount(path.as_ptr(), flags) } == 0 {
+            return Ok(());
+        }
+        Err(io::Error::last_os_error())
+    }
+
+    pub fn is_mounted(mountpoint: &Path) -> io::Result<bool> {
+        let mut mounts: *mut libc::statfs = std::ptr::null_mut();
+        // Asking the filesystems for fresh statistics would hang on one that went away.
+        let count = unsafe { libc::getmntinfo(&mut mounts, libc::MNT_NOWAIT) };
+        if count == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mounts = unsafe { std::slice::from_raw_parts(mounts, count as usize) };
+        Ok(mounts.iter().any(|mount| {
+            let name = unsafe { CStr::from_ptr(mount.f_mntonname.as_ptr()) };
+            Path::new(OsStr::from_bytes(name.to_bytes())) == mountpoint
+        }))
+    }
+}